@@ -0,0 +1,32 @@
+/* C interface to the smiletrack tracker (src/ffi.rs). Link against the crate's cdylib. */
+#ifndef SMILETRACK_H
+#define SMILETRACK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SmileTrackHandle SmileTrackHandle;
+
+/* Values per detection/track row: [x, y, w, h, score, class] in, [x, y, w, h, id, class] out */
+#define SMILETRACK_ROW_LEN 6
+
+/* Create a tracker from a JSON config string (NULL for defaults). Returns NULL on error. */
+SmileTrackHandle *smiletrack_new(const char *config_json, float frame_rate);
+
+/* Track one frame of n detections, writing up to out_cap track rows to out.
+   Returns the number of activated tracks (may exceed out_cap), or -1 on error. */
+int32_t smiletrack_update(SmileTrackHandle *handle, const float *dets, size_t n,
+                          int32_t frame_id, float *out, size_t out_cap);
+
+/* Destroy a tracker. NULL is ignored. */
+void smiletrack_free(SmileTrackHandle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SMILETRACK_H */
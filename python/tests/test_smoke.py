@@ -0,0 +1,25 @@
+# Run after `maturin develop --features python`: pytest python/tests
+import numpy as np
+import pytest
+
+smiletrack_rs = pytest.importorskip("smiletrack_rs")
+
+
+def test_update_returns_track_rows():
+    tracker = smiletrack_rs.SMILEtrack()
+    tracks = np.zeros((0, 6), dtype=np.float32)
+    for frame_id in range(1, 6):
+        x = 100.0 + 3.0 * frame_id
+        dets = np.array([[x, 100.0, 50.0, 100.0, 0.9, 0.0]], dtype=np.float32)
+        tracks = tracker.update(dets, frame_id)
+
+    assert tracks.shape == (1, 6)
+    assert tracks[0, 4] == 1.0  # track id
+    assert tracks[0, 5] == 0.0  # class
+    assert abs(tracks[0, 0] - 115.0) < 5.0
+
+
+def test_update_rejects_wrong_width():
+    tracker = smiletrack_rs.SMILEtrack()
+    with pytest.raises(ValueError):
+        tracker.update(np.zeros((1, 5), dtype=np.float32), 1)
@@ -0,0 +1,846 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use nalgebra::{Matrix3, SVector, Vector3};
+use opencv::core::Point2f;
+use crate::roi::{point_in_polygon, ReferencePoint};
+use crate::tracker::STrack;
+
+/// Center point of a tlwh box
+fn box_center(tlwh: &SVector<f32, 4>) -> Point2f {
+    Point2f::new(tlwh[0] + tlwh[2] / 2.0, tlwh[1] + tlwh[3] / 2.0)
+}
+
+/// Z component of (a - o) x (b - o)
+fn cross(o: Point2f, a: Point2f, b: Point2f) -> f32 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+/// Counts tracks whose center crosses a line segment, per class and direction.
+///
+/// Directions are relative to the segment `p1 -> p2`. For a vertical line drawn
+/// top-to-bottom, a *forward* crossing is left-to-right; for a horizontal line drawn
+/// left-to-right, forward is bottom-to-top. Swap the points to flip the convention.
+pub struct LineCounter {
+    p1: Point2f,
+    p2: Point2f,
+    /// Last center of each track ID that was strictly off the line
+    last_off_line: HashMap<u32, Point2f>,
+    /// (forward, backward) crossing counts keyed by class ID
+    counts: HashMap<i32, (u32, u32)>,
+}
+
+impl LineCounter {
+    /// Create a counter for the segment between two points.
+    pub fn new(p1: Point2f, p2: Point2f) -> Self {
+        LineCounter {
+            p1,
+            p2,
+            last_off_line: HashMap::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Check the latest motion of each track against the line. Tracks missing from
+    /// `tracks` are forgotten.
+    pub fn update(&mut self, tracks: &[STrack]) {
+        for track in tracks {
+            let trail = match track.motion_trail() {
+                Some(trail) => trail,
+                None => continue,
+            };
+            let track_id = track.track_id();
+            if !self.last_off_line.contains_key(&track_id) {
+                // Seed a new track with its newest earlier center that is off the line
+                let earlier = trail[..trail.len() - 1].iter().rev().map(box_center).find(|&p| self.side(p) != 0.0);
+                if let Some(prev) = earlier {
+                    self.last_off_line.insert(track_id, prev);
+                }
+            }
+            self.observe(track_id, track.class_id, box_center(&trail[trail.len() - 1]));
+        }
+        let present: HashSet<u32> = tracks.iter().map(|t| t.track_id()).collect();
+        self.last_off_line.retain(|id, _| present.contains(id));
+    }
+
+    /// Count a crossing when `center` is on the other side from the track's last center
+    /// off the line. A center exactly on the line is skipped, so a track stepping onto it
+    /// and then off the far side still counts once.
+    fn observe(&mut self, track_id: u32, class_id: i32, center: Point2f) {
+        if self.side(center) == 0.0 {
+            return;
+        }
+        if let Some(&prev) = self.last_off_line.get(&track_id) {
+            if let Some(forward) = self.crossing(prev, center) {
+                let entry = self.counts.entry(class_id).or_insert((0, 0));
+                if forward {
+                    entry.0 += 1;
+                } else {
+                    entry.1 += 1;
+                }
+            }
+        }
+        self.last_off_line.insert(track_id, center);
+    }
+
+    /// Signed side of `pt` relative to the line, 0 when on it
+    fn side(&self, pt: Point2f) -> f32 {
+        cross(self.p1, self.p2, pt)
+    }
+
+    /// (forward, backward) crossing counts keyed by class ID.
+    pub fn counts(&self) -> HashMap<i32, (u32, u32)> {
+        self.counts.clone()
+    }
+
+    /// Returns `Some(true)` for a forward crossing, `Some(false)` for a backward one.
+    fn crossing(&self, prev: Point2f, curr: Point2f) -> Option<bool> {
+        // Movement must go from one side of the line to the other...
+        let side_prev = self.side(prev);
+        let side_curr = self.side(curr);
+        if side_prev * side_curr >= 0.0 {
+            return None;
+        }
+        // ...and pass between the segment's endpoints
+        let end1 = cross(prev, curr, self.p1);
+        let end2 = cross(prev, curr, self.p2);
+        if end1 * end2 > 0.0 {
+            return None;
+        }
+        Some(side_prev > 0.0)
+    }
+}
+
+/// Active track count for each processed frame, optionally broken down by class.
+#[derive(Default)]
+pub struct TrackCountSeries {
+    /// (frame ID, count per class ID) in processing order
+    rows: Vec<(i32, BTreeMap<i32, u32>)>,
+}
+
+impl TrackCountSeries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record how many activated tracks are present in this frame.
+    pub fn record(&mut self, frame_id: i32, tracks: &[STrack]) {
+        let mut per_class = BTreeMap::new();
+        for track in tracks.iter().filter(|t| t.is_activated()) {
+            *per_class.entry(track.class_id).or_insert(0) += 1;
+        }
+        self.rows.push((frame_id, per_class));
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// `frame,active_tracks` rows; with `per_class`, one extra column per class seen in the run.
+    pub fn to_csv(&self, per_class: bool) -> String {
+        let classes: BTreeSet<i32> = if per_class {
+            self.rows.iter().flat_map(|(_, counts)| counts.keys().copied()).collect()
+        } else {
+            BTreeSet::new()
+        };
+
+        let mut header = vec!["frame".to_string(), "active_tracks".to_string()];
+        header.extend(classes.iter().map(|&c| crate::visualization::class_label(c)));
+        let mut csv = header.join(",") + "\n";
+
+        for (frame_id, counts) in &self.rows {
+            let mut row = vec![frame_id.to_string(), counts.values().sum::<u32>().to_string()];
+            row.extend(classes.iter().map(|c| counts.get(c).copied().unwrap_or(0).to_string()));
+            csv += &(row.join(",") + "\n");
+        }
+        csv
+    }
+
+    /// Write `to_csv` output to a file.
+    pub fn write_csv(&self, path: &str, per_class: bool) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_csv(per_class))?;
+        Ok(())
+    }
+}
+
+/// Subtitle cues listing the active tracks of each frame, for overlaying track info on
+/// the untouched source video in any player.
+pub struct TrackSubtitles {
+    fps: f64,
+    /// (frame ID, cue text) for frames with at least one active track
+    cues: Vec<(i32, String)>,
+}
+
+impl TrackSubtitles {
+    pub fn new(fps: f64) -> Self {
+        TrackSubtitles { fps: if fps > 0.0 { fps } else { 30.0 }, cues: Vec::new() }
+    }
+
+    /// Add a cue for this frame naming its activated tracks, e.g. "ID 3 person, ID 7 car".
+    pub fn record(&mut self, frame_id: i32, tracks: &[STrack]) {
+        let text = tracks.iter()
+            .filter(|t| t.is_activated())
+            .map(|t| format!("ID {} {}", t.track_id(), crate::visualization::class_label(t.class_id)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !text.is_empty() {
+            self.cues.push((frame_id, text));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cues.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cues.is_empty()
+    }
+
+    /// Start of `frame_id` as `HH:MM:SS` plus `separator` and milliseconds
+    fn timestamp(&self, frame_id: i32, separator: char) -> String {
+        let ms = (frame_id.max(0) as f64 * 1000.0 / self.fps).round() as u64;
+        format!(
+            "{:02}:{:02}:{:02}{}{:03}",
+            ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, separator, ms % 1000
+        )
+    }
+
+    fn cue_lines(&self, separator: char, numbered: bool) -> String {
+        let mut out = String::new();
+        for (i, (frame_id, text)) in self.cues.iter().enumerate() {
+            if numbered {
+                out += &format!("{}\n", i + 1);
+            }
+            out += &format!(
+                "{} --> {}\n{}\n\n",
+                self.timestamp(*frame_id, separator),
+                self.timestamp(frame_id + 1, separator),
+                text
+            );
+        }
+        out
+    }
+
+    /// SubRip (.srt) text, one cue per frame lasting one frame interval.
+    pub fn to_srt(&self) -> String {
+        self.cue_lines(',', true)
+    }
+
+    /// WebVTT (.vtt) text with the same cues as `to_srt`.
+    pub fn to_vtt(&self) -> String {
+        format!("WEBVTT\n\n{}", self.cue_lines('.', false))
+    }
+
+    /// Write WebVTT if `path` ends in `.vtt`, SubRip otherwise.
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        let text = if path.to_ascii_lowercase().ends_with(".vtt") { self.to_vtt() } else { self.to_srt() };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// One observation of a track
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrajectoryPoint {
+    pub frame_id: i32,
+    pub tlwh: [f32; 4],
+    pub score: f32,
+}
+
+/// A track's full trajectory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trajectory {
+    pub track_id: u32,
+    /// Class voted at the track's latest frame
+    pub class_id: i32,
+    pub class_name: String,
+    pub start_frame: i32,
+    pub end_frame: i32,
+    pub points: Vec<TrajectoryPoint>,
+}
+
+/// Collects per-frame track boxes into one trajectory per track ID, the track-centric
+/// counterpart of the frame-by-frame tracking log.
+#[derive(Debug, Default)]
+pub struct TrajectoryExporter {
+    points: HashMap<u32, Vec<TrajectoryPoint>>,
+    /// Latest class of each track
+    classes: HashMap<u32, i32>,
+}
+
+impl TrajectoryExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add this frame's activated tracks to their trajectories.
+    pub fn record(&mut self, frame_id: i32, tracks: &[STrack]) {
+        for track in tracks.iter().filter(|t| t.is_activated()) {
+            let tlwh = track.tlwh();
+            self.points.entry(track.track_id()).or_default().push(TrajectoryPoint {
+                frame_id,
+                tlwh: [tlwh[0], tlwh[1], tlwh[2], tlwh[3]],
+                score: track.score,
+            });
+            self.classes.insert(track.track_id(), track.class_id);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Trajectories ordered by track ID.
+    pub fn trajectories(&self) -> Vec<Trajectory> {
+        let mut ids: Vec<u32> = self.points.keys().copied().collect();
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(|id| {
+                let points = self.points[&id].clone();
+                let class_id = self.classes[&id];
+                Trajectory {
+                    track_id: id,
+                    class_id,
+                    class_name: crate::visualization::class_label(class_id),
+                    start_frame: points.first().map_or(0, |p| p.frame_id),
+                    end_frame: points.last().map_or(0, |p| p.frame_id),
+                    points,
+                }
+            })
+            .collect()
+    }
+
+    /// JSON object keyed by track ID.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        let by_id: BTreeMap<u32, Trajectory> = self.trajectories().into_iter()
+            .map(|t| (t.track_id, t))
+            .collect();
+        Ok(serde_json::to_string_pretty(&by_id)?)
+    }
+
+    /// Write `to_json` output to a file.
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+}
+
+/// Column header written by `CsvWriter`
+pub const CSV_HEADER: &str = "frame_id,track_id,class_id,class_name,x,y,w,h,score,vx,vy";
+
+/// Flat CSV of tracking results, one row per activated track per frame. Velocities are
+/// the box-center motion in pixels per frame.
+pub struct CsvWriter<W: Write> {
+    out: W,
+}
+
+impl CsvWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        CsvWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Wrap `out` and write the header row
+    pub fn new(mut out: W) -> anyhow::Result<Self> {
+        writeln!(out, "{}", CSV_HEADER)?;
+        Ok(CsvWriter { out })
+    }
+
+    /// Append this frame's activated tracks and flush, so the file is usable mid-run
+    pub fn write_frame(&mut self, frame_id: i32, tracks: &[STrack]) -> anyhow::Result<()> {
+        for track in tracks.iter().filter(|t| t.is_activated()) {
+            let tlwh = track.tlwh();
+            let (vx, vy) = track.velocity();
+            writeln!(
+                self.out,
+                "{},{},{},{},{:.2},{:.2},{:.2},{:.2},{:.4},{:.3},{:.3}",
+                frame_id,
+                track.track_id(),
+                track.class_id,
+                crate::visualization::class_label(track.class_id),
+                tlwh[0], tlwh[1], tlwh[2], tlwh[3],
+                track.score,
+                vx, vy,
+            )?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// How image positions map to the ground plane
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpeedCalibration {
+    /// Uniform scale, for roughly top-down views
+    Scale { pixels_per_meter: f32 },
+    /// Image-to-ground homography, ground coordinates in meters
+    Homography(Matrix3<f32>),
+}
+
+/// Converts a track's pixel velocity into real-world speed
+pub struct SpeedEstimator {
+    calibration: SpeedCalibration,
+}
+
+impl SpeedEstimator {
+    pub fn from_scale(pixels_per_meter: f32) -> Self {
+        SpeedEstimator { calibration: SpeedCalibration::Scale { pixels_per_meter } }
+    }
+
+    /// `image_to_ground` maps homogeneous pixel coordinates to ground-plane meters
+    pub fn from_homography(image_to_ground: Matrix3<f32>) -> Self {
+        SpeedEstimator { calibration: SpeedCalibration::Homography(image_to_ground) }
+    }
+
+    /// Ground speed in meters per second at `fps` frames per second. With a homography the
+    /// box's bottom-center (where the object touches the ground) is projected now and one
+    /// frame ahead on the motion model, and the two ground points are differenced.
+    pub fn speed_mps(&self, track: &STrack, fps: f32) -> f32 {
+        match &self.calibration {
+            SpeedCalibration::Scale { pixels_per_meter } => track.speed() * fps / pixels_per_meter,
+            SpeedCalibration::Homography(h) => {
+                let project = |tlwh: &SVector<f32, 4>| {
+                    let p = h * Vector3::new(tlwh[0] + tlwh[2] / 2.0, tlwh[1] + tlwh[3], 1.0);
+                    (p.x / p.z, p.y / p.z)
+                };
+                let (x0, y0) = project(&track.predict_ahead(0));
+                let (x1, y1) = project(&track.predict_ahead(1));
+                (x1 - x0).hypot(y1 - y0) * fps
+            }
+        }
+    }
+
+    /// Ground speed in kilometers per hour at `fps` frames per second
+    pub fn speed_kmh(&self, track: &STrack, fps: f32) -> f32 {
+        self.speed_mps(track, fps) * 3.6
+    }
+}
+
+/// A track entering or leaving a zone
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ZoneEvent {
+    Entered { track_id: u32, zone: String, frame_id: i32 },
+    Exited { track_id: u32, zone: String, frame_id: i32 },
+}
+
+/// Time one track spent in one zone
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZoneDwell {
+    pub track_id: u32,
+    pub zone: String,
+    /// Frames the track was seen inside the zone
+    pub frames: u32,
+    /// Separate entries into the zone
+    pub visits: u32,
+    pub first_frame: i32,
+    pub last_frame: i32,
+}
+
+/// Named polygon zones with per-track dwell times.
+///
+/// Each `update` tests every activated track's reference point (the box center by default)
+/// against every zone with `roi::point_in_polygon`, emits entry and exit events, and counts
+/// the frames each track spends in each zone. A track missing from a frame's list exits
+/// the zones it was in.
+pub struct ZoneManager {
+    zones: Vec<(String, Vec<Point2f>)>,
+    reference: ReferencePoint,
+    /// Zone indices each track was inside at the previous update
+    inside: HashMap<u32, BTreeSet<usize>>,
+    /// Dwell statistics keyed by (track ID, zone index)
+    dwell: BTreeMap<(u32, usize), ZoneDwell>,
+}
+
+impl Default for ZoneManager {
+    fn default() -> Self {
+        ZoneManager {
+            zones: Vec::new(),
+            reference: ReferencePoint::Center,
+            inside: HashMap::new(),
+            dwell: BTreeMap::new(),
+        }
+    }
+}
+
+impl ZoneManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load zones from a JSON object mapping names to polygons, `{"door": [[x, y], ...]}`.
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let zones: BTreeMap<String, Vec<[f32; 2]>> = serde_json::from_str(&data)?;
+        let mut manager = Self::new();
+        for (name, points) in zones {
+            if points.len() < 3 {
+                anyhow::bail!("zone {:?} in {} needs at least 3 points, got {}", name, path, points.len());
+            }
+            manager.add_zone(&name, points.iter().map(|p| Point2f::new(p[0], p[1])).collect());
+        }
+        Ok(manager)
+    }
+
+    /// Add a zone; a zone with an existing name replaces its polygon.
+    pub fn add_zone(&mut self, name: &str, polygon: Vec<Point2f>) {
+        match self.zones.iter_mut().find(|(zone, _)| zone == name) {
+            Some(zone) => zone.1 = polygon,
+            None => self.zones.push((name.to_string(), polygon)),
+        }
+    }
+
+    /// Choose which point of each box is tested against the zones.
+    pub fn with_reference(mut self, reference: ReferencePoint) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    /// Zone names in the order they were added
+    pub fn zone_names(&self) -> impl Iterator<Item = &str> {
+        self.zones.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Record this frame's activated tracks, returning entries and exits.
+    pub fn update(&mut self, frame_id: i32, tracks: &[STrack]) -> Vec<ZoneEvent> {
+        let mut events = Vec::new();
+        let mut now_inside: HashMap<u32, BTreeSet<usize>> = HashMap::new();
+
+        for track in tracks.iter().filter(|t| t.is_activated()) {
+            let track_id = track.track_id();
+            let point = self.reference.of(track.tlwh());
+            let zones: BTreeSet<usize> = self.zones.iter()
+                .enumerate()
+                .filter(|(_, (_, polygon))| point_in_polygon(point, polygon))
+                .map(|(i, _)| i)
+                .collect();
+            let before = self.inside.get(&track_id);
+
+            for &zone in &zones {
+                let entered = before.map_or(true, |b| !b.contains(&zone));
+                let stats = self.dwell.entry((track_id, zone)).or_insert_with(|| ZoneDwell {
+                    track_id,
+                    zone: self.zones[zone].0.clone(),
+                    frames: 0,
+                    visits: 0,
+                    first_frame: frame_id,
+                    last_frame: frame_id,
+                });
+                stats.frames += 1;
+                stats.last_frame = frame_id;
+                if entered {
+                    stats.visits += 1;
+                    events.push(ZoneEvent::Entered { track_id, zone: self.zones[zone].0.clone(), frame_id });
+                }
+            }
+            now_inside.insert(track_id, zones);
+        }
+
+        // Exits: zones a track was in last frame but not now, including vanished tracks
+        let mut previous: Vec<_> = self.inside.iter().collect();
+        previous.sort_by_key(|(id, _)| **id);
+        for (&track_id, zones) in previous {
+            let current = now_inside.get(&track_id);
+            for &zone in zones {
+                if current.map_or(true, |c| !c.contains(&zone)) {
+                    events.push(ZoneEvent::Exited { track_id, zone: self.zones[zone].0.clone(), frame_id });
+                }
+            }
+        }
+
+        now_inside.retain(|_, zones| !zones.is_empty());
+        self.inside = now_inside;
+        events
+    }
+
+    /// Frames `track_id` has spent inside `zone` (0 for unknown tracks or zones)
+    pub fn dwell_frames(&self, track_id: u32, zone: &str) -> u32 {
+        self.zones.iter()
+            .position(|(name, _)| name == zone)
+            .and_then(|i| self.dwell.get(&(track_id, i)))
+            .map_or(0, |stats| stats.frames)
+    }
+
+    /// Dwell statistics for every track and zone it visited, ordered by track ID then zone.
+    pub fn summary(&self) -> Vec<ZoneDwell> {
+        self.dwell.values().cloned().collect()
+    }
+
+    /// `summary` as CSV with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("track_id,zone,frames,visits,first_frame,last_frame\n");
+        for d in self.summary() {
+            csv.push_str(&format!("{},{},{},{},{},{}\n", d.track_id, d.zone, d.frames, d.visits, d.first_frame, d.last_frame));
+        }
+        csv
+    }
+
+    /// Write `to_csv` output to a file.
+    pub fn write_csv(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_csv())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::Detection;
+    use crate::tracker::KalmanFilter;
+
+    #[test]
+    fn test_line_counter_left_to_right() {
+        let mut counter = LineCounter::new(Point2f::new(200.0, 0.0), Point2f::new(200.0, 480.0));
+
+        let mut track = STrack::new(SVector::<f32, 4>::new(100.0, 200.0, 50.0, 100.0), 0.9, 0, None, 1);
+        track.activate(&KalmanFilter::new(), 1, 1);
+        counter.update(&[track.clone()]);
+
+        for frame_id in 2..=12 {
+            let x = 100.0 + (frame_id - 1) as f32 * 20.0;
+            let det = Detection::new(SVector::<f32, 4>::new(x, 200.0, 50.0, 100.0), 0.9, 0, None);
+            track.predict();
+            track.update(&det, frame_id, None);
+            counter.update(&[track.clone()]);
+        }
+
+        let counts = counter.counts();
+        assert_eq!(counts.get(&0), Some(&(1, 0)));
+    }
+
+    #[test]
+    fn test_line_counter_counts_center_landing_on_line() {
+        let mut counter = LineCounter::new(Point2f::new(200.0, 0.0), Point2f::new(200.0, 480.0));
+        // Snapped to pixels, the center sits exactly on x=200 for a frame each way
+        for x in [190.0, 200.0, 210.0, 200.0, 190.0] {
+            counter.observe(1, 0, Point2f::new(x, 50.0));
+        }
+        assert_eq!(counter.counts().get(&0), Some(&(1, 1)));
+
+        // Touching the line and returning is not a crossing
+        for x in [190.0, 200.0, 190.0] {
+            counter.observe(2, 2, Point2f::new(x, 50.0));
+        }
+        assert_eq!(counter.counts().get(&2), None);
+    }
+
+    #[test]
+    fn test_line_counter_forgets_missing_tracks() {
+        let mut counter = LineCounter::new(Point2f::new(200.0, 0.0), Point2f::new(200.0, 480.0));
+        let mut track = STrack::new(SVector::<f32, 4>::new(100.0, 200.0, 50.0, 100.0), 0.9, 0, None, 1);
+        track.activate(&KalmanFilter::new(), 1, 1);
+        counter.update(&[track]);
+        assert_eq!(counter.last_off_line.len(), 1);
+
+        counter.update(&[]);
+        assert!(counter.last_off_line.is_empty());
+    }
+
+    #[test]
+    fn test_line_counter_ignores_motion_beyond_segment() {
+        let counter = LineCounter::new(Point2f::new(200.0, 0.0), Point2f::new(200.0, 100.0));
+        // Crosses x=200 well below the end of the segment
+        assert_eq!(counter.crossing(Point2f::new(150.0, 300.0), Point2f::new(250.0, 300.0)), None);
+        assert_eq!(counter.crossing(Point2f::new(150.0, 50.0), Point2f::new(250.0, 50.0)), Some(true));
+        assert_eq!(counter.crossing(Point2f::new(250.0, 50.0), Point2f::new(150.0, 50.0)), Some(false));
+    }
+
+    #[test]
+    fn test_track_count_series_csv() {
+        let kf = KalmanFilter::new();
+        let make = |id: u32, class_id: i32| {
+            let mut track = STrack::new(SVector::<f32, 4>::new(10.0 * id as f32, 0.0, 5.0, 5.0), 0.9, class_id, None, 1);
+            track.activate(&kf, 1, id);
+            track.confirm(1);
+            track
+        };
+        let tentative = STrack::new(SVector::<f32, 4>::new(0.0, 0.0, 5.0, 5.0), 0.9, 0, None, 1);
+
+        let mut series = TrackCountSeries::new();
+        series.record(1, &[make(1, 0)]);
+        series.record(2, &[make(1, 0), make(2, 2), tentative]);
+        series.record(3, &[]);
+        assert_eq!(series.len(), 3);
+
+        assert_eq!(series.to_csv(false), "frame,active_tracks\n1,1\n2,2\n3,0\n");
+        assert_eq!(
+            series.to_csv(true),
+            "frame,active_tracks,person,car\n1,1,1,0\n2,2,1,1\n3,0,0,0\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_writer_round_trip() {
+        let kf = KalmanFilter::new();
+        let mut person = STrack::new(SVector::<f32, 4>::new(10.0, 20.0, 30.0, 60.0), 0.9, 0, None, 1);
+        person.activate(&kf, 1, 4);
+        person.confirm(1);
+        let mut car = STrack::new(SVector::<f32, 4>::new(200.0, 100.0, 80.0, 40.0), 0.75, 2, None, 1);
+        car.activate(&kf, 1, 9);
+        car.confirm(1);
+        let tentative = STrack::new(SVector::<f32, 4>::new(0.0, 0.0, 5.0, 5.0), 0.9, 0, None, 1);
+
+        let path = std::env::temp_dir().join("smiletrack_test_tracks.csv");
+        let mut writer = CsvWriter::create(&path).unwrap();
+        writer.write_frame(1, &[person.clone(), tentative]).unwrap();
+        writer.write_frame(2, &[person, car]).unwrap();
+        drop(writer);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines.len(), 4);
+        for line in &lines {
+            assert_eq!(line.split(',').count(), 11);
+        }
+
+        let row: Vec<&str> = lines[3].split(',').collect();
+        assert_eq!(&row[..4], &["2", "9", "2", "car"]);
+        let x: f32 = row[4].parse().unwrap();
+        let score: f32 = row[8].parse().unwrap();
+        let vx: f32 = row[9].parse().unwrap();
+        assert!((x - 200.0).abs() < 1e-3);
+        assert!((score - 0.75).abs() < 1e-4);
+        assert_eq!(vx, 0.0);
+        assert!(lines[1].starts_with("1,4,0,person,"));
+    }
+
+    #[test]
+    fn test_trajectories_per_track() {
+        let kf = KalmanFilter::new();
+        let make = |id: u32, x: f32, class_id: i32| {
+            let mut track = STrack::new(SVector::<f32, 4>::new(x, 0.0, 5.0, 5.0), 0.9, class_id, None, 1);
+            track.activate(&kf, 1, id);
+            track.confirm(1);
+            track
+        };
+
+        let mut exporter = TrajectoryExporter::new();
+        exporter.record(1, &[make(1, 0.0, 0)]);
+        exporter.record(2, &[make(1, 2.0, 0), make(2, 100.0, 2)]);
+        exporter.record(3, &[make(1, 4.0, 0), make(2, 102.0, 2)]);
+        assert_eq!(exporter.len(), 2);
+
+        let trajectories = exporter.trajectories();
+        assert_eq!(trajectories.len(), 2);
+        let (first, second) = (&trajectories[0], &trajectories[1]);
+        assert_eq!((first.track_id, first.points.len(), first.start_frame, first.end_frame), (1, 3, 1, 3));
+        assert_eq!((second.track_id, second.points.len(), second.start_frame, second.end_frame), (2, 2, 2, 3));
+        assert_eq!(second.class_name, "car");
+        assert_eq!(first.points[2].tlwh, [4.0, 0.0, 5.0, 5.0]);
+
+        let parsed: BTreeMap<String, Trajectory> = serde_json::from_str(&exporter.to_json().unwrap()).unwrap();
+        assert_eq!(parsed["2"], *second);
+    }
+
+    #[test]
+    fn test_subtitles_timestamps() {
+        let make = |id: u32, class_id: i32| {
+            let mut track = STrack::new(SVector::<f32, 4>::new(10.0 * id as f32, 0.0, 5.0, 5.0), 0.9, class_id, None, 1);
+            track.activate(&KalmanFilter::new(), 1, id);
+            track.confirm(1);
+            track
+        };
+        let mut subtitles = TrackSubtitles::new(25.0);
+        subtitles.record(0, &[make(1, 0)]);
+        subtitles.record(1, &[]);
+        subtitles.record(2, &[make(1, 0), make(4, 2)]);
+        subtitles.record(90_000, &[make(4, 2)]);
+        assert_eq!(subtitles.len(), 3);
+
+        assert_eq!(
+            subtitles.to_srt(),
+            "1\n00:00:00,000 --> 00:00:00,040\nID 1 person\n\n\
+             2\n00:00:00,080 --> 00:00:00,120\nID 1 person, ID 4 car\n\n\
+             3\n01:00:00,000 --> 01:00:00,040\nID 4 car\n\n"
+        );
+        assert!(subtitles.to_vtt().starts_with("WEBVTT\n\n00:00:00.000 --> 00:00:00.040\nID 1 person\n"));
+    }
+
+    #[test]
+    fn test_zone_dwell_through_two_zones() {
+        let square = |x0: f32| vec![
+            Point2f::new(x0, 0.0),
+            Point2f::new(x0 + 100.0, 0.0),
+            Point2f::new(x0 + 100.0, 100.0),
+            Point2f::new(x0, 100.0),
+        ];
+        let mut zones = ZoneManager::new();
+        zones.add_zone("a", square(0.0));
+        zones.add_zone("b", square(200.0));
+
+        let at = |id: u32, center_x: f32| {
+            let mut track = STrack::new(SVector::<f32, 4>::new(center_x - 5.0, 45.0, 10.0, 10.0), 0.9, 0, None, 1);
+            track.activate(&KalmanFilter::new(), 1, id);
+            track.confirm(1);
+            track
+        };
+
+        // Track 1 walks right 25 px per frame: centers 5..80 in a, 205..280 in b
+        let mut events = Vec::new();
+        for frame_id in 1..=13 {
+            let mut tracks = vec![at(1, 5.0 + 25.0 * (frame_id - 1) as f32)];
+            // Track 2 stands in b for two frames, then disappears
+            if frame_id <= 2 {
+                tracks.push(at(2, 250.0));
+            }
+            events.extend(zones.update(frame_id, &tracks));
+        }
+
+        assert_eq!(zones.dwell_frames(1, "a"), 4);
+        assert_eq!(zones.dwell_frames(1, "b"), 4);
+        assert_eq!(zones.dwell_frames(2, "b"), 2);
+        assert_eq!(zones.dwell_frames(2, "a"), 0);
+        assert_eq!(zones.dwell_frames(1, "c"), 0);
+
+        let entered = |id: u32, zone: &str, frame_id: i32| ZoneEvent::Entered { track_id: id, zone: zone.to_string(), frame_id };
+        let exited = |id: u32, zone: &str, frame_id: i32| ZoneEvent::Exited { track_id: id, zone: zone.to_string(), frame_id };
+        assert_eq!(events, vec![
+            entered(1, "a", 1),
+            entered(2, "b", 1),
+            exited(2, "b", 3),
+            exited(1, "a", 5),
+            entered(1, "b", 9),
+            exited(1, "b", 13),
+        ]);
+
+        let summary = zones.summary();
+        assert_eq!(summary.len(), 3);
+        assert_eq!((summary[1].zone.as_str(), summary[1].first_frame, summary[1].last_frame, summary[1].visits), ("b", 9, 12, 1));
+        assert!(zones.to_csv().contains("\n2,b,2,1,1,2\n"));
+    }
+
+    #[test]
+    fn test_speed_kmh_from_known_motion() {
+        // 10 px per frame at 20 px/m and 30 fps: 15 m/s = 54 km/h
+        let mut track = STrack::new(SVector::<f32, 4>::new(100.0, 200.0, 40.0, 80.0), 0.9, 0, None, 1);
+        track.activate(&KalmanFilter::new(), 1, 1);
+        for frame_id in 2..=40 {
+            let x = 100.0 + (frame_id - 1) as f32 * 10.0;
+            track.predict();
+            track.update(&Detection::new(SVector::<f32, 4>::new(x, 200.0, 40.0, 80.0), 0.9, 0, None), frame_id, None);
+        }
+
+        let by_scale = SpeedEstimator::from_scale(20.0).speed_kmh(&track, 30.0);
+        assert!((by_scale - 54.0).abs() < 1.0, "{}", by_scale);
+
+        let scale = Matrix3::new(1.0 / 20.0, 0.0, 0.0, 0.0, 1.0 / 20.0, 0.0, 0.0, 0.0, 1.0);
+        let by_homography = SpeedEstimator::from_homography(scale).speed_kmh(&track, 30.0);
+        assert!((by_homography - 54.0).abs() < 1.0, "{}", by_homography);
+    }
+}
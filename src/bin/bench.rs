@@ -0,0 +1,253 @@
+use clap::Parser;
+use opencv::{
+    core::{self, Mat, Scalar, Size, CV_32F, CV_8UC3},
+    imgcodecs, imgproc,
+    prelude::*,
+};
+use smiletrack::detection::Detection;
+use smiletrack::utils::{elapsed_ms, Timings};
+use smiletrack::{Config, Detector, SMILEtrack};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use log::{info, warn};
+use nalgebra::SVector;
+
+#[derive(Parser)]
+#[command(
+    name = "bench",
+    about = "Measure detection and tracking throughput (median / p95 latency per stage)"
+)]
+struct Args {
+    /// Tracker and detector config; defaults are used when absent
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// TorchScript weights, overriding the config; without a readable model a mock
+    /// detector is benchmarked instead, so the harness also runs in CI
+    #[arg(short, long)]
+    weights: Option<String>,
+
+    /// Image to run the detector on; a synthetic 1280x720 frame is used otherwise
+    #[arg(long)]
+    image: Option<PathBuf>,
+
+    /// Measured iterations (frames)
+    #[arg(short = 'k', long, default_value_t = 100)]
+    iterations: usize,
+
+    /// Untimed iterations run first to warm caches and lazy initialization
+    #[arg(long, default_value_t = 10)]
+    warmup: usize,
+
+    /// Objects in the scripted detection stream fed to the tracker
+    #[arg(long, default_value_t = 20)]
+    objects: usize,
+}
+
+/// Size of the synthetic benchmark frame
+const SYNTHETIC_SIZE: (i32, i32) = (1280, 720);
+
+/// Input size the mock detector resizes to, like the real model
+const MOCK_INPUT_SIZE: i32 = 640;
+
+/// The real detector, or a stand-in that does comparable preprocessing and returns the
+/// scripted detections
+enum BenchDetector {
+    Model(Box<Detector>),
+    Mock,
+}
+
+impl BenchDetector {
+    fn detect(&self, frame: &Mat, scripted: &[Detection]) -> anyhow::Result<(Vec<Detection>, Timings)> {
+        match self {
+            BenchDetector::Model(detector) => detector.detect_timed(frame),
+            BenchDetector::Mock => {
+                let mut timings = Timings::default();
+                let start = Instant::now();
+                let mut resized = Mat::default();
+                imgproc::resize(frame, &mut resized, Size::new(MOCK_INPUT_SIZE, MOCK_INPUT_SIZE), 0.0, 0.0, imgproc::INTER_LINEAR)?;
+                let mut normalized = Mat::default();
+                resized.convert_to(&mut normalized, CV_32F, 1.0 / 255.0, 0.0)?;
+                timings.preprocess_ms = elapsed_ms(start);
+
+                let start = Instant::now();
+                let detections = scripted.to_vec();
+                timings.postprocess_ms = elapsed_ms(start);
+                Ok((detections, timings))
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            BenchDetector::Model(_) => "model",
+            BenchDetector::Mock => "mock",
+        }
+    }
+}
+
+/// Detections for `objects` boxes moving on straight lines, wrapping at the frame edges
+fn scripted_detections(frame_id: usize, objects: usize, width: i32, height: i32) -> Vec<Detection> {
+    let (width, height) = (width as f32, height as f32);
+    (0..objects)
+        .map(|i| {
+            let (w, h) = (40.0 + (i % 5) as f32 * 10.0, 80.0 + (i % 3) as f32 * 20.0);
+            let (vx, vy) = (1.0 + (i % 4) as f32, ((i % 3) as f32 - 1.0) * 0.5);
+            let x0 = (i as f32 * 97.0) % (width - w);
+            let y0 = (i as f32 * 53.0) % (height - h);
+            let x = (x0 + vx * frame_id as f32).rem_euclid(width - w);
+            let y = (y0 + vy * frame_id as f32).rem_euclid(height - h);
+            Detection::new(SVector::<f32, 4>::new(x, y, w, h), 0.9, 0, None)
+        })
+        .collect()
+}
+
+/// Latency distribution of one stage
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LatencyStats {
+    median_ms: f64,
+    p95_ms: f64,
+}
+
+impl LatencyStats {
+    fn from_samples(samples: &[f64]) -> Self {
+        if samples.is_empty() {
+            return LatencyStats { median_ms: 0.0, p95_ms: 0.0 };
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+        LatencyStats { median_ms: percentile(0.5), p95_ms: percentile(0.95) }
+    }
+
+    /// Throughput at the median latency (0 for stages that took no measurable time)
+    fn fps(&self) -> f64 {
+        if self.median_ms > 0.0 { 1000.0 / self.median_ms } else { 0.0 }
+    }
+}
+
+struct BenchReport {
+    detector: &'static str,
+    iterations: usize,
+    stages: Vec<(&'static str, LatencyStats)>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} detector, {} iterations", self.detector, self.iterations)?;
+        writeln!(f, "{:<12} {:>10} {:>10} {:>10}", "stage", "median ms", "p95 ms", "fps")?;
+        for (name, stats) in &self.stages {
+            writeln!(f, "{:<12} {:>10.3} {:>10.3} {:>10.1}", name, stats.median_ms, stats.p95_ms, stats.fps())?;
+        }
+        Ok(())
+    }
+}
+
+/// Run detection on `frame` and tracking on the scripted stream for `warmup + iterations`
+/// frames, timing only the last `iterations`
+fn run_bench(
+    detector: &BenchDetector,
+    config: &Config,
+    frame: &Mat,
+    iterations: usize,
+    warmup: usize,
+    objects: usize,
+) -> anyhow::Result<BenchReport> {
+    let mut tracker = SMILEtrack::new(config, 30.0);
+    let mut samples: [Vec<f64>; 5] = Default::default();
+
+    for i in 0..warmup + iterations {
+        let scripted = scripted_detections(i, objects, frame.cols(), frame.rows());
+        let start = Instant::now();
+        let (_, detect_timings) = detector.detect(frame, &scripted)?;
+        // The tracker always sees the scripted stream, so its load doesn't depend on the model
+        tracker.update(&scripted, frame, i as i32 + 1)?;
+        let end_to_end = elapsed_ms(start);
+
+        if i >= warmup {
+            let track_timings = tracker.last_timings();
+            samples[0].push(detect_timings.preprocess_ms);
+            samples[1].push(detect_timings.inference_ms);
+            samples[2].push(detect_timings.postprocess_ms);
+            samples[3].push(track_timings.gmc_ms + track_timings.association_ms);
+            samples[4].push(end_to_end);
+        }
+    }
+
+    let names = ["preprocess", "inference", "postprocess", "association", "end-to-end"];
+    Ok(BenchReport {
+        detector: detector.name(),
+        iterations,
+        stages: names.iter().zip(&samples).map(|(&name, s)| (name, LatencyStats::from_samples(s))).collect(),
+    })
+}
+
+/// Textured synthetic frame, so motion compensation has features to work with
+fn synthetic_frame() -> opencv::Result<Mat> {
+    let mut frame = Mat::new_rows_cols_with_default(SYNTHETIC_SIZE.1, SYNTHETIC_SIZE.0, CV_8UC3, Scalar::all(0.0))?;
+    core::randu(&mut frame, &Scalar::all(0.0), &Scalar::all(255.0))?;
+    Ok(frame)
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Args::parse();
+
+    let mut config = match &args.config {
+        Some(path) => Config::from_file(&path.to_string_lossy())?,
+        None => Config::default(),
+    };
+    if let Some(weights) = &args.weights {
+        config.model_path = weights.clone();
+    }
+
+    let detector = if !config.model_path.is_empty() && Path::new(&config.model_path).exists() {
+        info!("Benchmarking model {}", config.model_path);
+        BenchDetector::Model(Box::new(Detector::from_config(&config)?))
+    } else {
+        warn!("No model at {:?}; benchmarking the mock detector", config.model_path);
+        BenchDetector::Mock
+    };
+
+    let frame = match &args.image {
+        Some(path) => {
+            let frame = imgcodecs::imread(&path.to_string_lossy(), imgcodecs::IMREAD_COLOR)?;
+            anyhow::ensure!(!frame.empty(), "could not read image {:?}", path);
+            frame
+        }
+        None => synthetic_frame()?,
+    };
+
+    let report = run_bench(&detector, &config, &frame, args.iterations.max(1), args.warmup, args.objects)?;
+    print!("{}", report);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_stats_percentiles() {
+        let samples: Vec<f64> = (1..=100).map(f64::from).collect();
+        let stats = LatencyStats::from_samples(&samples);
+        assert_eq!(stats.median_ms, 51.0);
+        assert_eq!(stats.p95_ms, 95.0);
+        assert_eq!(LatencyStats::from_samples(&[]).fps(), 0.0);
+    }
+
+    #[test]
+    fn test_mock_bench_completes() {
+        let frame = synthetic_frame().unwrap();
+        let report = run_bench(&BenchDetector::Mock, &Config::default(), &frame, 5, 2, 8).unwrap();
+        assert_eq!(report.stages.len(), 5);
+        for (name, stats) in &report.stages {
+            assert!(stats.median_ms.is_finite() && stats.p95_ms.is_finite() && stats.fps().is_finite(), "{}", name);
+            assert!(stats.p95_ms >= stats.median_ms, "{}", name);
+        }
+        let (_, end_to_end) = report.stages[4];
+        assert!(end_to_end.median_ms > 0.0);
+        assert!(report.to_string().contains("end-to-end"));
+    }
+}
@@ -6,6 +6,9 @@ use opencv::{imgcodecs, prelude::*};
 use smiletrack::{Config, detection::Detector};
 
 fn main() -> Result<()> {
+    // RUST_LOG=debug (or trace) shows the detector's internals
+    env_logger::init();
+
     // Load config from file
     let mut config = Config::from_file("config.json")?;
     // Override some settings for testing
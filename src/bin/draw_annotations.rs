@@ -26,7 +26,7 @@ struct Annotation {
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to the annotations JSON file
+    /// Path to the annotations JSON file, or a YOLO `.txt` label file for the input image
     #[arg(short, long)]
     annotations: PathBuf,
 
@@ -55,16 +55,52 @@ struct Args {
     only_persons: bool,
 }
 
+/// Parse a YOLO label file: one `class x_center y_center width height` row per object,
+/// normalized to the image size. An optional sixth column is read as the confidence,
+/// which otherwise defaults to 1.0 for ground truth.
+fn parse_yolo_labels(content: &str) -> Result<Vec<BoundingBox>, String> {
+    let mut boxes = Vec::new();
+    for (line_no, line) in content.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() != 5 && fields.len() != 6 {
+            return Err(format!("line {}: expected 5 or 6 columns, got {}", line_no + 1, fields.len()));
+        }
+        let number = |i: usize| {
+            fields[i].parse::<f32>().map_err(|e| format!("line {}: {:?}: {}", line_no + 1, fields[i], e))
+        };
+        let class_id = fields[0].parse::<i32>().map_err(|e| format!("line {}: {:?}: {}", line_no + 1, fields[0], e))?;
+        boxes.push(BoundingBox {
+            class_id,
+            confidence: if fields.len() == 6 { number(5)? } else { 1.0 },
+            x_center: number(1)?,
+            y_center: number(2)?,
+            width: number(3)?,
+            height: number(4)?,
+        });
+    }
+    Ok(boxes)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    let input_path = args.input.to_string_lossy().to_string();
 
     // Read annotations file
     println!("Reading annotations from {:?}", args.annotations);
-    let json_content = fs::read_to_string(args.annotations)?;
-    let annotations: Vec<Annotation> = serde_json::from_str(&json_content)?;
+    let is_yolo = args.annotations.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("txt"));
+    let annotations: Vec<Annotation> = if is_yolo {
+        // A YOLO label file belongs to a single image, the input
+        let boxes = parse_yolo_labels(&fs::read_to_string(&args.annotations)?)
+            .map_err(|e| format!("{:?}: {}", args.annotations, e))?;
+        vec![Annotation { frame: input_path.clone(), annotations: boxes }]
+    } else {
+        serde_json::from_str(&fs::read_to_string(&args.annotations)?)?
+    };
 
     // Find annotation for the input image
-    let input_path = args.input.to_string_lossy().to_string();
     println!("Looking for annotations for: {}", input_path);
     
     // Create a more flexible matching solution - look for annotations with the same filename
@@ -103,20 +139,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let img_width = img.cols() as f32;
     let img_height = img.rows() as f32;
     
-    // Define COCO class names (or relevant classes for your model)
-    let class_names = vec![
-        "person", "bicycle", "car", "motorcycle", "airplane", "bus", "train", "truck", "boat", 
-        "traffic light", "fire hydrant", "stop sign", "parking meter", "bench", "bird", "cat", 
-        "dog", "horse", "sheep", "cow", "elephant", "bear", "zebra", "giraffe", "backpack", 
-        "umbrella", "handbag", "tie", "suitcase", "frisbee", "skis", "snowboard", "sports ball", 
-        "kite", "baseball bat", "baseball glove", "skateboard", "surfboard", "tennis racket", 
-        "bottle", "wine glass", "cup", "fork", "knife", "spoon", "bowl", "banana", "apple", 
-        "sandwich", "orange", "broccoli", "carrot", "hot dog", "pizza", "donut", "cake", "chair", 
-        "couch", "potted plant", "bed", "dining table", "toilet", "tv", "laptop", "mouse", "remote", 
-        "keyboard", "cell phone", "microwave", "oven", "toaster", "sink", "refrigerator", "book", 
-        "clock", "vase", "scissors", "teddy bear", "hair drier", "toothbrush"
-    ];
-    
     // Define colors for different classes
     let colors = vec![
         Scalar::new(0.0, 255.0, 0.0, 0.0),    // Green
@@ -190,11 +212,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         
         // Add label if requested
         if args.show_labels {
-            let class_name = if bbox.class_id >= 0 && (bbox.class_id as usize) < class_names.len() {
-                class_names[bbox.class_id as usize]
-            } else {
-                "unknown"
-            };
+            let class_name = smiletrack::visualization::class_label(bbox.class_id);
             
             let text = format!("{} {:.2}", class_name, bbox.confidence);
             
@@ -255,4 +273,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Done! Saved image with {} bounding boxes", drawn_count);
     
     Ok(())
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yolo_labels() {
+        let content = "0 0.5 0.5 0.25 0.5\n\n2 0.1 0.2 0.05 0.1 0.8\n";
+        let boxes = parse_yolo_labels(content).unwrap();
+        assert_eq!(boxes.len(), 2);
+
+        assert_eq!(boxes[0].class_id, 0);
+        assert_eq!(boxes[0].confidence, 1.0);
+        assert_eq!((boxes[0].x_center, boxes[0].y_center), (0.5, 0.5));
+        assert_eq!((boxes[0].width, boxes[0].height), (0.25, 0.5));
+
+        assert_eq!(boxes[1].class_id, 2);
+        assert_eq!(boxes[1].confidence, 0.8);
+        assert_eq!((boxes[1].width, boxes[1].height), (0.05, 0.1));
+
+        assert!(parse_yolo_labels("0 0.5 0.5 0.25").unwrap_err().contains("line 1"));
+        assert!(parse_yolo_labels("person 0.5 0.5 0.25 0.5").is_err());
+    }
+}
@@ -1,11 +1,18 @@
 use clap::{Arg, Command};
 use opencv::{imgcodecs, prelude::*};
-use smiletrack::simple_detector::{SimpleDetector, SimpleFrameResult, SimpleTrack};
+use smiletrack::simple_detector::{to_coco_json, SimpleDetector, SimpleFrameResult, SimpleTrack};
+use smiletrack::utils::parse_class_list;
 use std::fs::File;
 use std::io::Write;
 use anyhow::Result;
+use log::{error, info};
+
+/// Dummy forward passes run before the first frame so its latency is not an outlier
+const WARMUP_ITERS: usize = 3;
 
 fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
     // Parse command line arguments
     let matches = Command::new("Simple Detector")
         .version("0.1.0")
@@ -42,6 +49,18 @@ fn main() -> Result<()> {
                 .help("Output JSON file")
                 .default_value("./detections.json"),
         )
+        .arg(
+            Arg::new("coco_output")
+                .long("coco-output")
+                .value_name("COCO_OUTPUT")
+                .help("Also write detections as a COCO results.json (image id taken from a numeric file name, else 0)"),
+        )
+        .arg(
+            Arg::new("classes")
+                .long("classes")
+                .value_name("IDS")
+                .help("Comma-separated class ids to detect, e.g. 0,2,5"),
+        )
         .get_matches();
 
     let input_path = matches.get_one::<String>("input").unwrap();
@@ -52,31 +71,49 @@ fn main() -> Result<()> {
         .parse::<f32>()
         .unwrap_or(0.25);
     let output_path = matches.get_one::<String>("output").unwrap();
+    let coco_output_path = matches.get_one::<String>("coco_output");
+    let classes = matches.get_one::<String>("classes").map(|list| parse_class_list(list)).transpose()?;
 
-    println!("Loading model from: {}", weights_path);
-    println!("Using confidence threshold: {}", conf_threshold);
+    info!("Loading model from: {}", weights_path);
+    info!("Using confidence threshold: {}", conf_threshold);
 
     // Initialize the simple detector
-    let detector = SimpleDetector::new(
+    let mut detector = SimpleDetector::new(
         weights_path,
         "cpu",
         (640, 640),
         conf_threshold,
         0.45,
     )?;
+    if let Some(classes) = classes {
+        info!("Only detecting classes: {:?}", classes);
+        detector.set_allowed_classes(classes);
+    }
 
-    println!("Processing input: {}", input_path);
+    info!("Processing input: {}", input_path);
 
     // Read input frame
     let frame = imgcodecs::imread(input_path, imgcodecs::IMREAD_COLOR)?;
     if frame.rows() == 0 || frame.cols() == 0 {
-        println!("Error: Could not read input image: {}", input_path);
+        error!("Could not read input image: {}", input_path);
         return Ok(());
     }
 
     // Process frame
+    detector.warmup(WARMUP_ITERS)?;
     let frame_result = detector.process_frame(&frame, 0)?;
 
+    if let Some(coco_path) = coco_output_path {
+        // COCO image files are named by their id, e.g. 000000000139.jpg
+        let image_id = std::path::Path::new(input_path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<i64>().ok())
+            .unwrap_or(0);
+        info!("Saving COCO results to: {}", coco_path);
+        std::fs::write(coco_path, to_coco_json(std::slice::from_ref(&frame_result), &[image_id]))?;
+    }
+
     // Add track IDs to create tracks
     let output_result = SimpleFrameResult {
         frame_id: frame_result.frame_id,
@@ -87,11 +124,11 @@ fn main() -> Result<()> {
     // Save results to JSON
     let json_str = serde_json::to_string_pretty(&vec![output_result])?;
     
-    println!("Saving results to: {}", output_path);
+    info!("Saving results to: {}", output_path);
     let mut file = File::create(output_path)?;
     file.write_all(json_str.as_bytes())?;
 
-    println!("Done!");
+    info!("Done!");
     Ok(())
 }
 
@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use opencv::{
     prelude::*,
     videoio::{self, VideoCapture, VideoWriter, CAP_ANY},
@@ -8,14 +8,23 @@ use opencv::{
 };
 use std::{path::PathBuf, fs};
 use smiletrack::{Config, Detector, SMILEtrack, visualization, STrack};
-use smiletrack::detection::Detection;
+use smiletrack::detection::{Detection, DetectionCache};
+use smiletrack::roi::{MaskFilter, RoiFilter};
+use smiletrack::undistort::Undistorter;
+use smiletrack::visualization::VisStyle;
+use smiletrack::utils::{parse_class_list, Profiler, RollingTimings, Timings};
+use smiletrack::analytics::{CsvWriter, TrackCountSeries, TrackSubtitles, TrajectoryExporter};
+use smiletrack::self_test;
+use smiletrack::simple_detector::{to_coco_json, SimpleDetection, SimpleFrameResult};
 use std::fs::File;
 use std::io::Write;
 use serde::{Serialize, Deserialize};
 use serde_json;
 use anyhow;
 use std::path::Path;
+use std::collections::HashMap;
 use std::sync::Arc;
+use log::{debug, error, info, trace, warn};
 
 #[derive(Parser)]
 #[command(
@@ -24,9 +33,9 @@ use std::sync::Arc;
     version = "0.1.0"
 )]
 struct Args {
-    /// Path to video file or image
-    #[arg(short, long, required = true)]
-    input: PathBuf,
+    /// Path to video file, image, or directory of images; a camera index; or an rtsp:// / http(s):// URL
+    #[arg(short, long, required_unless_present = "self_test")]
+    input: Option<PathBuf>,
 
     /// Output path (directory for frames or video file)
     #[arg(short, long)]
@@ -44,9 +53,521 @@ struct Args {
     #[arg(short, long)]
     visualize: bool,
 
-    /// Frames per second (for video output)
-    #[arg(long, default_value_t = 30.0)]
-    fps: f64,
+    /// Frames per second for the motion model and video output [default: the source's
+    /// frame rate, or 30 when it has none]
+    #[arg(long)]
+    fps: Option<f64>,
+
+    /// Codec for video output; falls back to the others, then to an image sequence, if the
+    /// writer cannot be opened with it
+    #[arg(long, value_enum, default_value_t = Codec::Avc1)]
+    codec: Codec,
+
+    /// JSON file with a polygon `[[x, y], ...]`; only detections inside it are tracked
+    #[arg(long)]
+    roi: Option<PathBuf>,
+
+    /// Mask image of the valid area; detections centered on black pixels are dropped
+    #[arg(long, value_name = "PNG")]
+    mask: Option<PathBuf>,
+
+    /// Camera calibration JSON (`camera_matrix`, `dist_coeffs`); frames are undistorted
+    /// before detection so straight-line motion stays straight
+    #[arg(long, value_name = "FILE")]
+    calibration: Option<PathBuf>,
+
+    /// Comma-separated class ids to detect and track, e.g. 0,2,5; overrides the config
+    #[arg(long, value_name = "IDS")]
+    classes: Option<String>,
+
+    /// Run the detector in fp16 (CUDA only); overrides the config
+    #[arg(long)]
+    half: bool,
+
+    /// Detect only: skip the tracker and draw and log detections, with an empty `tracks`
+    /// array in the tracking log
+    #[arg(long)]
+    no_track: bool,
+
+    /// Stream one JSON line per frame to stdout instead of writing output files
+    #[arg(long, conflicts_with = "output")]
+    output_stdout: bool,
+
+    /// Write the tracking log as one pretty-printed JSON array (rewritten every frame)
+    /// instead of appending one JSON object per line
+    #[arg(long)]
+    pretty_log: bool,
+
+    /// Fill track gaps of up to N frames in the pretty-printed log by linear interpolation
+    #[arg(long, value_name = "N", requires = "pretty_log")]
+    interpolate_gaps: Option<usize>,
+
+    /// Write a CSV of active track counts per frame to this path
+    #[arg(long)]
+    counts_csv: Option<PathBuf>,
+
+    /// Write a subtitle file (.srt, or WebVTT for .vtt) listing the active tracks of
+    /// each frame, to overlay on the original video in a player
+    #[arg(long, value_name = "PATH")]
+    subtitles: Option<PathBuf>,
+
+    /// Write tracking results as a flat CSV (frame_id, track_id, class, box, score, velocity)
+    #[arg(long, value_name = "PATH")]
+    csv: Option<PathBuf>,
+
+    /// Write one JSON record per track ID with its boxes over the whole run
+    #[arg(long, value_name = "PATH")]
+    trajectories: Option<PathBuf>,
+
+    /// Also write detections as a COCO results.json (image id taken from a numeric frame
+    /// file name, else the frame index)
+    #[arg(long, value_name = "PATH")]
+    coco_output: Option<PathBuf>,
+
+    /// Add one count column per class to --counts-csv
+    #[arg(long, requires = "counts_csv")]
+    counts_per_class: bool,
+
+    /// Label tracks with the ID only, without class name and score
+    #[arg(long)]
+    short_labels: bool,
+
+    /// Check the config, device and model (with one dummy inference), print a pass/fail
+    /// report and exit
+    #[arg(long)]
+    self_test: bool,
+
+    /// Save a montage.png grid of the first N annotated frames next to the outputs
+    #[arg(long, value_name = "N")]
+    montage: Option<usize>,
+
+    /// Track on per-frame detections cached by `--save-detections` instead of running the model
+    #[arg(long, value_name = "PATH")]
+    detections: Option<PathBuf>,
+
+    /// Save every frame's detections (before ROI filtering) to a .json/.jsonl cache
+    #[arg(long, value_name = "PATH")]
+    save_detections: Option<PathBuf>,
+
+    /// Overlay a heatmap of where tracks have been, accumulated over the run, and save the
+    /// final map as heatmap.png next to the outputs
+    #[arg(long)]
+    heatmap: bool,
+
+    /// Run the detector on every Nth frame only; tracks coast on prediction in between
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    detection_stride: u32,
+
+    /// Draw a small bar above each box whose length and color (red to green) show its score
+    #[arg(long)]
+    confidence_bar: bool,
+
+    /// Record each track's Kalman innovation norm (measurement minus prediction) in the tracking log
+    #[arg(long)]
+    log_innovation: bool,
+
+    /// On video input, read and detect on a worker thread while the main thread tracks,
+    /// draws and writes, so the two stages overlap
+    #[arg(long)]
+    pipelined: bool,
+
+    /// How to treat a directory of images
+    #[arg(long, value_enum, default_value_t = BatchMode::Sequence)]
+    batch_mode: BatchMode,
+
+    /// Write the effective configuration (after command-line overrides) as
+    /// effective_config.json next to the outputs
+    #[arg(long)]
+    dump_config: bool,
+}
+
+/// Whether frame `frame_id` gets a detector pass under `--detection-stride`
+fn is_detection_frame(frame_id: i32, stride: u32) -> bool {
+    frame_id.rem_euclid(stride.max(1) as i32) == 0
+}
+
+/// Feed one frame to the tracker: a full update on detection frames, prediction only on
+/// the frames skipped by the stride
+fn step_tracker(
+    tracker: &mut SMILEtrack,
+    detections: &[Detection],
+    frame: &Mat,
+    frame_id: i32,
+    stride: u32,
+) -> anyhow::Result<()> {
+    if is_detection_frame(frame_id, stride) {
+        tracker.update(detections, frame, frame_id)?;
+    } else {
+        tracker.predict_only(frame, frame_id)?;
+    }
+    Ok(())
+}
+
+/// Step the tracker, if there is one, and return its activated tracks. Without a tracker
+/// (`--no-track`) there are no tracks and no association cost.
+fn track_frame(
+    tracker: Option<&mut SMILEtrack>,
+    detections: &[Detection],
+    frame: &Mat,
+    frame_id: i32,
+    stride: u32,
+) -> anyhow::Result<Vec<STrack>> {
+    let Some(tracker) = tracker else {
+        return Ok(Vec::new());
+    };
+    step_tracker(tracker, detections, frame, frame_id, stride)?;
+    Ok(tracker.tracks().iter().filter(|t| t.is_activated()).cloned().collect())
+}
+
+/// COCO image id of a frame: the number in its file name (COCO images are named by id,
+/// e.g. 000000000139.jpg), else the frame index
+fn coco_image_id(frame_path: Option<&str>, frame_id: i32) -> i64 {
+    frame_path
+        .and_then(|path| Path::new(path).file_stem())
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.parse::<i64>().ok())
+        .unwrap_or(frame_id as i64)
+}
+
+/// One frame's detections in the shape `to_coco_json` takes
+fn coco_frame(frame_id: i32, detections: &[Detection]) -> SimpleFrameResult {
+    SimpleFrameResult {
+        frame_id,
+        detections: detections.iter()
+            .map(|det| SimpleDetection {
+                bbox: [det.tlwh[0], det.tlwh[1], det.tlwh[2], det.tlwh[3]],
+                confidence: det.confidence,
+                class_id: det.class_id,
+                class_name: None,
+            })
+            .collect(),
+        tracks: Vec::new(),
+    }
+}
+
+/// Opacity of the `--heatmap` overlay
+const HEATMAP_ALPHA: f64 = 0.4;
+
+/// Frames averaged in the periodic per-stage timing log
+const STAGE_TIMING_WINDOW: usize = 30;
+
+/// Wall-clock span the on-screen processing FPS is averaged over
+const FPS_WINDOW: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Measured processing rate: frames completed over a sliding wall-clock window
+struct FpsMeter {
+    window: std::time::Duration,
+    ticks: std::collections::VecDeque<std::time::Instant>,
+}
+
+impl FpsMeter {
+    fn new(window: std::time::Duration) -> Self {
+        FpsMeter { window, ticks: std::collections::VecDeque::new() }
+    }
+
+    /// Record a finished frame at `now`, forgetting frames older than the window
+    fn tick(&mut self, now: std::time::Instant) {
+        self.ticks.push_back(now);
+        while let Some(&oldest) = self.ticks.front() {
+            if now.duration_since(oldest) > self.window {
+                self.ticks.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Frames per second over the window, 0 until two frames have been seen
+    fn fps(&self) -> f64 {
+        match (self.ticks.front(), self.ticks.back()) {
+            (Some(first), Some(last)) if self.ticks.len() > 1 => {
+                let span = last.duration_since(*first).as_secs_f64();
+                if span > 0.0 { (self.ticks.len() - 1) as f64 / span } else { 0.0 }
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Frames in flight between the two stages of `--pipelined`
+const PIPELINE_DEPTH: usize = 4;
+
+/// Overlap detection with tracking and drawing: a worker thread pulls frames from `read` and
+/// runs `detect` on them while the calling thread hands the results and detector stage
+/// times, in frame order, to `consume`. The channel is bounded so a slow consumer throttles
+/// the reader, and dropping the receiver (on interrupt or error) stops the worker at its
+/// next send.
+///
+/// Each frame must be a fresh `Mat` moved through the channel. `VideoCapture::read` into one
+/// reused buffer would overwrite pixels the consumer still holds, since `Mat` clones share
+/// their data. Returns the number of frames consumed.
+fn run_pipelined<R, D, C>(read: R, detect: D, mut consume: C) -> Result<i32, Box<dyn std::error::Error>>
+where
+    R: FnMut() -> anyhow::Result<Option<Mat>> + Send,
+    D: FnMut(&Mat) -> anyhow::Result<(Vec<Detection>, Timings)> + Send,
+    C: FnMut(i32, &Mat, Vec<Detection>, Timings) -> Result<bool, Box<dyn std::error::Error>>,
+{
+    let (tx, rx) = std::sync::mpsc::sync_channel::<anyhow::Result<(Mat, Vec<Detection>, Timings)>>(PIPELINE_DEPTH);
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let (mut read, mut detect) = (read, detect);
+            loop {
+                let item = match read() {
+                    Ok(Some(frame)) => detect(&frame).map(|(detections, timings)| (frame, detections, timings)),
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                };
+                let failed = item.is_err();
+                if tx.send(item).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let mut frame_id = 0;
+        for item in rx {
+            let (frame, detections, timings) = item?;
+            if !consume(frame_id, &frame, detections, timings)? {
+                break;
+            }
+            frame_id += 1;
+        }
+        Ok(frame_id)
+    })
+}
+
+/// Frame rate used when neither `--fps` nor the source provides one
+const DEFAULT_FPS: f64 = 30.0;
+
+/// Frame rate for tracking and output: `--fps` when given, else the source's reported rate
+/// when it is usable, else `DEFAULT_FPS`
+fn resolve_fps(explicit: Option<f64>, source_fps: f64) -> f64 {
+    match explicit {
+        Some(fps) => fps,
+        None if source_fps.is_finite() && source_fps > 0.0 => source_fps,
+        None => DEFAULT_FPS,
+    }
+}
+
+/// Video output codec
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Codec {
+    /// MPEG-4 Part 2
+    Mp4v,
+    /// H.264
+    Avc1,
+    /// Xvid MPEG-4
+    Xvid,
+}
+
+impl Codec {
+    fn fourcc(self) -> opencv::Result<i32> {
+        match self {
+            Codec::Mp4v => VideoWriter::fourcc('m', 'p', '4', 'v'),
+            Codec::Avc1 => VideoWriter::fourcc('a', 'v', 'c', '1'),
+            Codec::Xvid => VideoWriter::fourcc('X', 'V', 'I', 'D'),
+        }
+    }
+
+    /// `self` first, then the other codecs as fallbacks
+    fn with_fallbacks(self) -> Vec<Codec> {
+        let mut codecs = vec![self];
+        codecs.extend([Codec::Mp4v, Codec::Avc1, Codec::Xvid].into_iter().filter(|&c| c != self));
+        codecs
+    }
+}
+
+/// Handling of a directory of images
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum BatchMode {
+    /// Frames of one sequence; tracks persist across images
+    Sequence,
+    /// Unrelated images; the tracker is reset and outputs are written per image
+    Independent,
+}
+
+/// Path for an auxiliary output file: inside `output` when it is a directory (or has no
+/// extension, i.e. is about to become one), beside it when it is a file, else the working directory
+fn output_sibling(output: Option<&Path>, name: &str) -> PathBuf {
+    match output {
+        Some(dir) if dir.is_dir() || dir.extension().is_none() => dir.join(name),
+        Some(file) => file.parent().map_or_else(|| PathBuf::from(name), |p| p.join(name)),
+        None => PathBuf::from(name),
+    }
+}
+
+/// One image of a directory run
+#[derive(Debug)]
+struct BatchItem {
+    path: PathBuf,
+    frame_id: i32,
+    /// Start from a fresh tracker before this image
+    reset_tracker: bool,
+    /// Per-image tracking log (independent mode only)
+    log_path: Option<PathBuf>,
+}
+
+/// Consecutive failed reads tolerated on a live source before giving up
+const MAX_READ_RETRIES: u32 = 5;
+
+/// Consecutive empty (dropped or corrupt) frames skipped before the source is treated as dead
+const MAX_EMPTY_FRAMES: u32 = 50;
+
+/// Dummy forward passes run before the first frame so its latency is not an outlier
+const WARMUP_ITERS: usize = 3;
+
+/// Next non-empty frame from `read`, which fills a fresh `Mat` and returns false once the
+/// capture has nothing more. Empty frames are skipped without consuming a frame id, so ids
+/// count processed frames; a failed read ends a file but is retried on a live source.
+fn read_good_frame(
+    read: &mut impl FnMut(&mut Mat) -> opencv::Result<bool>,
+    is_live: bool,
+) -> anyhow::Result<Option<Mat>> {
+    let mut failed_reads = 0;
+    let mut empty_frames = 0;
+    loop {
+        let mut frame = Mat::default();
+        if !read(&mut frame)? {
+            // Live sources drop frames occasionally; retry a few times before giving up
+            if is_live && failed_reads < MAX_READ_RETRIES {
+                failed_reads += 1;
+                warn!("Failed to read frame, retrying ({}/{})", failed_reads, MAX_READ_RETRIES);
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                continue;
+            }
+            return Ok(None);
+        }
+        if frame.empty() {
+            empty_frames += 1;
+            if empty_frames > MAX_EMPTY_FRAMES {
+                warn!("{} empty frames in a row, stopping", empty_frames);
+                return Ok(None);
+            }
+            warn!("Skipping empty frame ({}/{})", empty_frames, MAX_EMPTY_FRAMES);
+            continue;
+        }
+        return Ok(Some(frame));
+    }
+}
+
+/// Kind of source named by `--input`
+#[derive(Debug, PartialEq)]
+enum InputSource {
+    /// Local camera by device index
+    Camera(i32),
+    /// Network stream (rtsp/http)
+    Stream(String),
+    /// Directory of images
+    Directory(PathBuf),
+    /// Single image file
+    Image(PathBuf),
+    /// Video file
+    Video(PathBuf),
+}
+
+impl InputSource {
+    fn classify(input: &Path) -> Self {
+        let text = input.to_string_lossy();
+        let lower = text.to_lowercase();
+        if let Ok(index) = text.parse::<i32>() {
+            InputSource::Camera(index)
+        } else if ["rtsp://", "rtsps://", "http://", "https://"].iter().any(|p| lower.starts_with(p)) {
+            InputSource::Stream(text.to_string())
+        } else if input.is_dir() {
+            InputSource::Directory(input.to_path_buf())
+        } else if is_image_path(input) {
+            InputSource::Image(input.to_path_buf())
+        } else {
+            InputSource::Video(input.to_path_buf())
+        }
+    }
+
+    /// Cameras and network streams have no fixed length and may drop frames
+    fn is_live(&self) -> bool {
+        matches!(self, InputSource::Camera(_) | InputSource::Stream(_))
+    }
+}
+
+fn is_image_path(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "bmp"),
+        None => false,
+    }
+}
+
+/// Consume a run of ASCII digits
+fn take_number(it: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = it.peek().copied().filter(|c| c.is_ascii_digit()) {
+        digits.push(c);
+        it.next();
+    }
+    digits
+}
+
+/// Compare file names with digit runs ordered numerically, so `frame_2.jpg` sorts before `frame_10.jpg`
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let (na, nb) = (take_number(&mut a), take_number(&mut b));
+                let (ta, tb) = (na.trim_start_matches('0'), nb.trim_start_matches('0'));
+                let ord = ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb));
+                if ord != std::cmp::Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(ca), Some(cb)) => {
+                if ca != cb {
+                    return ca.cmp(&cb);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Image files in `dir`, in frame order (numeric-aware file name sort)
+fn list_images(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_image_path(&path) {
+            images.push(path);
+        }
+    }
+    images.sort_by(|a, b| {
+        let name = |p: &PathBuf| p.file_name().unwrap_or_default().to_string_lossy().to_string();
+        natural_cmp(&name(a), &name(b))
+    });
+    Ok(images)
+}
+
+/// Decide frame ids, tracker resets and per-image logs for a directory run
+fn plan_batch(images: Vec<PathBuf>, mode: BatchMode, output_dir: Option<&Path>, log_ext: &str) -> Vec<BatchItem> {
+    images.into_iter().enumerate().map(|(i, path)| match mode {
+        BatchMode::Sequence => BatchItem {
+            path,
+            frame_id: i as i32 + 1,
+            reset_tracker: false,
+            log_path: None,
+        },
+        BatchMode::Independent => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let log_name = format!("{}_tracking.{}", stem, log_ext);
+            BatchItem {
+                log_path: Some(output_dir.map_or_else(|| PathBuf::from(&log_name), |d| d.join(&log_name))),
+                path,
+                frame_id: 1,
+                reset_tracker: true,
+            }
+        }
+    }).collect()
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -71,6 +592,8 @@ struct DetectionLog {
     confidence: f32,
     class_id: i32,
     class_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -80,6 +603,11 @@ struct TrackLog {
     confidence: f32,
     class_id: i32,
     class_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    /// Kalman innovation norm at the latest update, with --log-innovation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    innovation: Option<f32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -90,8 +618,10 @@ struct FrameLog {
 }
 
 struct ProcessingState {
-    detector: Detector,
-    tracker: SMILEtrack,
+    /// `None` when the detector has moved to the worker thread of the `--pipelined` path
+    detector: Option<Detector>,
+    /// `None` with `--no-track`
+    tracker: Option<SMILEtrack>,
     writer: Option<VideoWriter>,
     annotations: Vec<YoloAnnotation>,
     annotation_path: Option<PathBuf>,
@@ -99,41 +629,326 @@ struct ProcessingState {
     window_name: String,
     show_visualization: bool,
     show_detections: bool,
-    tracking_log: Vec<FrameLog>,
-    tracking_log_path: Option<PathBuf>,
+    verbose_labels: bool,
+    /// Draw a score bar above each box
+    confidence_bar: bool,
+    /// Annotated frames collected for the montage, how many to collect, and where to save it
+    montage: Option<(Vec<Mat>, usize, PathBuf)>,
+    /// Detect on every `detection_stride`-th frame, predicting only in between
+    detection_stride: u32,
+    /// Track-position heatmap accumulated so far (allocated on the first frame) and where to save it
+    heatmap: Option<(Mat, PathBuf)>,
+    /// Whether the heatmap is overlaid on the shown frames ('h' in the preview window)
+    show_heatmap: bool,
+    /// Frames per second actually being processed, shown in the frame info
+    fps_meter: FpsMeter,
+    /// Preview paused with the space bar
+    paused: bool,
+    /// Detections read from `--detections`, used instead of the detector
+    cached_detections: Option<DetectionCache>,
+    /// Detections collected for `--save-detections` and the file to write them to
+    detection_log: Option<(DetectionCache, PathBuf)>,
+    /// Include each track's Kalman innovation norm in the tracking log
+    log_innovation: bool,
+    log_writer: TrackingLogWriter,
+    roi_filter: Option<RoiFilter>,
+    mask_filter: Option<MaskFilter>,
+    profiler: Profiler,
+    /// Detector stage times of the frame being processed (zero when detection was skipped)
+    detect_timings: Timings,
+    /// Per-stage times over recent frames, logged periodically
+    stage_timings: RollingTimings,
+    /// Per-frame track counts and the CSV path (and per-class flag) to export them to
+    track_counts: Option<(TrackCountSeries, PathBuf, bool)>,
+    /// Per-frame track subtitles and the file to write them to
+    subtitles: Option<(TrackSubtitles, PathBuf)>,
+    /// Flat CSV of tracking results, written as frames are processed
+    csv_writer: Option<CsvWriter<std::io::BufWriter<File>>>,
+    /// Per-track trajectories and the file to write them to
+    trajectories: Option<(TrajectoryExporter, PathBuf)>,
+    /// Per-frame detections with their COCO image ids, and the results.json to write
+    coco_results: Option<(Vec<SimpleFrameResult>, Vec<i64>, PathBuf)>,
+    finalized: bool,
+}
+
+/// The tracking-log entry for one frame: every detection and every track passed in
+fn build_frame_log(frame_id: i32, detections: &[Detection], tracks: &[STrack], log_innovation: bool) -> FrameLog {
+    // Log ALL detections without filtering
+    let mut detection_logs = Vec::new();
+    
+    debug!("Logging all {} detections for comparison", detections.len());
+    
+    for det in detections {
+        let tlwh = det.tlwh();
+        let class_name = visualization::class_label(det.class_id);
+        
+        // Print each detection for debugging
+        trace!("Detection: class={} ({}), conf={:.3}, bbox=[{:.1}, {:.1}, {:.1}, {:.1}]",
+            class_name, det.class_id, det.confidence, tlwh[0], tlwh[1], tlwh[2], tlwh[3]);
+        
+        detection_logs.push(DetectionLog {
+            bbox: vec![tlwh[0], tlwh[1], tlwh[2], tlwh[3]],
+            confidence: det.confidence,
+            class_id: det.class_id,
+            class_name,
+            source: det.source.clone(),
+        });
+    }
+    
+    // Log ALL tracks, not just activated ones for debugging
+    let mut track_logs = Vec::new();
+    
+    debug!("Logging all {} tracks for comparison", tracks.len());
+    
+    for track in tracks {
+        let tlwh = track.tlwh().clone();
+        let class_name = visualization::class_label(track.class_id);
+        
+        // Print each track for debugging
+        trace!("Track: id={}, class={} ({}), conf={:.3}, bbox=[{:.1}, {:.1}, {:.1}, {:.1}], activated={}",
+            track.track_id(), class_name, track.class_id, track.score, 
+            tlwh[0], tlwh[1], tlwh[2], tlwh[3], track.is_activated());
+        
+        track_logs.push(TrackLog {
+            track_id: track.track_id(),
+            bbox: vec![tlwh[0], tlwh[1], tlwh[2], tlwh[3]],
+            confidence: track.score,
+            class_id: track.class_id,
+            class_name,
+            source: track.source.clone(),
+            innovation: log_innovation.then_some(track.last_innovation()),
+        });
+    }
+    
+    FrameLog {
+        frame_id,
+        detections: detection_logs,
+        tracks: track_logs,
+    }
+}
+
+/// Fill frames where a track was briefly missing (at most `max_gap` frames) with boxes
+/// linearly interpolated between the last-seen and refound boxes.
+fn interpolate_gaps(frames: &mut [FrameLog], max_gap: usize) {
+    let frame_index: HashMap<i32, usize> = frames.iter()
+        .enumerate()
+        .map(|(i, f)| (f.frame_id, i))
+        .collect();
+
+    // Observations per track, in frame order
+    let mut observations: HashMap<u32, Vec<(i32, TrackLog)>> = HashMap::new();
+    for frame in frames.iter() {
+        for track in &frame.tracks {
+            observations.entry(track.track_id).or_default().push((frame.frame_id, track.clone()));
+        }
+    }
+
+    for obs in observations.values_mut() {
+        obs.sort_by_key(|(frame_id, _)| *frame_id);
+        for pair in obs.windows(2) {
+            let (start_frame, start) = &pair[0];
+            let (end_frame, end) = &pair[1];
+            let span = end_frame - start_frame;
+            if span < 2 || (span - 1) as usize > max_gap {
+                continue;
+            }
+            for frame_id in start_frame + 1..*end_frame {
+                let Some(&idx) = frame_index.get(&frame_id) else { continue };
+                let t = (frame_id - start_frame) as f32 / span as f32;
+                let bbox = start.bbox.iter().zip(&end.bbox)
+                    .map(|(a, b)| a + (b - a) * t)
+                    .collect();
+                frames[idx].tracks.push(TrackLog {
+                    bbox,
+                    confidence: start.confidence + (end.confidence - start.confidence) * t,
+                    ..start.clone()
+                });
+            }
+        }
+    }
+}
+
+/// Write a frame log as a single JSON line and flush it so downstream readers see it immediately.
+fn write_frame_line<W: Write + ?Sized>(out: &mut W, frame_log: &FrameLog) -> Result<(), Box<dyn std::error::Error>> {
+    let line = serde_json::to_string(frame_log)?;
+    writeln!(out, "{}", line)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Destinations for the per-frame tracking log
+struct TrackingLogWriter {
+    /// Pretty-printed JSON array file, rewritten on every frame
+    path: Option<PathBuf>,
+    /// Frames logged so far (only kept when writing to `path`)
+    frames: Vec<FrameLog>,
+    /// JSON-lines stream (stdout or an append-mode file)
+    stream: Option<Box<dyn Write>>,
+    /// Interpolate track gaps up to this many frames when finalizing `path`
+    max_gap: Option<usize>,
+}
+
+impl TrackingLogWriter {
+    fn new(path: Option<PathBuf>, stream: Option<Box<dyn Write>>) -> Self {
+        TrackingLogWriter {
+            path,
+            frames: Vec::new(),
+            stream,
+            max_gap: None,
+        }
+    }
+
+    /// Fill short track gaps in the array log on `finalize`
+    fn with_gap_filling(mut self, max_gap: Option<usize>) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    /// Log to stdout, or to `path` as JSON lines (or as a pretty array when `pretty` is set)
+    fn open(path: Option<PathBuf>, to_stdout: bool, pretty: bool) -> std::io::Result<Self> {
+        if to_stdout {
+            return Ok(Self::new(None, Some(Box::new(std::io::stdout()))));
+        }
+        match path {
+            Some(path) if pretty => Ok(Self::new(Some(path), None)),
+            Some(path) => {
+                let file = std::io::BufWriter::new(File::create(&path)?);
+                Ok(Self::new(None, Some(Box::new(file))))
+            }
+            None => Ok(Self::new(None, None)),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.path.is_some() || self.stream.is_some()
+    }
+
+    fn write_frame(&mut self, frame_log: FrameLog) -> Result<(), Box<dyn std::error::Error>> {
+        // Stream the frame if requested
+        if let Some(out) = &mut self.stream {
+            write_frame_line(out, &frame_log)?;
+        }
+        
+        // Write to file (write the entire log each time to handle crashes)
+        if self.path.is_some() {
+            self.frames.push(frame_log);
+            self.write_file()?;
+        }
+        Ok(())
+    }
+
+    fn write_file(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(path) = &self.path {
+            let json = serde_json::to_string_pretty(&self.frames)?;
+            let mut file = File::create(path)?;
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+            debug!("Updated tracking log saved to {:?}", path);
+        }
+        Ok(())
+    }
+
+    /// Flush everything still buffered.
+    fn finalize(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(out) = &mut self.stream {
+            out.flush()?;
+        }
+        if let Some(max_gap) = self.max_gap {
+            interpolate_gaps(&mut self.frames, max_gap);
+        }
+        self.write_file()
+    }
 }
 
 impl ProcessingState {
-    fn process_frame(&mut self, frame: &Mat, frame_path: Option<&str>, frame_id: i32, fps: f64) -> Result<bool, Box<dyn std::error::Error>> {
+    fn process_frame(&mut self, frame: &Mat, frame_path: Option<&str>, frame_id: i32) -> Result<bool, Box<dyn std::error::Error>> {
         // Run detection
-        let detections = self.detector.detect(frame)?;
-        println!("{} detections found", detections.len());
+        let detections = if is_detection_frame(frame_id, self.detection_stride) {
+            let start = std::time::Instant::now();
+            let detections = self.detect(frame, frame_id)?;
+            self.profiler.record("detect", start.elapsed());
+            detections
+        } else {
+            Vec::new()
+        };
+        self.process_detections(frame, detections, frame_path, frame_id)
+    }
+
+    /// Detections for a frame, from the cache when one was loaded, otherwise from the model
+    fn detect(&mut self, frame: &Mat, frame_id: i32) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+        if let Some(cache) = &self.cached_detections {
+            return Ok(cache.get(frame_id).to_vec());
+        }
+        let (detections, timings) = self.detector.as_ref().ok_or("detector not available")?.detect_timed(frame)?;
+        self.detect_timings = timings;
+        Ok(detections)
+    }
+
+    /// Everything after detection: ROI filtering, tracking, logging, drawing and output
+    fn process_detections(
+        &mut self,
+        frame: &Mat,
+        mut detections: Vec<Detection>,
+        frame_path: Option<&str>,
+        frame_id: i32,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if let Some((cache, _)) = &mut self.detection_log {
+            if is_detection_frame(frame_id, self.detection_stride) {
+                cache.insert(frame_id, &detections);
+            }
+        }
+        if let Some(roi) = &self.roi_filter {
+            detections = roi.filter(detections);
+        }
+        if let Some(mask) = &mut self.mask_filter {
+            detections = mask.filter(detections, frame.size()?)?;
+        }
+        debug!("{} detections found", detections.len());
 
         // Print high confidence detections
         let high_conf_dets: Vec<_> = detections.iter()
             .filter(|det| det.confidence >= 0.25)
             .collect();
         
-        println!("{} high confidence detections", high_conf_dets.len());
+        debug!("{} high confidence detections", high_conf_dets.len());
         
         for det in high_conf_dets.iter().take(5) {  // Show first 5 high confidence detections
             let tlwh = det.tlwh();
-            println!("High score detection: class={}, score={:.3}, box=[{:.1}, {:.1}, {:.1}, {:.1}]", 
+            trace!("High score detection: class={}, score={:.3}, box=[{:.1}, {:.1}, {:.1}, {:.1}]", 
                 det.class_id, det.confidence, tlwh[0], tlwh[1], tlwh[2], tlwh[3]);
         }
 
         // Update tracks
-        println!("Updating tracks...");
-        self.tracker.update(&detections, frame, frame_id)?;
-        
-        // Get tracks that are activated
-        let tracks = self.tracker.tracks();
-        let activated_tracks: Vec<STrack> = tracks.iter()
-            .filter(|t| t.is_activated())
-            .cloned()
-            .collect();
-
-        println!("{} tracks are activated", activated_tracks.len());
+        debug!("Updating tracks...");
+        let timer = self.profiler.scope("track");
+        let activated_tracks = track_frame(self.tracker.as_mut(), &detections, frame, frame_id, self.detection_stride)?;
+        drop(timer);
+        let mut timings = std::mem::take(&mut self.detect_timings);
+        if let Some(tracker) = &self.tracker {
+            timings = timings.add(&tracker.last_timings());
+        }
+        self.stage_timings.push(timings);
+        if self.stage_timings.len() == STAGE_TIMING_WINDOW && frame_id % STAGE_TIMING_WINDOW as i32 == 0 {
+            info!("Stage times (mean of last {} frames): {}", STAGE_TIMING_WINDOW, self.stage_timings.mean());
+        }
+        
+        debug!("{} tracks are activated", activated_tracks.len());
+        if let Some((series, _, _)) = &mut self.track_counts {
+            series.record(frame_id, &activated_tracks);
+        }
+        if let Some((subtitles, _)) = &mut self.subtitles {
+            subtitles.record(frame_id, &activated_tracks);
+        }
+        if let Some((exporter, _)) = &mut self.trajectories {
+            exporter.record(frame_id, &activated_tracks);
+        }
+        if let Some((results, image_ids, _)) = &mut self.coco_results {
+            results.push(coco_frame(frame_id, &detections));
+            image_ids.push(coco_image_id(frame_path, frame_id));
+        }
+        if let Some(csv_writer) = &mut self.csv_writer {
+            csv_writer.write_frame(frame_id, &activated_tracks)?;
+        }
         
         // Log tracking details for comparison with Python
         self.log_tracking_details(frame_id, &detections, &activated_tracks)?;
@@ -175,34 +990,58 @@ impl ProcessingState {
         }
 
         // Create visualization with tracking results
+        let timer = self.profiler.scope("draw");
         let mut output_frame = frame.clone();
+        let style = VisStyle::for_resolution(frame.cols(), frame.rows());
         
-        // Draw frame information - number of tracks
-        let track_count_text = format!("Total Tracked IDs: {}", activated_tracks.len());
-        visualization::draw_text(&mut output_frame, &track_count_text, 20, 30, 0.7, (0, 255, 0))?;
+        // Draw frame information - number of tracks, or of detections when not tracking
+        let track_count_text = if self.tracker.is_some() {
+            format!("Total Tracked IDs: {}", activated_tracks.len())
+        } else {
+            format!("Detections: {}", detections.len())
+        };
+        visualization::draw_text(&mut output_frame, &track_count_text, 20, 30, style.heading_scale(), (0, 255, 0), &style)?;
         
-        // Draw frame info - frame number, fps
-        visualization::draw_frame_info(&mut output_frame, frame_id, fps)?;
+        // Draw frame info - frame number, measured processing fps
+        self.fps_meter.tick(std::time::Instant::now());
+        visualization::draw_frame_info(&mut output_frame, frame_id, self.fps_meter.fps(), &style)?;
 
         // Draw detections if requested
         if self.show_detections {
-            visualization::draw_detections(&mut output_frame, &detections)?;
+            visualization::draw_detections(&mut output_frame, &detections, self.confidence_bar, &style)?;
+        }
+
+        if let Some((accumulator, _)) = &mut self.heatmap {
+            if accumulator.empty() {
+                *accumulator = Mat::new_rows_cols_with_default(frame.rows(), frame.cols(), opencv::core::CV_32FC1, opencv::core::Scalar::all(0.0))?;
+            }
+            visualization::render_heatmap(accumulator, &activated_tracks)?;
+            if self.show_heatmap {
+                visualization::overlay_heatmap(&mut output_frame, accumulator, HEATMAP_ALPHA)?;
+            }
         }
 
         // Draw tracks
-        visualization::draw_tracks(&mut output_frame, &activated_tracks)?;
+        visualization::draw_tracks(&mut output_frame, &activated_tracks, self.verbose_labels, self.confidence_bar, &style)?;
+        drop(timer);
+
+        if let Some((frames, limit, _)) = &mut self.montage {
+            if frames.len() < *limit {
+                frames.push(output_frame.clone());
+            }
+        }
         
         // Save visualization frame if output directory is provided
         if let Some(vis_dir) = &self.vis_output_dir {
             // Make sure vis_dir is a directory, not a file
             if vis_dir.exists() && !vis_dir.is_dir() {
-                println!("Warning: Output path {:?} is a file, not a directory. Skipping visualization output.", vis_dir);
+                warn!("Output path {:?} is a file, not a directory. Skipping visualization output.", vis_dir);
             } else {
                 // Create the directory if it doesn't exist
                 if !vis_dir.exists() {
-                    println!("Creating output directory: {:?}", vis_dir);
+                    info!("Creating output directory: {:?}", vis_dir);
                     fs::create_dir_all(vis_dir).map_err(|e| {
-                        println!("Failed to create directory: {}", e);
+                        error!("Failed to create directory: {}", e);
                         e
                     })?;
                 }
@@ -218,7 +1057,7 @@ impl ProcessingState {
                 };
                 
                 let output_path = vis_dir.join(output_filename);
-                println!("Writing output to: {:?}", output_path);
+                debug!("Writing output to: {:?}", output_path);
                 imgcodecs::imwrite(
                     &output_path.to_string_lossy(),
                     &output_frame,
@@ -230,10 +1069,16 @@ impl ProcessingState {
         // Show visualization if requested
         if self.show_visualization {
             highgui::imshow(&self.window_name, &output_frame)?;
-            let key = highgui::wait_key(1)?;
-            if key == 27 {  // ESC key
-                println!("\nTracking interrupted by user.");
-                return Ok(false);
+            let mut key = highgui::wait_key(1)?;
+            loop {
+                if !self.handle_key(key) {
+                    info!("Tracking interrupted by user.");
+                    return Ok(false);
+                }
+                if !self.paused {
+                    break;
+                }
+                key = highgui::wait_key(0)?;
             }
         }
 
@@ -245,116 +1090,146 @@ impl ProcessingState {
         Ok(true)
     }
     
+    /// Apply a preview keybind: space pauses/resumes, 'd' toggles detections, 'h' toggles
+    /// the heatmap overlay. Returns false on ESC.
+    fn handle_key(&mut self, key: i32) -> bool {
+        match key {
+            27 => return false,
+            32 => {
+                self.paused = !self.paused;
+                info!("{}", if self.paused { "Paused (space to resume)" } else { "Resumed" });
+            }
+            k if k == 'd' as i32 => {
+                self.show_detections = !self.show_detections;
+                info!("Detections {}", if self.show_detections { "shown" } else { "hidden" });
+            }
+            k if k == 'h' as i32 => {
+                if self.heatmap.is_some() {
+                    self.show_heatmap = !self.show_heatmap;
+                    info!("Heatmap {}", if self.show_heatmap { "shown" } else { "hidden" });
+                } else {
+                    info!("No heatmap to show; run with --heatmap");
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
     fn log_tracking_details(&mut self, frame_id: i32, detections: &[Detection], tracks: &[STrack]) -> Result<(), Box<dyn std::error::Error>> {
-        // Skip if no logging path is set
-        if self.tracking_log_path.is_none() {
+        // Skip if there is nowhere to log to
+        if !self.log_writer.is_enabled() {
             return Ok(());
         }
-        
-        // Log ALL detections without filtering
-        let mut detection_logs = Vec::new();
-        
-        println!("Logging all {} detections for comparison", detections.len());
-        
-        for det in detections {
-            let tlwh = det.tlwh();
-            let class_name = match det.class_id {
-                0 => "person".to_string(),
-                1 => "bicycle".to_string(),
-                2 => "car".to_string(),
-                3 => "motorcycle".to_string(),
-                5 => "bus".to_string(),
-                7 => "truck".to_string(),
-                15 => "cat".to_string(),
-                16 => "dog".to_string(),
-                _ => format!("class_{}", det.class_id),
-            };
-            
-            // Print each detection for debugging
-            println!("Detection: class={} ({}), conf={:.3}, bbox=[{:.1}, {:.1}, {:.1}, {:.1}]",
-                class_name, det.class_id, det.confidence, tlwh[0], tlwh[1], tlwh[2], tlwh[3]);
-            
-            detection_logs.push(DetectionLog {
-                bbox: vec![tlwh[0], tlwh[1], tlwh[2], tlwh[3]],
-                confidence: det.confidence,
-                class_id: det.class_id,
-                class_name,
-            });
-        }
-        
-        // Log ALL tracks, not just activated ones for debugging
-        let mut track_logs = Vec::new();
-        
-        println!("Logging all {} tracks for comparison", tracks.len());
-        
-        for track in tracks {
-            let tlwh = track.tlwh().clone();
-            let class_name = match track.class_id {
-                0 => "person".to_string(),
-                1 => "bicycle".to_string(),
-                2 => "car".to_string(),
-                3 => "motorcycle".to_string(),
-                5 => "bus".to_string(),
-                7 => "truck".to_string(),
-                15 => "cat".to_string(),
-                16 => "dog".to_string(),
-                _ => format!("class_{}", track.class_id),
-            };
-            
-            // Print each track for debugging
-            println!("Track: id={}, class={} ({}), conf={:.3}, bbox=[{:.1}, {:.1}, {:.1}, {:.1}], activated={}",
-                track.track_id(), class_name, track.class_id, track.score, 
-                tlwh[0], tlwh[1], tlwh[2], tlwh[3], track.is_activated());
-            
-            track_logs.push(TrackLog {
-                track_id: track.track_id(),
-                bbox: vec![tlwh[0], tlwh[1], tlwh[2], tlwh[3]],
-                confidence: track.score,
-                class_id: track.class_id,
-                class_name,
-            });
-        }
-        
-        // Create frame log
-        let frame_log = FrameLog {
-            frame_id,
-            detections: detection_logs,
-            tracks: track_logs,
-        };
-        
-        // Add to tracking log
-        self.tracking_log.push(frame_log);
-        
-        // Write to file (write the entire log each time to handle crashes)
-        if let Some(path) = &self.tracking_log_path {
-            let json = serde_json::to_string_pretty(&self.tracking_log)?;
-            let mut file = File::create(path)?;
-            file.write_all(json.as_bytes())?;
-            println!("Updated tracking log saved to {:?}", path);
-        }
-        
-        Ok(())
+        let frame_log = build_frame_log(frame_id, detections, tracks, self.log_innovation);
+        self.log_writer.write_frame(frame_log)
     }
     
     // Save annotations to JSON file
     fn save_annotations(&self) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(path) = &self.annotation_path {
-            println!("Saving annotations to {:?}...", path);
+            info!("Saving annotations to {:?}...", path);
             let json = serde_json::to_string_pretty(&self.annotations)?;
             let mut file = File::create(path)?;
             file.write_all(json.as_bytes())?;
-            println!("Annotations saved successfully.");
+            info!("Annotations saved successfully.");
+        }
+        Ok(())
+    }
+
+    /// Flush and close every output: the video writer, the tracking log and annotations.
+    /// Safe to call more than once; only the first call does any work.
+    fn finalize(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.finalized {
+            return Ok(());
+        }
+        self.finalized = true;
+
+        if let Some(mut writer) = self.writer.take() {
+            writer.release()?;
+        }
+        self.log_writer.finalize()?;
+        self.save_annotations()?;
+        if let Some((series, path, per_class)) = &self.track_counts {
+            series.write_csv(&path.to_string_lossy(), *per_class)?;
+            info!("Track counts for {} frames saved to {:?}", series.len(), path);
+        }
+        if let Some((cache, path)) = &self.detection_log {
+            cache.save(&path.to_string_lossy())?;
+            info!("Detections for {} frames saved to {:?}", cache.len(), path);
+        }
+        if let Some((subtitles, path)) = &self.subtitles {
+            subtitles.write(&path.to_string_lossy())?;
+            info!("{} subtitle cues saved to {:?}", subtitles.len(), path);
+        }
+        if let Some((exporter, path)) = &self.trajectories {
+            exporter.write(&path.to_string_lossy())?;
+            info!("{} track trajectories saved to {:?}", exporter.len(), path);
+        }
+        if let Some((results, image_ids, path)) = &self.coco_results {
+            fs::write(path, to_coco_json(results, image_ids))?;
+            info!("COCO results for {} frames saved to {:?}", results.len(), path);
+        }
+        if let Some((accumulator, path)) = &self.heatmap {
+            if !accumulator.empty() {
+                let mut image = Mat::new_rows_cols_with_default(accumulator.rows(), accumulator.cols(), opencv::core::CV_8UC3, opencv::core::Scalar::all(0.0))?;
+                visualization::overlay_heatmap(&mut image, accumulator, 1.0)?;
+                imgcodecs::imwrite(&path.to_string_lossy(), &image, &opencv::core::Vector::new())?;
+                info!("Track heatmap saved to {:?}", path);
+            }
+        }
+        if let Some((frames, _, path)) = &self.montage {
+            if !frames.is_empty() {
+                let cols = (frames.len() as f64).sqrt().ceil() as usize;
+                let montage = visualization::make_montage(frames, cols)?;
+                imgcodecs::imwrite(&path.to_string_lossy(), &montage, &opencv::core::Vector::new())?;
+                info!("Montage of {} frames saved to {:?}", frames.len(), path);
+            }
+        }
+        if let Some(detect) = self.profiler.stats("detect") {
+            info!("Timing over {} frames:\n{}", detect.count, self.profiler.report());
         }
         Ok(())
     }
 }
 
+impl Drop for ProcessingState {
+    /// Best-effort flush when processing is interrupted before `finalize` runs
+    fn drop(&mut self) {
+        if let Err(e) = self.finalize() {
+            error!("Failed to finalize outputs: {}", e);
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse command line arguments
     let args = Args::parse();
 
+    // Status messages at info level unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    if args.self_test {
+        let config_path = args.config.clone().unwrap_or_else(|| PathBuf::from("config.json"));
+        let weights = args.weights.as_ref().map(|w| w.to_string_lossy().to_string());
+        let report = self_test::run_checks(&config_path.to_string_lossy(), weights.as_deref());
+        for check in &report {
+            println!("{}", check);
+        }
+        if !self_test::all_passed(&report) {
+            return Err("self-test failed".into());
+        }
+        println!("All checks passed");
+        return Ok(());
+    }
+    let input = args.input.clone().expect("clap requires --input without --self-test");
+
+    // Tracking log file name for the chosen format
+    let log_name = if args.pretty_log { "tracking_details.json" } else { "tracking_details.jsonl" };
+    let log_ext = if args.pretty_log { "json" } else { "jsonl" };
+
     // Load config
-    println!("Loading configuration from {:?}...", args.config.as_deref().unwrap_or(&PathBuf::from("config.json")));
+    info!("Loading configuration from {:?}...", args.config.as_deref().unwrap_or(&PathBuf::from("config.json")));
     let mut config = Config::from_file(args.config.as_deref().unwrap_or(&PathBuf::from("config.json")).to_string_lossy().as_ref())?;
     
     // Override config with command line arguments if provided
@@ -365,42 +1240,184 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Use a very low threshold to catch everything, we'll filter later for visualization
     config.conf_threshold = 0.001;  // Catch all detections
     
-    println!("Initializing detector with weights from {:?}...", config.model_path);
-    println!("Using VERY LOW confidence threshold: {}", config.conf_threshold);
-    println!("Using track threshold: {}", config.track_high_thresh);
+    if args.dump_config {
+        let config_path = output_sibling(args.output.as_deref(), "effective_config.json");
+        if let Some(parent) = config_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        config.to_file(&config_path.to_string_lossy())?;
+        info!("Effective configuration saved to {:?}", config_path);
+    }
     
-    // Initialize detector with specific classes
-    let mut detector = Detector::new(
-        &config.model_path,
-        &config.device,
-        (config.input_size[0] as i64, config.input_size[1] as i64),
-        config.conf_threshold,
-        config.nms_threshold,
-    )?;
+    info!("Using track threshold: {}", config.track_high_thresh);
+
+    // Cached detections replace the model entirely
+    let cached_detections = match &args.detections {
+        Some(path) => {
+            let cache = DetectionCache::load(&path.to_string_lossy())?;
+            info!("Loaded cached detections for {} frames from {:?}", cache.len(), path);
+            Some(cache)
+        }
+        None => None,
+    };
+
+    let detector = if cached_detections.is_some() {
+        None
+    } else {
+        info!("Initializing detector with weights from {:?}...", config.model_path);
+        info!("Using VERY LOW confidence threshold: {}", config.conf_threshold);
+
+        // Initialize detector with specific classes
+        let mut detector = Detector::from_config(&config)?;
+        if args.half {
+            detector.set_half(true);
+        }
+
+        // The command line overrides the configured class list
+        let classes = match &args.classes {
+            Some(list) => parse_class_list(list)?,
+            None => config.classes.clone(),
+        };
+        let names: Vec<String> = classes.iter().map(|&id| visualization::class_label(id)).collect();
+        info!("Detector will only consider classes: {:?}", classes);
+        info!("These correspond to: {}", names.join(", "));
+        detector.set_classes(classes);
+        detector.warmup(WARMUP_ITERS)?;
+        Some(detector)
+    };
     
-    // Set allowed classes to match Python implementation
-    detector.set_classes(vec![0, 1, 2, 3, 5, 7, 15, 16]);
-    println!("Detector will only consider classes: [0, 1, 2, 3, 5, 7, 15, 16]");
-    println!("These correspond to: person, bicycle, car, motorcycle, bus, truck, cat, dog");
+    // Load the region of interest, if any
+    let roi_filter = match &args.roi {
+        Some(path) => Some(RoiFilter::from_file(&path.to_string_lossy())?),
+        None => None,
+    };
+    let mask_filter = match &args.mask {
+        Some(path) => Some(MaskFilter::from_file(&path.to_string_lossy())?),
+        None => None,
+    };
+
+    // Lens undistortion applied to every frame before detection, if calibrated
+    let mut undistorter = match &args.calibration {
+        Some(path) => Some(Undistorter::from_file(&path.to_string_lossy())?),
+        None => None,
+    };
     
     // Initialize tracker (passing FPS for motion model)
-    let tracker = SMILEtrack::new(&config, args.fps as f32);
+    // Image inputs have no frame rate of their own; video re-resolves it from the source
+    let fps = args.fps.unwrap_or(DEFAULT_FPS);
+    let tracker = (!args.no_track).then(|| SMILEtrack::new(&config, fps as f32));
     
-    // Check if input is an image or video
-    let is_image = match args.input.extension().and_then(|e| e.to_str()) {
-        Some(ext) => matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "bmp"),
-        None => false,
-    };
+    // Check what kind of source the input names
+    let source = InputSource::classify(&input);
     
     // Open input source
-    println!("Opening input file {:?}...", args.input);
+    info!("Opening input {:?}...", source);
     
-    // Handle single image input
-    if is_image {
-        println!("Processing single image input...");
-        let frame = imgcodecs::imread(&args.input.to_string_lossy(), imgcodecs::IMREAD_COLOR)?;
+    // Handle a directory of images
+    if let InputSource::Directory(_) = source {
+        info!("Processing image directory in {:?} mode...", args.batch_mode);
+        let images = list_images(&input)?;
+        if images.is_empty() {
+            return Err(anyhow::anyhow!("No images found in {:?}", input).into());
+        }
+
+        // Output is always a directory of visualized frames
+        let vis_output_dir = args.output.clone();
+        if let Some(output_dir) = &vis_output_dir {
+            fs::create_dir_all(output_dir)?;
+        }
+
+        if args.visualize {
+            highgui::named_window("SMILEtrack", highgui::WINDOW_NORMAL)?;
+        }
+
+        // Sequence mode keeps one log for the whole run
+        let tracking_log_path = if args.output_stdout || args.batch_mode == BatchMode::Independent {
+            None
+        } else if let Some(output_dir) = &vis_output_dir {
+            Some(output_dir.join(log_name))
+        } else {
+            Some(PathBuf::from(log_name))
+        };
+
+        let log_dir = vis_output_dir.clone();
+        let mut processing_state = ProcessingState {
+            detector,
+            tracker,
+            writer: None,
+            annotations: Vec::new(),
+            annotation_path: None,
+            vis_output_dir,
+            window_name: "SMILEtrack".to_string(),
+            show_visualization: args.visualize,
+            show_detections: args.no_track || args.batch_mode == BatchMode::Independent,
+            verbose_labels: !args.short_labels,
+            confidence_bar: args.confidence_bar,
+            detection_stride: args.detection_stride,
+            heatmap: args.heatmap.then(|| (Mat::default(), output_sibling(args.output.as_deref(), "heatmap.png"))),
+            show_heatmap: true,
+            fps_meter: FpsMeter::new(FPS_WINDOW),
+            paused: false,
+            cached_detections: cached_detections.clone(),
+            detection_log: args.save_detections.clone().map(|path| (DetectionCache::new(), path)),
+            montage: args.montage.map(|n| (Vec::new(), n, output_sibling(args.output.as_deref(), "montage.png"))),
+            log_innovation: args.log_innovation,
+            log_writer: TrackingLogWriter::open(tracking_log_path, args.output_stdout, args.pretty_log)?
+                .with_gap_filling(args.interpolate_gaps),
+            roi_filter,
+            mask_filter,
+            profiler: Profiler::new(),
+            detect_timings: Timings::default(),
+            stage_timings: RollingTimings::new(STAGE_TIMING_WINDOW),
+            track_counts: args.counts_csv.clone().map(|path| (TrackCountSeries::new(), path, args.counts_per_class)),
+            subtitles: args.subtitles.clone().map(|path| (TrackSubtitles::new(fps), path)),
+            csv_writer: args.csv.as_ref().map(CsvWriter::create).transpose()?,
+            trajectories: args.trajectories.clone().map(|path| (TrajectoryExporter::new(), path)),
+            coco_results: args.coco_output.clone().map(|path| (Vec::new(), Vec::new(), path)),
+            finalized: false,
+        };
+
+        let plan = plan_batch(images, args.batch_mode, log_dir.as_deref(), log_ext);
+        let total = plan.len();
+        for (i, item) in plan.into_iter().enumerate() {
+            if item.reset_tracker && processing_state.tracker.is_some() {
+                processing_state.tracker = Some(SMILEtrack::new(&config, fps as f32));
+            }
+            if let Some(log_path) = item.log_path.filter(|_| !args.output_stdout) {
+                processing_state.log_writer.finalize()?;
+                processing_state.log_writer = TrackingLogWriter::open(Some(log_path), false, args.pretty_log)?
+                    .with_gap_filling(args.interpolate_gaps);
+            }
+
+            let mut frame = imgcodecs::imread(&item.path.to_string_lossy(), imgcodecs::IMREAD_COLOR)?;
+            if frame.empty() {
+                warn!("failed to load image {:?}, skipping", item.path);
+                continue;
+            }
+            if let Some(undistorter) = &mut undistorter {
+                frame = undistorter.undistort(&frame)?;
+            }
+
+            let frame_path = item.path.to_string_lossy().to_string();
+            if !processing_state.process_frame(&frame, Some(&frame_path), item.frame_id)? {
+                break;
+            }
+
+            if (i + 1) % 10 == 0 {
+                info!("Processed {}/{} images", i + 1, total);
+            }
+        }
+
+        processing_state.finalize()?;
+        info!("Directory processing completed!");
+    } else if let InputSource::Image(_) = source {
+        info!("Processing single image input...");
+        let mut frame = imgcodecs::imread(&input.to_string_lossy(), imgcodecs::IMREAD_COLOR)?;
         if frame.empty() {
-            return Err(anyhow::anyhow!("Failed to load image: {:?}", args.input).into());
+            return Err(anyhow::anyhow!("Failed to load image: {:?}", input).into());
+        }
+        if let Some(undistorter) = &mut undistorter {
+            frame = undistorter.undistort(&frame)?;
         }
         
         // Create visualization window if needed
@@ -411,19 +1428,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         
         // For single image input, determine if we're outputting directly to a file or to a directory
         let (vis_output_dir, direct_output_file) = if let Some(output_path) = &args.output {
-            let is_image_extension = match output_path.extension().and_then(|e| e.to_str()) {
-                Some(ext) => matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "bmp"),
-                None => false,
-            };
-            
-            if is_image_extension {
+            if is_image_path(output_path) {
                 // Direct file output - we'll save directly to this file
                 (None, Some(output_path.clone()))
             } else {
                 // Directory output - we'll save to a file in this directory
                 // Ensure directory exists
                 if !output_path.exists() || !output_path.is_dir() {
-                    println!("Creating output directory: {:?}", output_path);
+                    info!("Creating output directory: {:?}", output_path);
                     fs::create_dir_all(output_path)?;
                 }
                 (Some(output_path.clone()), None)
@@ -433,12 +1445,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         };
         
         // Setup tracking log path
-        let tracking_log_path = if let Some(output_dir) = &vis_output_dir {
-            Some(output_dir.join("tracking_details.json"))
+        let tracking_log_path = if args.output_stdout {
+            None
+        } else if let Some(output_dir) = &vis_output_dir {
+            Some(output_dir.join(log_name))
         } else if let Some(parent) = direct_output_file.as_ref().and_then(|p| p.parent()) {
-            Some(parent.join("tracking_details.json"))
+            Some(parent.join(log_name))
         } else {
-            Some(PathBuf::from("tracking_details.json"))
+            Some(PathBuf::from(log_name))
         };
         
         // Create processing state
@@ -452,31 +1466,54 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             window_name: "SMILEtrack".to_string(),
             show_visualization: args.visualize,
             show_detections: true, // Show detections for images
-            tracking_log: Vec::new(),
-            tracking_log_path,
+            verbose_labels: !args.short_labels,
+            confidence_bar: args.confidence_bar,
+            detection_stride: args.detection_stride,
+            heatmap: args.heatmap.then(|| (Mat::default(), output_sibling(args.output.as_deref(), "heatmap.png"))),
+            show_heatmap: true,
+            fps_meter: FpsMeter::new(FPS_WINDOW),
+            paused: false,
+            cached_detections: cached_detections.clone(),
+            detection_log: args.save_detections.clone().map(|path| (DetectionCache::new(), path)),
+            montage: args.montage.map(|n| (Vec::new(), n, output_sibling(args.output.as_deref(), "montage.png"))),
+            log_innovation: args.log_innovation,
+            log_writer: TrackingLogWriter::open(tracking_log_path, args.output_stdout, args.pretty_log)?
+                .with_gap_filling(args.interpolate_gaps),
+            roi_filter,
+            mask_filter,
+            profiler: Profiler::new(),
+            detect_timings: Timings::default(),
+            stage_timings: RollingTimings::new(STAGE_TIMING_WINDOW),
+            track_counts: args.counts_csv.clone().map(|path| (TrackCountSeries::new(), path, args.counts_per_class)),
+            subtitles: args.subtitles.clone().map(|path| (TrackSubtitles::new(fps), path)),
+            csv_writer: args.csv.as_ref().map(CsvWriter::create).transpose()?,
+            trajectories: args.trajectories.clone().map(|path| (TrajectoryExporter::new(), path)),
+            coco_results: args.coco_output.clone().map(|path| (Vec::new(), Vec::new(), path)),
+            finalized: false,
         };
         
         // Process the single image frame
-        let frame_path = args.input.to_string_lossy().to_string();
-        processing_state.process_frame(&frame, Some(&frame_path), 0, args.fps)?;
+        let frame_path = input.to_string_lossy().to_string();
+        processing_state.process_frame(&frame, Some(&frame_path), 0)?;
         
         // If direct output file is specified, save the result directly
         if let Some(direct_output_path) = direct_output_file {
-            println!("Saving final result to {:?}", direct_output_path);
+            info!("Saving final result to {:?}", direct_output_path);
             
             // Create a visualization with tracking results
             let mut output_frame = frame.clone();
+            let style = VisStyle::for_resolution(frame.cols(), frame.rows());
             
             // Draw detections and tracks
-            let detections = processing_state.detector.detect(&frame)?;
-            visualization::draw_detections(&mut output_frame, &detections)?;
+            let detections = processing_state.detect(&frame, 0)?;
+            visualization::draw_detections(&mut output_frame, &detections, args.confidence_bar, &style)?;
             
-            let tracks = processing_state.tracker.tracks();
-            let activated_tracks: Vec<STrack> = tracks.iter()
+            let activated_tracks: Vec<STrack> = processing_state.tracker.iter()
+                .flat_map(|tracker| tracker.tracks())
                 .filter(|t| t.is_activated())
                 .cloned()
                 .collect();
-            visualization::draw_tracks(&mut output_frame, &activated_tracks)?;
+            visualization::draw_tracks(&mut output_frame, &activated_tracks, !args.short_labels, args.confidence_bar, &style)?;
             
             // Ensure parent directory exists
             if let Some(parent) = direct_output_path.parent() {
@@ -493,29 +1530,41 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             )?;
         }
         
+        processing_state.finalize()?;
+        
         // Wait for key press if showing visualization
         if args.visualize {
-            println!("Press any key to exit...");
+            eprintln!("Press any key to exit...");
             highgui::wait_key(0)?;
         }
     } else {
-        // Handle video input
-        println!("Processing video input...");
-        let mut cap = VideoCapture::from_file(&args.input.to_string_lossy(), videoio::CAP_ANY)?;
+        // Handle video, camera or stream input
+        info!("Processing video input...");
+        let mut cap = match &source {
+            InputSource::Camera(index) => VideoCapture::new(*index, CAP_ANY)?,
+            InputSource::Stream(url) => VideoCapture::from_file(url, CAP_ANY)?,
+            _ => VideoCapture::from_file(&input.to_string_lossy(), CAP_ANY)?,
+        };
         if !cap.is_opened()? {
-            return Err(anyhow::anyhow!("Failed to open video file: {:?}", args.input).into());
+            return Err(anyhow::anyhow!("Failed to open input: {:?}", input).into());
         }
         
         // Get video properties
         let width = cap.get(videoio::CAP_PROP_FRAME_WIDTH)? as i32;
         let height = cap.get(videoio::CAP_PROP_FRAME_HEIGHT)? as i32;
         let total_frames = cap.get(videoio::CAP_PROP_FRAME_COUNT)? as i32;
-        let fps = cap.get(videoio::CAP_PROP_FPS)?;
+        let fps = resolve_fps(args.fps, cap.get(videoio::CAP_PROP_FPS)?);
         
-        println!("Video properties:");
-        println!("  Resolution: {}x{}", width, height);
-        println!("  Total frames: {}", total_frames);
-        println!("  FPS: {:.2}", fps);
+        // Live sources report no meaningful length and often no frame rate
+        let total_frames = if source.is_live() { None } else { Some(total_frames) };
+        
+        info!("Video properties:");
+        info!("  Resolution: {}x{}", width, height);
+        match total_frames {
+            Some(total) => info!("  Total frames: {}", total),
+            None => info!("  Total frames: live"),
+        }
+        info!("  FPS: {:.2}", fps);
         
         // Setup output writer
         let mut video_writer: Option<VideoWriter> = None;
@@ -527,23 +1576,29 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 fs::create_dir_all(parent)?;
             }
             
-            if output_path.extension().and_then(|e| e.to_str()) == Some("mp4") {
+            if matches!(output_path.extension().and_then(|e| e.to_str()), Some("mp4" | "avi")) {
                 // Video output
-                println!("Setting up video writer to {:?}", output_path);
-                let fourcc = VideoWriter::fourcc('a', 'v', 'c', '1')?;
-                let video_writer_obj = VideoWriter::new(
-                    &output_path.to_string_lossy(),
-                    fourcc,
-                    args.fps,
-                    Size::new(width, height),
-                    true,
-                )?;
+                info!("Setting up video writer to {:?} at {:.2} fps", output_path, fps);
+                for codec in args.codec.with_fallbacks() {
+                    let writer = VideoWriter::new(
+                        &output_path.to_string_lossy(),
+                        codec.fourcc()?,
+                        fps,
+                        Size::new(width, height),
+                        true,
+                    )?;
+                    if writer.is_opened()? {
+                        if codec != args.codec {
+                            warn!("Codec {:?} unavailable, writing with {:?}", args.codec, codec);
+                        }
+                        video_writer = Some(writer);
+                        break;
+                    }
+                }
                 
-                if !video_writer_obj.is_opened()? {
-                    println!("Warning: Failed to open video writer, falling back to image sequence");
+                if video_writer.is_none() {
+                    warn!("Failed to open video writer with any codec, falling back to image sequence");
                     vis_output_dir = Some(output_path.clone());
-                } else {
-                    video_writer = Some(video_writer_obj);
                 }
             } else {
                 // Directory output for frame sequence
@@ -561,71 +1616,543 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         
         // Setup tracking log path
-        let tracking_log_path = if let Some(output_dir) = &vis_output_dir {
-            Some(output_dir.join("tracking_details.json"))
+        let tracking_log_path = if args.output_stdout {
+            None
+        } else if let Some(output_dir) = &vis_output_dir {
+            Some(output_dir.join(log_name))
         } else {
-            Some(PathBuf::from("tracking_details.json"))
+            Some(PathBuf::from(log_name))
         };
         
         // Create processing state
         let mut processing_state = ProcessingState {
             detector,
-            tracker,
+            tracker: (!args.no_track).then(|| SMILEtrack::new(&config, fps as f32)),
             writer: video_writer,
             annotations: Vec::new(),
             annotation_path: None,
             vis_output_dir,
             window_name: "SMILEtrack".to_string(),
             show_visualization: args.visualize,
-            show_detections: false, // Don't show detections for videos by default
-            tracking_log: Vec::new(),
-            tracking_log_path,
+            show_detections: args.no_track, // Don't show detections for videos unless they are all there is
+            verbose_labels: !args.short_labels,
+            confidence_bar: args.confidence_bar,
+            detection_stride: args.detection_stride,
+            heatmap: args.heatmap.then(|| (Mat::default(), output_sibling(args.output.as_deref(), "heatmap.png"))),
+            show_heatmap: true,
+            fps_meter: FpsMeter::new(FPS_WINDOW),
+            paused: false,
+            cached_detections: cached_detections.clone(),
+            detection_log: args.save_detections.clone().map(|path| (DetectionCache::new(), path)),
+            montage: args.montage.map(|n| (Vec::new(), n, output_sibling(args.output.as_deref(), "montage.png"))),
+            log_innovation: args.log_innovation,
+            log_writer: TrackingLogWriter::open(tracking_log_path, args.output_stdout, args.pretty_log)?
+                .with_gap_filling(args.interpolate_gaps),
+            roi_filter,
+            mask_filter,
+            profiler: Profiler::new(),
+            detect_timings: Timings::default(),
+            stage_timings: RollingTimings::new(STAGE_TIMING_WINDOW),
+            track_counts: args.counts_csv.clone().map(|path| (TrackCountSeries::new(), path, args.counts_per_class)),
+            subtitles: args.subtitles.clone().map(|path| (TrackSubtitles::new(fps), path)),
+            csv_writer: args.csv.as_ref().map(CsvWriter::create).transpose()?,
+            trajectories: args.trajectories.clone().map(|path| (TrajectoryExporter::new(), path)),
+            coco_results: args.coco_output.clone().map(|path| (Vec::new(), Vec::new(), path)),
+            finalized: false,
         };
         
         // Process frames
-        let mut frame = Mat::default();
         let mut frame_id = 0;
-        
-        while cap.read(&mut frame)? {
-            if frame.empty() {
-                break;
+        let is_live = source.is_live();
+
+        // A fresh Mat per frame, so frames handed to the pipelined consumer are never overwritten
+        let mut read_frame = move || -> anyhow::Result<Option<Mat>> {
+            let frame = read_good_frame(&mut |frame: &mut Mat| cap.read(frame), is_live)?;
+            match (frame, &mut undistorter) {
+                (Some(frame), Some(undistorter)) => Ok(Some(undistorter.undistort(&frame)?)),
+                (frame, _) => Ok(frame),
             }
-            
-            // Process frame
-            if !processing_state.process_frame(&frame, None, frame_id, fps)? {
-                // Processing was interrupted by user
-                break;
+        };
+
+        // Print progress
+        let report_progress = |processed: i32| {
+            if processed % 10 == 0 {
+                match total_frames {
+                    Some(total) => info!("Processed {}/{} frames", processed, total),
+                    None => info!("Processed {} frames", processed),
+                }
             }
-            
-            frame_id += 1;
-            
-            // Print progress
-            if frame_id % 10 == 0 {
-                println!("Processed {}/{} frames", frame_id, total_frames);
+        };
+
+        // With cached detections there is no model work to overlap
+        if args.pipelined && processing_state.detector.is_some() {
+            let detector = processing_state.detector.take().ok_or("detector not available")?;
+            let stride = args.detection_stride;
+            let mut next_id = 0;
+            frame_id = run_pipelined(
+                read_frame,
+                move |frame| {
+                    let detect_now = is_detection_frame(next_id, stride);
+                    next_id += 1;
+                    if detect_now { detector.detect_timed(frame) } else { Ok((Vec::new(), Timings::default())) }
+                },
+                |id, frame, detections, timings| {
+                    processing_state.detect_timings = timings;
+                    let keep_going = processing_state.process_detections(frame, detections, None, id)?;
+                    report_progress(id + 1);
+                    Ok(keep_going)
+                },
+            )?;
+        } else {
+            while let Some(frame) = read_frame()? {
+                // Process frame
+                if !processing_state.process_frame(&frame, None, frame_id)? {
+                    // Processing was interrupted by user
+                    break;
+                }
+                frame_id += 1;
+                report_progress(frame_id);
             }
         }
         
-        println!("\nVideo processing completed!");
-        println!("Processed {} frames", frame_id);
+        processing_state.finalize()?;
+        
+        info!("Video processing completed!");
+        info!("Processed {} frames", frame_id);
     }
     
     // Get the tracking log path from the command line arguments instead of processing_state
-    println!("Tracking completed successfully");
+    info!("Tracking completed successfully");
     if let Some(output_path) = &args.output {
         let log_path = if output_path.is_dir() {
-            output_path.join("tracking_details.json")
+            output_path.join(log_name)
         } else if let Some(parent) = output_path.parent() {
-            parent.join("tracking_details.json")
+            parent.join(log_name)
         } else {
-            PathBuf::from("tracking_details.json")
+            PathBuf::from(log_name)
         };
         
         if log_path.exists() {
-            println!("Tracking details saved to {:?}", log_path);
+            info!("Tracking details saved to {:?}", log_path);
         }
-    } else {
-        println!("Tracking details saved to tracking_details.json");
+    } else if !args.output_stdout {
+        info!("Tracking details saved to {}", log_name);
     }
     
     Ok(())
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_log(frame_id: i32) -> FrameLog {
+        FrameLog {
+            frame_id,
+            detections: vec![DetectionLog {
+                bbox: vec![10.0, 20.0, 30.0, 40.0],
+                confidence: 0.9,
+                class_id: 0,
+                class_name: "person".to_string(),
+                source: None,
+            }],
+            tracks: vec![TrackLog {
+                track_id: 1,
+                bbox: vec![10.0, 20.0, 30.0, 40.0],
+                confidence: 0.9,
+                class_id: 0,
+                class_name: "person".to_string(),
+                source: None,
+                innovation: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_finalize_writes_all_frames() {
+        let path = std::env::temp_dir().join("smiletrack_test_finalize.json");
+        let mut writer = TrackingLogWriter::new(Some(path.clone()), None);
+        for frame_id in 0..5 {
+            writer.write_frame(frame_log(frame_id)).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let data = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let frames: Vec<FrameLog> = serde_json::from_str(&data).unwrap();
+        let ids: Vec<i32> = frames.iter().map(|f| f.frame_id).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_independent_batch_resets_tracker_per_image() {
+        let dir = std::env::temp_dir().join("smiletrack_test_batch_independent");
+        fs::create_dir_all(&dir).unwrap();
+        let blank = Mat::new_size_with_default(
+            Size::new(320, 240),
+            opencv::core::CV_8UC3,
+            opencv::core::Scalar::all(0.0),
+        ).unwrap();
+        for name in ["b.jpg", "a.jpg"] {
+            imgcodecs::imwrite(&dir.join(name).to_string_lossy(), &blank, &opencv::core::Vector::new()).unwrap();
+        }
+
+        let images = list_images(&dir).unwrap();
+        let plan = plan_batch(images, BatchMode::Independent, Some(&dir), "jsonl");
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(plan.len(), 2);
+        assert!(plan.iter().all(|item| item.reset_tracker && item.frame_id == 1));
+        assert_eq!(plan[0].log_path, Some(dir.join("a_tracking.jsonl")));
+        assert_eq!(plan[1].log_path, Some(dir.join("b_tracking.jsonl")));
+
+        // The same object in both images gets the first ID each time
+        let config = Config::from_file("config.json").unwrap();
+        let det = Detection::new(nalgebra::SVector::<f32, 4>::new(50.0, 50.0, 40.0, 80.0), 0.9, 0, None);
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+        for item in &plan {
+            if item.reset_tracker {
+                tracker = SMILEtrack::new(&config, 30.0);
+            }
+            tracker.update(&[det.clone()], &blank, item.frame_id).unwrap();
+            assert_eq!(tracker.tracks().len(), 1);
+            assert_eq!(tracker.tracks()[0].track_id(), 1);
+        }
+    }
+
+    #[test]
+    fn test_sequence_dir_processes_frames_in_ascending_order() {
+        let dir = std::env::temp_dir().join("smiletrack_test_sequence_dir");
+        fs::create_dir_all(&dir).unwrap();
+        let blank = Mat::new_size_with_default(
+            Size::new(64, 48),
+            opencv::core::CV_8UC3,
+            opencv::core::Scalar::all(0.0),
+        ).unwrap();
+        // Unpadded names would sort 1, 10, 2, 9 lexicographically
+        for name in ["10.jpg", "2.jpg", "1.jpg", "9.jpg", "notes.txt"] {
+            let path = dir.join(name);
+            if is_image_path(&path) {
+                imgcodecs::imwrite(&path.to_string_lossy(), &blank, &opencv::core::Vector::new()).unwrap();
+            } else {
+                fs::write(&path, "not a frame").unwrap();
+            }
+        }
+
+        let plan = plan_batch(list_images(&dir).unwrap(), BatchMode::Sequence, Some(&dir), "jsonl");
+        fs::remove_dir_all(&dir).ok();
+
+        let names: Vec<String> = plan.iter()
+            .map(|item| item.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["1.jpg", "2.jpg", "9.jpg", "10.jpg"]);
+        let ids: Vec<i32> = plan.iter().map(|item| item.frame_id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        assert!(plan.iter().all(|item| !item.reset_tracker && item.log_path.is_none()));
+    }
+
+    #[test]
+    fn test_natural_cmp_orders_mot_frame_names() {
+        use std::cmp::Ordering;
+        assert_eq!(natural_cmp("000009.jpg", "000010.jpg"), Ordering::Less);
+        assert_eq!(natural_cmp("frame_2.png", "frame_10.png"), Ordering::Less);
+        assert_eq!(natural_cmp("b1.jpg", "a2.jpg"), Ordering::Greater);
+        assert_eq!(natural_cmp("007.jpg", "7.jpg"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_output_sibling() {
+        assert_eq!(output_sibling(None, "c.json"), PathBuf::from("c.json"));
+        assert_eq!(output_sibling(Some(Path::new("runs/out")), "c.json"), PathBuf::from("runs/out/c.json"));
+        assert_eq!(output_sibling(Some(Path::new("runs/out.mp4")), "c.json"), PathBuf::from("runs/c.json"));
+    }
+
+    #[test]
+    fn test_classify_input_source() {
+        let dir = std::env::temp_dir();
+        assert_eq!(InputSource::classify(Path::new("0")), InputSource::Camera(0));
+        assert_eq!(InputSource::classify(Path::new("2")), InputSource::Camera(2));
+        assert_eq!(
+            InputSource::classify(Path::new("rtsp://192.168.1.10:554/stream")),
+            InputSource::Stream("rtsp://192.168.1.10:554/stream".to_string())
+        );
+        assert_eq!(
+            InputSource::classify(Path::new("HTTP://cam.local/video.mjpg")),
+            InputSource::Stream("HTTP://cam.local/video.mjpg".to_string())
+        );
+        assert_eq!(InputSource::classify(&dir), InputSource::Directory(dir.clone()));
+        assert_eq!(InputSource::classify(Path::new("frame.JPG")), InputSource::Image(PathBuf::from("frame.JPG")));
+        assert_eq!(InputSource::classify(Path::new("clip.mp4")), InputSource::Video(PathBuf::from("clip.mp4")));
+
+        assert!(InputSource::Camera(0).is_live());
+        assert!(!InputSource::Video(PathBuf::from("clip.mp4")).is_live());
+    }
+
+    #[test]
+    fn test_jsonl_log_writes_one_line_per_frame() {
+        let path = std::env::temp_dir().join("smiletrack_test_log.jsonl");
+        let mut writer = TrackingLogWriter::open(Some(path.clone()), false, false).unwrap();
+        assert!(writer.is_enabled());
+        for frame_id in 0..7 {
+            writer.write_frame(frame_log(frame_id)).unwrap();
+        }
+        // Nothing is accumulated in memory for the append-mode file
+        assert!(writer.frames.is_empty());
+        writer.finalize().unwrap();
+        drop(writer);
+
+        let data = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let lines: Vec<&str> = data.lines().collect();
+        assert_eq!(lines.len(), 7);
+        for (i, line) in lines.iter().enumerate() {
+            let parsed: FrameLog = serde_json::from_str(line).unwrap();
+            assert_eq!(parsed.frame_id, i as i32);
+        }
+    }
+
+    #[test]
+    fn test_interpolate_gaps_fills_straight_line() {
+        let track = |x: f32, y: f32| TrackLog {
+            track_id: 7,
+            bbox: vec![x, y, 10.0, 20.0],
+            confidence: 0.8,
+            class_id: 0,
+            class_name: "person".to_string(),
+            source: None,
+            innovation: None,
+        };
+        let mut frames: Vec<FrameLog> = (0..6).map(|frame_id| FrameLog {
+            frame_id,
+            detections: Vec::new(),
+            tracks: Vec::new(),
+        }).collect();
+        frames[0].tracks.push(track(0.0, 0.0));
+        frames[4].tracks.push(track(40.0, 20.0));
+        frames[5].tracks.push(track(50.0, 25.0));
+
+        // A 3-frame gap is left alone when only 2 may be filled
+        let mut short = frames.clone();
+        interpolate_gaps(&mut short, 2);
+        assert!(short[1..4].iter().all(|f| f.tracks.is_empty()));
+
+        interpolate_gaps(&mut frames, 3);
+        for (frame_id, expected) in [(1, [10.0, 5.0]), (2, [20.0, 10.0]), (3, [30.0, 15.0])] {
+            let filled = &frames[frame_id].tracks;
+            assert_eq!(filled.len(), 1);
+            assert_eq!(filled[0].track_id, 7);
+            assert_eq!(filled[0].bbox, vec![expected[0], expected[1], 10.0, 20.0]);
+        }
+        assert_eq!(frames[5].tracks.len(), 1);
+    }
+
+    #[test]
+    fn test_empty_frame_mid_stream_is_skipped() {
+        // Frames 0..5 with an empty one after frame 2, then the end of the stream
+        let mut script = vec![Some(0.0), Some(1.0), Some(2.0), None, Some(3.0), Some(4.0)].into_iter();
+        let mut read = |frame: &mut Mat| -> opencv::Result<bool> {
+            match script.next() {
+                Some(Some(value)) => {
+                    *frame = Mat::new_size_with_default(Size::new(8, 8), opencv::core::CV_8UC1, opencv::core::Scalar::all(value))?;
+                    Ok(true)
+                }
+                Some(None) => Ok(true),
+                None => Ok(false),
+            }
+        };
+
+        let mut seen = Vec::new();
+        while let Some(frame) = read_good_frame(&mut read, false).unwrap() {
+            seen.push(*frame.at_2d::<u8>(0, 0).unwrap());
+        }
+        assert_eq!(seen, vec![0, 1, 2, 3, 4]);
+
+        // A source that only yields empty frames gives up instead of spinning
+        let mut reads = 0;
+        let mut dead = |_: &mut Mat| -> opencv::Result<bool> {
+            reads += 1;
+            Ok(true)
+        };
+        assert!(read_good_frame(&mut dead, false).unwrap().is_none());
+        assert_eq!(reads, MAX_EMPTY_FRAMES + 1);
+    }
+
+    #[test]
+    fn test_pipelined_frames_arrive_in_order() {
+        // Each frame is filled with its index and the mock detector reports that index as
+        // the class, so a mismatch shows up as either out of order or corrupted frames
+        let mut next = 0;
+        let read = move || -> anyhow::Result<Option<Mat>> {
+            if next == 25 {
+                return Ok(None);
+            }
+            let frame = Mat::new_size_with_default(
+                Size::new(8, 8),
+                opencv::core::CV_8UC1,
+                opencv::core::Scalar::all(next as f64),
+            )?;
+            next += 1;
+            Ok(Some(frame))
+        };
+        let detect = |frame: &Mat| -> anyhow::Result<(Vec<Detection>, Timings)> {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            let value = *frame.at_2d::<u8>(0, 0)? as i32;
+            let detections = vec![Detection::new(nalgebra::SVector::<f32, 4>::new(0.0, 0.0, 4.0, 4.0), 0.9, value, None)];
+            Ok((detections, Timings { inference_ms: value as f64, ..Timings::default() }))
+        };
+
+        let mut seen = Vec::new();
+        let consumed = run_pipelined(read, detect, |frame_id, frame, detections, timings| {
+            assert_eq!(*frame.at_2d::<u8>(0, 0)? as i32, frame_id);
+            // Stage times travel with their frame's detections
+            assert_eq!(timings.inference_ms, frame_id as f64);
+            seen.push(detections[0].class_id);
+            Ok(true)
+        }).unwrap();
+
+        assert_eq!(consumed, 25);
+        assert_eq!(seen, (0..25).collect::<Vec<_>>());
+
+        // Stopping early returns without draining the reader
+        let read = || -> anyhow::Result<Option<Mat>> { Ok(Some(Mat::default())) };
+        let consumed = run_pipelined(read, |_: &Mat| Ok((Vec::new(), Timings::default())), |frame_id, _, _, _| Ok(frame_id < 2)).unwrap();
+        assert_eq!(consumed, 2);
+    }
+
+    #[test]
+    fn test_detection_stride_halves_detector_calls() {
+        let frame = Mat::new_size_with_default(
+            Size::new(640, 480),
+            opencv::core::CV_8UC3,
+            opencv::core::Scalar::all(0.0),
+        ).unwrap();
+        let config = Config::from_file("config.json").unwrap();
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+
+        // Mock detector: a person walking right 5 px per frame
+        let mut calls = 0;
+        let mut detect = |frame_id: i32| {
+            calls += 1;
+            let x = 100.0 + 5.0 * frame_id as f32;
+            vec![Detection::new(nalgebra::SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None)]
+        };
+
+        let mut xs = Vec::new();
+        for frame_id in 0..20 {
+            let detections = if is_detection_frame(frame_id, 2) { detect(frame_id) } else { Vec::new() };
+            step_tracker(&mut tracker, &detections, &frame, frame_id, 2).unwrap();
+            if let Some(track) = tracker.tracks().iter().find(|t| t.is_activated()) {
+                xs.push((frame_id, track.track_id(), track.tlwh()[0]));
+            }
+        }
+
+        assert_eq!(calls, 10);
+        // One track throughout, still moving on the skipped (odd) frames
+        assert!(xs.iter().all(|&(_, id, _)| id == xs[0].1));
+        let late: Vec<_> = xs.iter().filter(|&&(frame_id, _, _)| frame_id >= 10).collect();
+        assert!(late.windows(2).all(|w| w[1].2 > w[0].2), "{:?}", late);
+    }
+
+    #[test]
+    fn test_no_track_logs_detections_without_tracks() {
+        let frame = Mat::new_size_with_default(
+            Size::new(640, 480),
+            opencv::core::CV_8UC3,
+            opencv::core::Scalar::all(0.0),
+        ).unwrap();
+        let config = Config::from_file("config.json").unwrap();
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+
+        let mut tracked = Vec::new();
+        for frame_id in 0..10 {
+            let x = 100.0 + 5.0 * frame_id as f32;
+            let detections = vec![Detection::new(nalgebra::SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None)];
+
+            tracked = track_frame(Some(&mut tracker), &detections, &frame, frame_id, 1).unwrap();
+            let untracked = track_frame(None, &detections, &frame, frame_id, 1).unwrap();
+            assert!(untracked.is_empty());
+
+            let log = build_frame_log(frame_id, &detections, &untracked, false);
+            assert_eq!(log.detections.len(), 1);
+            assert!(log.tracks.is_empty());
+            let line = serde_json::to_string(&log).unwrap();
+            assert!(line.contains("\"tracks\":[]"), "{}", line);
+        }
+        // The same stream with a tracker does produce a track
+        assert_eq!(tracked.len(), 1);
+    }
+
+    #[test]
+    fn test_coco_output_per_frame() {
+        assert_eq!(coco_image_id(Some("val2017/000000000139.jpg"), 3), 139);
+        assert_eq!(coco_image_id(Some("frames/frame_a.jpg"), 3), 3);
+        assert_eq!(coco_image_id(None, 7), 7);
+
+        let detections = vec![
+            Detection::new(nalgebra::SVector::<f32, 4>::new(10.0, 20.0, 30.0, 40.0), 0.8, 2, None),
+            Detection::new(nalgebra::SVector::<f32, 4>::new(0.0, 0.0, 5.0, 5.0), 0.6, 99, None),
+        ];
+        let frames = vec![coco_frame(0, &detections), coco_frame(1, &[])];
+        let json: Vec<serde_json::Value> = serde_json::from_str(&to_coco_json(&frames, &[139, 140])).unwrap();
+
+        // Class 99 has no COCO category and the second frame has nothing to report
+        assert_eq!(json.len(), 1);
+        assert_eq!(json[0]["image_id"], 139);
+        assert_eq!(json[0]["category_id"], 3);
+        assert_eq!(json[0]["bbox"], serde_json::json!([10.0, 20.0, 30.0, 40.0]));
+    }
+
+    #[test]
+    fn test_fps_meter_rolling_window() {
+        let start = std::time::Instant::now();
+        let at = |ms: u64| start + std::time::Duration::from_millis(ms);
+        let mut meter = FpsMeter::new(std::time::Duration::from_secs(1));
+        assert_eq!(meter.fps(), 0.0);
+        meter.tick(at(0));
+        assert_eq!(meter.fps(), 0.0);
+
+        // 10 frames per second
+        for i in 1..=10 {
+            meter.tick(at(i * 100));
+        }
+        assert!((meter.fps() - 10.0).abs() < 1e-6, "fps {}", meter.fps());
+
+        // Slowing to 2 fps: after a full window only the slow frames remain
+        for i in 1..=4 {
+            meter.tick(at(1000 + i * 500));
+        }
+        assert!((meter.fps() - 2.0).abs() < 1e-6, "fps {}", meter.fps());
+    }
+
+    #[test]
+    fn test_resolve_fps() {
+        // An explicit --fps wins over the source
+        assert_eq!(resolve_fps(Some(15.0), 25.0), 15.0);
+        // Otherwise the source's rate is used when it reports one
+        assert_eq!(resolve_fps(None, 29.97), 29.97);
+        assert_eq!(resolve_fps(None, 0.0), DEFAULT_FPS);
+        assert_eq!(resolve_fps(None, f64::NAN), DEFAULT_FPS);
+    }
+
+    #[test]
+    fn test_codec_fallback_order() {
+        assert_eq!(Codec::Xvid.with_fallbacks(), vec![Codec::Xvid, Codec::Mp4v, Codec::Avc1]);
+    }
+
+    #[test]
+    fn test_stream_emits_one_json_line_per_frame() {
+        let mut out: Vec<u8> = Vec::new();
+        for frame_id in 0..3 {
+            write_frame_line(&mut out, &frame_log(frame_id)).unwrap();
+        }
+
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        for (i, line) in lines.iter().enumerate() {
+            let parsed: FrameLog = serde_json::from_str(line).unwrap();
+            assert_eq!(parsed.frame_id, i as i32);
+            assert_eq!(parsed.tracks.len(), 1);
+        }
+    }
+}
@@ -1,14 +1,41 @@
-use serde::Deserialize;
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
 use std::fs;
+use crate::tracker::{GmcFeatures, GmcMode};
 
-#[derive(Debug, Deserialize)]
+/// Tracker and detector settings.
+///
+/// Every field except `model_path` is optional in the JSON file; missing ones take the
+/// values from `Config::default()`:
+///
+/// | field | default |
+/// |---|---|
+/// | `track_high_thresh` / `track_low_thresh` / `new_track_thresh` | 0.5 / 0.1 / 0.6 |
+/// | `track_buffer` | 30 |
+/// | `proximity_thresh` / `appearance_thresh` | 0.5 / 0.25 |
+/// | `with_reid` | false |
+/// | `device` | "cpu" |
+/// | `input_size` | [640, 640] |
+/// | `conf_threshold` / `nms_threshold` | 0.25 / 0.45 |
+/// | `classes` | [0, 1, 2, 3, 5, 7, 15, 16] |
+/// | `n_init` | 3 |
+/// | `min_box_area` | 10 |
+/// | `reid_gallery_window` | 600 |
+/// | `duplicate_iou` | 0.7 |
+/// | `half` | false |
+/// | everything else | off / 0 / homography + optical flow GMC |
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    /// TorchScript detector weights (required)
     pub model_path: String,
     pub track_high_thresh: f32,
     pub track_low_thresh: f32,
     pub new_track_thresh: f32,
     pub track_buffer: usize,
+    /// Max IoU distance (1 - IoU) at which a pair may still be matched by appearance
     pub proximity_thresh: f32,
+    /// Max cosine distance for an appearance match
     pub appearance_thresh: f32,
     pub with_reid: bool,
     pub device: String,
@@ -16,14 +43,538 @@ pub struct Config {
     pub conf_threshold: f32,
     pub nms_threshold: f32,
     pub classes: Vec<i32>,
+    /// With `with_reid`, try to revive removed tracks by appearance before spawning new IDs
+    pub reid_removed: bool,
+    /// Camera motion compensation: "homography", "affine" or "none"
+    pub gmc_method: GmcMode,
+    /// Point correspondences for motion compensation: "opticalflow" or "orb"
+    pub gmc_features: GmcFeatures,
+    /// Hold lost tracks to their last observed aspect ratio while they coast on prediction
+    pub keep_aspect_on_coast: bool,
+    /// Scale each track's velocity process noise by how far its recent measurements
+    /// deviated from prediction
+    pub adaptive_process_noise: bool,
+    /// Consecutive matched frames before a new track is activated (drawn/reported)
+    pub n_init: u32,
+    /// On frames where no detection clears `track_high_thresh`, associate the best
+    /// detections above `track_low_thresh` so tracks survive brief quality dips
+    pub adaptive_thresh: bool,
+    /// Dense-crowd handling: stricter duplicate-track IoU and appearance check before merging
+    pub crowd_mode: bool,
+    /// Weight of the box-size dissimilarity term added to the IoU matching cost (0 disables)
+    pub size_weight: f32,
+    /// Two-frame initiation gate: a new track is only created when its detection overlaps
+    /// (IoU above this) a candidate detection from the previous frame; 0 disables it
+    pub init_iou: f32,
+    /// Suppress overlapping boxes across classes in NMS, keeping one box per object
+    pub class_agnostic_nms: bool,
+    /// Temperature `T` for detector score calibration, `sigmoid(logit(p) / T)` of the model's
+    /// objectness `p`; 1 leaves scores unchanged
+    pub score_temperature: f32,
+    /// Cap on libtorch and OpenCV worker threads; unset leaves each library's own default
+    pub num_threads: Option<usize>,
+    /// Detections smaller than this many square pixels (after scaling to the frame) are dropped
+    pub min_box_area: f32,
+    /// Detections larger than this many square pixels are dropped; unset keeps any size
+    pub max_box_area: Option<f32>,
+    /// Detections wider than this width/height ratio are dropped; unset keeps any shape
+    pub max_aspect_ratio: Option<f32>,
+    /// EMA weight of the previous reported box when a track is updated (0 reports the raw
+    /// Kalman posterior); only the reported `tlwh` is smoothed, not the filter state
+    pub box_smoothing_alpha: f32,
+    /// With `reid_removed`, frames a removed track's appearance stays in the re-ID gallery
+    pub reid_gallery_window: usize,
+    /// `SMILEtrack::tracks_filtered` hides tracks with a box this many square pixels or smaller
+    pub min_track_area: f32,
+    /// Perspective prior: detections, and tracks in `SMILEtrack::tracks_filtered`, whose
+    /// height contradicts their image row are dropped; unset disables it
+    pub size_prior: Option<crate::roi::SizePrior>,
+    /// Sort detections canonically before association so identical inputs in any order get
+    /// the same track IDs; meant for golden-file tests and reproducing bug reports
+    pub deterministic: bool,
+    /// IoU above which two tracks are treated as duplicates of one object and the younger is
+    /// dropped; crowd mode raises it to at least 0.85
+    pub duplicate_iou: f32,
+    /// Run the detector in fp16 (CUDA only; ignored with a warning on the CPU)
+    pub half: bool,
     // … other fields from config.json …
 }
 
+pub(crate) fn default_n_init() -> u32 {
+    3
+}
+
+pub(crate) fn default_proximity_thresh() -> f32 {
+    0.5
+}
+
+pub(crate) fn default_duplicate_iou() -> f32 {
+    0.7
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            model_path: String::new(),
+            track_high_thresh: 0.5,
+            track_low_thresh: 0.1,
+            new_track_thresh: 0.6,
+            track_buffer: 30,
+            proximity_thresh: default_proximity_thresh(),
+            appearance_thresh: 0.25,
+            with_reid: false,
+            device: String::from("cpu"),
+            input_size: [640, 640],
+            conf_threshold: 0.25,
+            nms_threshold: 0.45,
+            classes: vec![0, 1, 2, 3, 5, 7, 15, 16],
+            reid_removed: false,
+            gmc_method: GmcMode::default(),
+            gmc_features: GmcFeatures::default(),
+            keep_aspect_on_coast: false,
+            adaptive_process_noise: false,
+            n_init: default_n_init(),
+            adaptive_thresh: false,
+            crowd_mode: false,
+            size_weight: 0.0,
+            init_iou: 0.0,
+            class_agnostic_nms: false,
+            score_temperature: 1.0,
+            num_threads: None,
+            min_box_area: 10.0,
+            max_box_area: None,
+            max_aspect_ratio: None,
+            box_smoothing_alpha: 0.0,
+            reid_gallery_window: 600,
+            min_track_area: 0.0,
+            size_prior: None,
+            deterministic: false,
+            duplicate_iou: default_duplicate_iou(),
+            half: false,
+        }
+    }
+}
+
+/// Programmatic construction of a `Config`, starting from the defaults.
+///
+/// ```ignore
+/// let config = ConfigBuilder::new("weights/yolov7.torchscript")
+///     .track_high_thresh(0.6)
+///     .with_reid(true)
+///     .build()?;
+/// ```
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn new(model_path: &str) -> Self {
+        ConfigBuilder {
+            config: Config {
+                model_path: model_path.to_string(),
+                ..Config::default()
+            },
+        }
+    }
+
+    pub fn track_high_thresh(mut self, value: f32) -> Self {
+        self.config.track_high_thresh = value;
+        self
+    }
+
+    pub fn track_low_thresh(mut self, value: f32) -> Self {
+        self.config.track_low_thresh = value;
+        self
+    }
+
+    pub fn new_track_thresh(mut self, value: f32) -> Self {
+        self.config.new_track_thresh = value;
+        self
+    }
+
+    pub fn track_buffer(mut self, value: usize) -> Self {
+        self.config.track_buffer = value;
+        self
+    }
+
+    pub fn proximity_thresh(mut self, value: f32) -> Self {
+        self.config.proximity_thresh = value;
+        self
+    }
+
+    pub fn appearance_thresh(mut self, value: f32) -> Self {
+        self.config.appearance_thresh = value;
+        self
+    }
+
+    pub fn with_reid(mut self, value: bool) -> Self {
+        self.config.with_reid = value;
+        self
+    }
+
+    pub fn device(mut self, value: &str) -> Self {
+        self.config.device = value.to_string();
+        self
+    }
+
+    pub fn input_size(mut self, width: i32, height: i32) -> Self {
+        self.config.input_size = [width, height];
+        self
+    }
+
+    pub fn conf_threshold(mut self, value: f32) -> Self {
+        self.config.conf_threshold = value;
+        self
+    }
+
+    pub fn nms_threshold(mut self, value: f32) -> Self {
+        self.config.nms_threshold = value;
+        self
+    }
+
+    pub fn classes(mut self, value: Vec<i32>) -> Self {
+        self.config.classes = value;
+        self
+    }
+
+    pub fn reid_removed(mut self, value: bool) -> Self {
+        self.config.reid_removed = value;
+        self
+    }
+
+    pub fn gmc_method(mut self, value: GmcMode) -> Self {
+        self.config.gmc_method = value;
+        self
+    }
+
+    pub fn gmc_features(mut self, value: GmcFeatures) -> Self {
+        self.config.gmc_features = value;
+        self
+    }
+
+    pub fn keep_aspect_on_coast(mut self, value: bool) -> Self {
+        self.config.keep_aspect_on_coast = value;
+        self
+    }
+
+    pub fn adaptive_process_noise(mut self, value: bool) -> Self {
+        self.config.adaptive_process_noise = value;
+        self
+    }
+
+    pub fn n_init(mut self, value: u32) -> Self {
+        self.config.n_init = value;
+        self
+    }
+
+    pub fn adaptive_thresh(mut self, value: bool) -> Self {
+        self.config.adaptive_thresh = value;
+        self
+    }
+
+    pub fn crowd_mode(mut self, value: bool) -> Self {
+        self.config.crowd_mode = value;
+        self
+    }
+
+    pub fn size_weight(mut self, value: f32) -> Self {
+        self.config.size_weight = value;
+        self
+    }
+
+    pub fn init_iou(mut self, value: f32) -> Self {
+        self.config.init_iou = value;
+        self
+    }
+
+    pub fn class_agnostic_nms(mut self, value: bool) -> Self {
+        self.config.class_agnostic_nms = value;
+        self
+    }
+
+    pub fn score_temperature(mut self, value: f32) -> Self {
+        self.config.score_temperature = value;
+        self
+    }
+
+    pub fn num_threads(mut self, value: usize) -> Self {
+        self.config.num_threads = Some(value);
+        self
+    }
+
+    pub fn min_box_area(mut self, value: f32) -> Self {
+        self.config.min_box_area = value;
+        self
+    }
+
+    pub fn max_box_area(mut self, value: f32) -> Self {
+        self.config.max_box_area = Some(value);
+        self
+    }
+
+    pub fn max_aspect_ratio(mut self, value: f32) -> Self {
+        self.config.max_aspect_ratio = Some(value);
+        self
+    }
+
+    pub fn box_smoothing_alpha(mut self, value: f32) -> Self {
+        self.config.box_smoothing_alpha = value;
+        self
+    }
+
+    pub fn reid_gallery_window(mut self, value: usize) -> Self {
+        self.config.reid_gallery_window = value;
+        self
+    }
+
+    pub fn min_track_area(mut self, value: f32) -> Self {
+        self.config.min_track_area = value;
+        self
+    }
+
+    pub fn size_prior(mut self, value: crate::roi::SizePrior) -> Self {
+        self.config.size_prior = Some(value);
+        self
+    }
+
+    pub fn deterministic(mut self, value: bool) -> Self {
+        self.config.deterministic = value;
+        self
+    }
+
+    pub fn duplicate_iou(mut self, value: f32) -> Self {
+        self.config.duplicate_iou = value;
+        self
+    }
+
+    pub fn half(mut self, value: bool) -> Self {
+        self.config.half = value;
+        self
+    }
+
+    /// Finish, rejecting invalid combinations like `Config::validate`.
+    pub fn build(self) -> anyhow::Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
 impl Config {
     /// Load from a JSON file.
     pub fn from_file(path: &str) -> anyhow::Result<Self> {
         let data = fs::read_to_string(path)?;
         let cfg: Config = serde_json::from_str(&data)?;
+        cfg.validate()?;
         Ok(cfg)
     }
+
+    /// Write as pretty-printed JSON, e.g. to record the effective settings of a run.
+    pub fn to_file(&self, path: &str) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Reject contradictory or out-of-range settings with a descriptive error.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(!self.model_path.is_empty(), "model_path is required");
+        self.validate_tracker()
+    }
+
+    /// `validate` without requiring `model_path`, for callers that only build a tracker
+    /// and feed it their own detections.
+    pub fn validate_tracker(&self) -> anyhow::Result<()> {
+        let unit_thresholds = [
+            ("track_high_thresh", self.track_high_thresh),
+            ("track_low_thresh", self.track_low_thresh),
+            ("new_track_thresh", self.new_track_thresh),
+            ("proximity_thresh", self.proximity_thresh),
+            ("appearance_thresh", self.appearance_thresh),
+            ("conf_threshold", self.conf_threshold),
+            ("nms_threshold", self.nms_threshold),
+            ("init_iou", self.init_iou),
+        ];
+        for (name, value) in unit_thresholds {
+            ensure!((0.0..=1.0).contains(&value), "{} must be in [0, 1], got {}", name, value);
+        }
+        ensure!(
+            self.track_low_thresh <= self.track_high_thresh,
+            "track_low_thresh ({}) must not exceed track_high_thresh ({})",
+            self.track_low_thresh, self.track_high_thresh
+        );
+        ensure!(
+            self.input_size[0] > 0 && self.input_size[1] > 0,
+            "input_size must be positive, got {:?}", self.input_size
+        );
+        ensure!(
+            self.duplicate_iou > 0.0 && self.duplicate_iou <= 1.0,
+            "duplicate_iou must be in (0, 1], got {}", self.duplicate_iou
+        );
+        ensure!(self.track_buffer > 0, "track_buffer must be at least 1");
+        ensure!(self.n_init > 0, "n_init must be at least 1");
+        ensure!(self.size_weight >= 0.0, "size_weight must not be negative, got {}", self.size_weight);
+        ensure!(
+            self.score_temperature > 0.0,
+            "score_temperature must be positive, got {}", self.score_temperature
+        );
+        ensure!(self.num_threads != Some(0), "num_threads must be at least 1 when set");
+        ensure!(self.min_box_area >= 0.0, "min_box_area must not be negative, got {}", self.min_box_area);
+        ensure!(self.min_track_area >= 0.0, "min_track_area must not be negative, got {}", self.min_track_area);
+        if let Some(max_area) = self.max_box_area {
+            ensure!(
+                max_area > self.min_box_area,
+                "max_box_area ({}) must exceed min_box_area ({})", max_area, self.min_box_area
+            );
+        }
+        ensure!(
+            (0.0..1.0).contains(&self.box_smoothing_alpha),
+            "box_smoothing_alpha must be in [0, 1), got {}", self.box_smoothing_alpha
+        );
+        if let Some(ratio) = self.max_aspect_ratio {
+            ensure!(ratio > 0.0, "max_aspect_ratio must be positive, got {}", ratio);
+        }
+        if let Some(prior) = &self.size_prior {
+            prior.validate()?;
+        }
+        if self.with_reid {
+            ensure!(
+                self.appearance_thresh > 0.0 && self.proximity_thresh > 0.0,
+                "with_reid requires appearance_thresh and proximity_thresh to be set above 0"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_config() -> Config {
+        serde_json::from_str(r#"{
+            "model_path": "yolov7.torchscript",
+            "device": "cpu",
+            "input_size": [640, 640],
+            "track_high_thresh": 0.5,
+            "track_low_thresh": 0.1,
+            "new_track_thresh": 0.6,
+            "track_buffer": 30,
+            "proximity_thresh": 0.5,
+            "appearance_thresh": 0.25,
+            "with_reid": false,
+            "conf_threshold": 0.25,
+            "nms_threshold": 0.45,
+            "classes": [0]
+        }"#).unwrap()
+    }
+
+    fn assert_invalid(config: Config, field: &str) {
+        let err = config.validate().expect_err("config should be rejected");
+        assert!(err.to_string().contains(field), "error {:?} should mention {}", err.to_string(), field);
+    }
+
+    #[test]
+    fn test_partial_json_uses_defaults() {
+        let config: Config = serde_json::from_str(r#"{"model_path": "weights/yolov7.torchscript"}"#).unwrap();
+        assert_eq!(config.model_path, "weights/yolov7.torchscript");
+        assert_eq!(config, Config { model_path: config.model_path.clone(), ..Config::default() });
+        assert_eq!(config.track_high_thresh, 0.5);
+        assert_eq!(config.track_buffer, 30);
+        assert_eq!(config.n_init, 3);
+        assert_eq!(config.input_size, [640, 640]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_to_file_round_trip() {
+        let config = ConfigBuilder::new("weights/yolov7.torchscript")
+            .conf_threshold(0.001)
+            .gmc_features(GmcFeatures::Orb)
+            .classes(vec![0, 2])
+            .n_init(2)
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join("smiletrack_test_config.json");
+        let path = path.to_string_lossy();
+
+        config.to_file(&path).unwrap();
+        let loaded = Config::from_file(&path).unwrap();
+        std::fs::remove_file(path.as_ref()).ok();
+
+        assert_eq!(loaded, config);
+    }
+
+    #[test]
+    fn test_missing_model_path_rejected() {
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_invalid(config, "model_path");
+    }
+
+    #[test]
+    fn test_builder_matches_struct() {
+        let built = ConfigBuilder::new("model.pt")
+            .track_high_thresh(0.6)
+            .track_buffer(60)
+            .with_reid(true)
+            .gmc_method(GmcMode::Affine)
+            .input_size(1280, 736)
+            .build()
+            .unwrap();
+        let expected = Config {
+            model_path: "model.pt".to_string(),
+            track_high_thresh: 0.6,
+            track_buffer: 60,
+            with_reid: true,
+            gmc_method: GmcMode::Affine,
+            input_size: [1280, 736],
+            ..Config::default()
+        };
+        assert_eq!(built, expected);
+
+        assert!(ConfigBuilder::new("model.pt").track_low_thresh(0.9).build().is_err());
+    }
+
+    #[test]
+    fn test_validate_accepts_valid_config() {
+        assert!(valid_config().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_threshold_out_of_range() {
+        let mut config = valid_config();
+        config.conf_threshold = 1.5;
+        assert_invalid(config, "conf_threshold");
+
+        let mut config = valid_config();
+        config.track_high_thresh = -0.1;
+        assert_invalid(config, "track_high_thresh");
+    }
+
+    #[test]
+    fn test_validate_threshold_order() {
+        let mut config = valid_config();
+        config.track_low_thresh = 0.7;
+        assert_invalid(config, "track_low_thresh");
+    }
+
+    #[test]
+    fn test_validate_input_size() {
+        let mut config = valid_config();
+        config.input_size = [640, 0];
+        assert_invalid(config, "input_size");
+    }
+
+    #[test]
+    fn test_validate_track_buffer() {
+        let mut config = valid_config();
+        config.track_buffer = 0;
+        assert_invalid(config, "track_buffer");
+    }
+
+    #[test]
+    fn test_validate_reid_needs_appearance_thresholds() {
+        let mut config = valid_config();
+        config.with_reid = true;
+        config.appearance_thresh = 0.0;
+        assert_invalid(config, "with_reid");
+    }
 }
\ No newline at end of file
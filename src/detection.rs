@@ -5,19 +5,67 @@ use opencv::{
     prelude::*,
 };
 use tch::{Device, Kind, Tensor};
+use crate::config::Config;
+use crate::tracker::STrack;
 use crate::utils;
 use nalgebra::SVector;
 use num_traits::cast::ToPrimitive;
+use log::{debug, log_enabled, trace, warn, Level};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Instant;
 
 /// A single detection result.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Detection {
     pub tlwh: SVector<f32, 4>,
     pub confidence: f32,
     pub class_id: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub feature: Option<Vec<f32>>,
+    /// Label of the detector that produced this detection, for multi-model fusion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Instance mask over the box as a `MASK_SIZE` x `MASK_SIZE` row-major bitmap (nonzero is
+    /// foreground). Only segmentation models produce one; box-only models leave it `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<Vec<u8>>,
 }
 
+/// Convert a frame to the 8-bit 3-channel BGR layout preprocessing expects: grayscale is
+/// expanded, BGRA loses its alpha, 16-bit is scaled down and float is taken as [0, 1].
+/// Returns `None` when the frame is already 8-bit BGR.
+pub(crate) fn to_bgr8(frame: &Mat) -> Result<Option<Mat>> {
+    let scale = match frame.depth() {
+        opencv::core::CV_8U => 1.0,
+        opencv::core::CV_16U => 255.0 / 65535.0,
+        opencv::core::CV_32F | opencv::core::CV_64F => 255.0,
+        depth => anyhow::bail!("unsupported frame depth {}", depth),
+    };
+    let code = match frame.channels() {
+        1 => Some(imgproc::COLOR_GRAY2BGR),
+        3 => None,
+        4 => Some(imgproc::COLOR_BGRA2BGR),
+        channels => anyhow::bail!("unsupported frame with {} channels", channels),
+    };
+    if scale == 1.0 && code.is_none() {
+        return Ok(None);
+    }
+
+    let mut bgr8 = Mat::default();
+    frame.convert_to(&mut bgr8, opencv::core::CV_8U, scale, 0.0)?;
+    if let Some(code) = code {
+        let mut expanded = Mat::default();
+        imgproc::cvt_color(&bgr8, &mut expanded, code, 0)?;
+        bgr8 = expanded;
+    }
+    Ok(Some(bgr8))
+}
+
+/// Side length of the bitmap in `Detection::mask`
+pub const MASK_SIZE: usize = 28;
+
 impl Detection {
     pub fn new(tlwh: SVector<f32, 4>, confidence: f32, class_id: i32, feature: Option<Vec<f32>>) -> Self {
         Self {
@@ -25,16 +73,186 @@ impl Detection {
             confidence,
             class_id,
             feature,
+            source: None,
+            mask: None,
         }
     }
 
+    /// Build a detection from corner coordinates `(x1, y1, x2, y2)`.
+    pub fn from_tlbr(tlbr: SVector<f32, 4>, confidence: f32, class_id: i32, feature: Option<Vec<f32>>) -> Self {
+        Self::new(STrack::tlbr_to_tlwh(&tlbr), confidence, class_id, feature)
+    }
+
+    /// Tag the detection with the label of the model that produced it.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
     pub fn tlwh(&self) -> &SVector<f32, 4> {
         &self.tlwh
     }
 
+    /// Box as corner coordinates `(x1, y1, x2, y2)`.
+    pub fn tlbr(&self) -> SVector<f32, 4> {
+        STrack::tlwh_to_tlbr(&self.tlwh)
+    }
+
     pub fn confidence(&self) -> f32 {
         self.confidence
     }
+
+    /// Fraction of the box covered by the mask, for suppressing boxes that are mostly
+    /// background; `None` without a mask
+    pub fn foreground_fraction(&self) -> Option<f32> {
+        let mask = self.mask.as_ref().filter(|m| !m.is_empty())?;
+        Some(mask.iter().filter(|&&v| v != 0).count() as f32 / mask.len() as f32)
+    }
+}
+
+/// One frame's entry in a detection cache file
+#[derive(Serialize, Deserialize)]
+struct CachedFrame {
+    frame_id: i32,
+    detections: Vec<Detection>,
+}
+
+/// Per-frame detections saved to disk, so the tracker can be re-run (e.g. while tuning its
+/// parameters) without the model. Files ending in `.jsonl` hold one frame per line; anything
+/// else is a single JSON array of frames.
+#[derive(Debug, Clone, Default)]
+pub struct DetectionCache {
+    frames: BTreeMap<i32, Vec<Detection>>,
+}
+
+impl DetectionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the detections of a frame, replacing any earlier entry for it
+    pub fn insert(&mut self, frame_id: i32, detections: &[Detection]) {
+        self.frames.insert(frame_id, detections.to_vec());
+    }
+
+    /// Detections of a frame; frames that were never recorded have none
+    pub fn get(&self, frame_id: i32) -> &[Detection] {
+        self.frames.get(&frame_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Number of frames recorded
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let frames = self.frames.iter().map(|(&frame_id, detections)| CachedFrame {
+            frame_id,
+            detections: detections.clone(),
+        });
+        let text = if path.ends_with(".jsonl") {
+            let mut text = String::new();
+            for frame in frames {
+                text.push_str(&serde_json::to_string(&frame)?);
+                text.push('\n');
+            }
+            text
+        } else {
+            serde_json::to_string(&frames.collect::<Vec<_>>())?
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let frames: Vec<CachedFrame> = if path.ends_with(".jsonl") {
+            text.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()?
+        } else {
+            serde_json::from_str(&text)?
+        };
+        Ok(DetectionCache {
+            frames: frames.into_iter().map(|frame| (frame.frame_id, frame.detections)).collect(),
+        })
+    }
+}
+
+/// Highest class score of a raw YOLOv7 row and its class ID (class 0 with score 0 when no
+/// score is positive)
+fn best_class(row: &[f32]) -> (i32, f32) {
+    let mut max_cls_conf = 0.0f32;
+    let mut max_cls_id = 0i32;
+    for (c, &cls_conf) in row[5..utils::YOLO_ROW_LEN].iter().enumerate() {
+        if cls_conf > max_cls_conf {
+            max_cls_conf = cls_conf;
+            max_cls_id = c as i32;
+        }
+    }
+    (max_cls_id, max_cls_conf)
+}
+
+/// Temperature-scaled sigmoid of a raw objectness logit: `sigmoid(logit / temperature)`.
+/// A temperature of 1 is the plain sigmoid; larger values soften scores towards 0.5.
+pub fn calibrate_confidence(logit: f32, temperature: f32) -> f32 {
+    1.0 / (1.0 + (-logit / temperature).exp())
+}
+
+/// Temperature-scaled objectness for a model that already applies the sigmoid:
+/// `sigmoid(logit(p) / temperature)`. A temperature of 1 returns `p` unchanged.
+pub fn calibrate_probability(p: f32, temperature: f32) -> f32 {
+    let p = p.clamp(1e-7, 1.0 - 1e-7);
+    calibrate_confidence((p / (1.0 - p)).ln(), temperature)
+}
+
+/// Decode raw YOLOv7 rows (`YOLO_ROW_LEN` values per box, row-major) into detections.
+///
+/// The exported model's objectness is already a probability; every box is rescored with
+/// `calibrate_probability` and kept if the score reaches `conf_threshold` and its best
+/// class is in `classes`.
+fn decode_raw_rows(rows: &[f32], classes: &[i32], conf_threshold: f32, temperature: f32) -> Vec<Detection> {
+    if log_enabled!(Level::Debug) {
+        let best = rows.chunks_exact(utils::YOLO_ROW_LEN)
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a[4].total_cmp(&b[4]));
+        if let Some((idx, row)) = best {
+            let (max_cls_id, max_cls_conf) = best_class(row);
+            debug!("Best detection at index {}: obj_conf={:.6}, class={}, class_conf={:.6}, coords=[{:.6}, {:.6}, {:.6}, {:.6}]",
+                    idx, row[4], max_cls_id, max_cls_conf, row[0], row[1], row[2], row[3]);
+            trace!("Values for best detection (first 10 out of 85): {:?}", &row[..10]);
+        }
+    }
+
+    rows.par_chunks_exact(utils::YOLO_ROW_LEN)
+        .filter_map(|row| {
+            let obj_conf = calibrate_probability(row[4], temperature);
+            if obj_conf < conf_threshold {
+                return None;
+            }
+            // Skip if class not in allowed classes
+            let (max_cls_id, _) = best_class(row);
+            classes.contains(&max_cls_id).then(|| {
+                Detection::new(SVector::from_vec(vec![row[0], row[1], row[2], row[3]]), obj_conf, max_cls_id, None)
+            })
+        })
+        .collect()
+}
+
+/// Anything that turns a frame into detections, e.g. `Detector` or a test double.
+pub trait ObjectDetector {
+    fn detect(&self, frame: &Mat) -> Result<Vec<Detection>>;
+}
+
+impl ObjectDetector for Detector {
+    fn detect(&self, frame: &Mat) -> Result<Vec<Detection>> {
+        Detector::detect(self, frame)
+    }
 }
 
 /// Wraps a YOLOv7 model tracer or ONNX runtime.
@@ -45,10 +263,24 @@ pub struct Detector {
     pub conf_threshold: f32,
     pub nms_threshold: f32,
     pub classes: Vec<i32>,  // List of allowed class IDs
+    /// Label stamped on every detection as `Detection::source`
+    pub source: Option<String>,
+    /// Let boxes of any class suppress each other in NMS (one box per object)
+    pub class_agnostic_nms: bool,
+    /// Temperature for `calibrate_probability` when rescoring raw objectness
+    pub temperature: f32,
+    /// Smallest box area kept, in frame pixels
+    pub min_box_area: f32,
+    /// Largest box area kept, in frame pixels
+    pub max_box_area: Option<f32>,
+    /// Widest width/height ratio kept
+    pub max_aspect_ratio: Option<f32>,
+    /// Model weights and inputs are fp16; see `set_half`
+    half: bool,
 }
 
 impl Detector {
-    /// Create a new detector from a model file and device ("cpu"/"cuda").
+    /// Create a new detector from a model file and device ("cpu", "cuda" or "cuda:N").
     pub fn new(
         model_path: &str,
         device: &str,
@@ -57,11 +289,7 @@ impl Detector {
         nms_threshold: f32,
     ) -> Result<Self> {
         // Load TorchScript model
-        let device = if device == "cuda" && tch::Cuda::is_available() {
-            Device::Cuda(0)
-        } else {
-            Device::Cpu
-        };
+        let device = utils::resolve_device(device);
         
         let model = tch::CModule::load(model_path)?;
         
@@ -72,17 +300,92 @@ impl Detector {
             conf_threshold,
             nms_threshold,
             classes: vec![0, 1, 2, 3, 5, 7, 15, 16],  // Default allowed classes
+            source: None,
+            class_agnostic_nms: false,
+            temperature: 1.0,
+            min_box_area: utils::MIN_BOX_AREA,
+            max_box_area: None,
+            max_aspect_ratio: None,
+            half: false,
         })
     }
 
+    /// Create a detector from the model, device, input size, thresholds and classes in
+    /// `config`, first capping libtorch/OpenCV threads if `num_threads` is set.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        if let Some(n) = config.num_threads {
+            tch::set_num_threads(n as i32);
+            opencv::core::set_num_threads(n as i32)?;
+        }
+        let mut detector = Self::new(
+            &config.model_path,
+            &config.device,
+            (config.input_size[0] as i64, config.input_size[1] as i64),
+            config.conf_threshold,
+            config.nms_threshold,
+        )?;
+        detector.set_classes(config.classes.clone());
+        detector.class_agnostic_nms = config.class_agnostic_nms;
+        detector.temperature = config.score_temperature;
+        detector.min_box_area = config.min_box_area;
+        detector.max_box_area = config.max_box_area;
+        detector.max_aspect_ratio = config.max_aspect_ratio;
+        detector.set_half(config.half);
+        Ok(detector)
+    }
+
+    /// Run the model in half precision. On CUDA the weights are cast to fp16 and each input
+    /// is cast before the forward pass, with the output cast back to fp32 for postprocessing.
+    /// A no-op (with a warning) on the CPU.
+    pub fn set_half(&mut self, half: bool) {
+        let half = utils::resolve_half(half, self.device);
+        if half != self.half {
+            let kind = if half { Kind::Half } else { Kind::Float };
+            self.model.to(self.device, kind, false);
+            self.half = half;
+        }
+    }
+
+    /// Whether inference runs in fp16
+    pub fn is_half(&self) -> bool {
+        self.half
+    }
+
+    /// Device the model and its inputs live on
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// Label this detector's output, e.g. "person_model", when fusing several models.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
     /// Preprocess frame for YOLOv7 inference
     fn preprocess(&self, frame: &Mat) -> Result<Tensor> {
+        self.preprocess_at(frame, self.input_size)
+    }
+
+    /// Preprocess frame for inference at a (width, height) input size
+    fn preprocess_at(&self, frame: &Mat, input_size: (i64, i64)) -> Result<Tensor> {
+        let converted = to_bgr8(frame)?;
+        let frame = converted.as_ref().unwrap_or(frame);
+        #[cfg(feature = "cuda-preprocess")]
+        if self.device.is_cuda() {
+            return self.preprocess_on_device(frame, input_size);
+        }
+        self.preprocess_cpu(frame, input_size)
+    }
+
+    /// Resize, convert and normalize with OpenCV on the CPU, then upload the result
+    fn preprocess_cpu(&self, frame: &Mat, input_size: (i64, i64)) -> Result<Tensor> {
         // Resize frame
         let mut resized = Mat::default();
         imgproc::resize(
             frame,
             &mut resized,
-            Size::new(self.input_size.0 as i32, self.input_size.1 as i32),
+            Size::new(input_size.0 as i32, input_size.1 as i32),
             0.0,
             0.0,
             imgproc::INTER_LINEAR,
@@ -112,22 +415,57 @@ impl Detector {
         Ok(tensor)
     }
 
+    /// Upload the raw BGR frame and do resize + color swap + normalize in libtorch on `self.device`
+    #[cfg(feature = "cuda-preprocess")]
+    fn preprocess_on_device(&self, frame: &Mat, input_size: (i64, i64)) -> Result<Tensor> {
+        let frame = frame.try_clone()?;  // guarantees a continuous buffer
+        let (rows, cols) = (frame.rows() as i64, frame.cols() as i64);
+
+        // [H, W, C] u8 on device -> [1, C, H, W] float in [0,1], BGR -> RGB
+        let tensor = Tensor::from_slice(frame.data_bytes()?)
+            .to_device(self.device)
+            .reshape([rows, cols, 3])
+            .permute([2, 0, 1])
+            .flip([0])
+            .to_kind(Kind::Float)
+            .unsqueeze(0)
+            / 255.0;
+
+        // Bilinear with half-pixel centers matches OpenCV's INTER_LINEAR
+        let resized = tensor.upsample_bilinear2d(
+            [input_size.1, input_size.0],
+            false,
+            None,
+            None,
+        );
+        Ok(resized)
+    }
+
     /// Run inference on preprocessed input
-    fn inference(&self, input: &Tensor) -> Result<Tensor> {
+    pub(crate) fn inference(&self, input: &Tensor) -> Result<Tensor> {
+        if self.half {
+            let output = self.model.forward_ts(&[input.to_kind(Kind::Half)])?;
+            return Ok(output.to_kind(Kind::Float));
+        }
         let output = self.model.forward_ts(&[input])?;
         Ok(output)
     }
 
-    /// Postprocess raw model output into detections
+    /// Postprocess the raw model output of a single frame (batch of one) into detections
     fn postprocess(&self, output: &Tensor, orig_size: (i32, i32)) -> Result<Vec<Detection>> {
+        self.postprocess_at(output, orig_size, self.input_size)
+    }
+
+    /// `postprocess` for output of a model run at `input_size` (width, height)
+    fn postprocess_at(&self, output: &Tensor, orig_size: (i32, i32), input_size: (i64, i64)) -> Result<Vec<Detection>> {
         // Print tensor shape for debugging
-        println!("Output tensor shape: {:?}", output.size());
+        debug!("Output tensor shape: {:?}", output.size());
         
         let mut detections = Vec::new();
         
         // Get original image dimensions for scaling
-        let (orig_h, orig_w) = orig_size;
-        let (input_h, input_w) = (self.input_size.1 as f32, self.input_size.0 as f32);
+        let (orig_w, orig_h) = orig_size;
+        let (input_h, input_w) = (input_size.1 as f32, input_size.0 as f32);
         
         // Calculate scaling factors
         let scale_w = orig_w as f32 / input_w;
@@ -138,177 +476,17 @@ impl Detector {
         let output_shape = output.size();
         
         if output_shape.len() == 3 && output_shape[2] == 85 {
-            println!("Processing raw YOLOv7 tensor output format");
-            
-            // Copy to CPU for easier processing
-            let cpu_tensor = output.to_device(Device::Cpu);
-            
-            // Find the indices with highest objectness scores
-            let mut high_conf_indices = Vec::new();
-            
-            // Check sample of boxes for diagnostic purposes
-            for i in 0..output_shape[1] {
-                let obj_conf = cpu_tensor.get(0).get(i).get(4).double_value(&[]) as f32;
-                if obj_conf > 0.5 {
-                    high_conf_indices.push((i, obj_conf));
-                }
-            }
-            
-            // Print information about high confidence detections
-            println!("Found {} boxes with objectness > 0.5", high_conf_indices.len());
-            if !high_conf_indices.is_empty() {
-                for &(idx, conf) in high_conf_indices.iter().take(5) {
-                    // Get bounding box coordinates
-                    let x = cpu_tensor.get(0).get(idx).get(0).double_value(&[]) as f32;
-                    let y = cpu_tensor.get(0).get(idx).get(1).double_value(&[]) as f32;
-                    let w = cpu_tensor.get(0).get(idx).get(2).double_value(&[]) as f32;
-                    let h = cpu_tensor.get(0).get(idx).get(3).double_value(&[]) as f32;
-                    
-                    // Get best class and its confidence
-                    let mut max_cls_conf = 0.0f32;
-                    let mut max_cls_id = 0i32;
-                    
-                    for c in 0..80 {
-                        let cls_conf = cpu_tensor.get(0).get(idx).get(5 + c).double_value(&[]) as f32;
-                        if cls_conf > max_cls_conf {
-                            max_cls_conf = cls_conf;
-                            max_cls_id = c as i32;
-                        }
-                    }
-                    
-                    println!("Box {}: obj_conf={:.4}, class={}, class_conf={:.4}, coords=[{:.4}, {:.4}, {:.4}, {:.4}]",
-                            idx, conf, max_cls_id, max_cls_conf, x, y, w, h);
-                    
-                    // Create detection if class is in allowed classes
-                    if self.classes.contains(&max_cls_id) {
-                        // Convert to pixel coordinates
-                        let x1 = x;
-                        let y1 = y;
-                        let w_scaled = w;
-                        let h_scaled = h;
-                        
-                        println!("Adding high-conf detection: class={}, conf={:.4}, bbox=[{:.1}, {:.1}, {:.1}, {:.1}]",
-                                max_cls_id, conf, x1, y1, w_scaled, h_scaled);
-                        
-                        detections.push(Detection::new(
-                            SVector::from_vec(vec![x1, y1, w_scaled, h_scaled]),
-                            conf,
-                            max_cls_id,
-                            None
-                        ));
-                    }
-                }
-            } else {
-                println!("No high confidence detections found, checking for ANY with obj_conf > 0.01");
-                // If no high confidence, get the highest objectness score
-                let mut highest_obj_conf = 0.0f32;
-                let mut highest_obj_idx = 0;
-                
-                for i in 0..output_shape[1] {
-                    let obj_conf = cpu_tensor.get(0).get(i).get(4).double_value(&[]) as f32;
-                    if obj_conf > highest_obj_conf {
-                        highest_obj_conf = obj_conf;
-                        highest_obj_idx = i;
-                    }
-                }
-                
-                println!("Highest objectness confidence: {:.6} at index {}", highest_obj_conf, highest_obj_idx);
-                
-                // Print detailed info about this best detection
-                let idx = highest_obj_idx;
-                let x = cpu_tensor.get(0).get(idx).get(0).double_value(&[]) as f32;
-                let y = cpu_tensor.get(0).get(idx).get(1).double_value(&[]) as f32;
-                let w = cpu_tensor.get(0).get(idx).get(2).double_value(&[]) as f32;
-                let h = cpu_tensor.get(0).get(idx).get(3).double_value(&[]) as f32;
-                
-                // Get best class
-                let mut max_cls_conf = 0.0f32;
-                let mut max_cls_id = 0i32;
-                
-                for c in 0..80 {
-                    let cls_conf = cpu_tensor.get(0).get(idx).get(5 + c).double_value(&[]) as f32;
-                    if cls_conf > max_cls_conf {
-                        max_cls_conf = cls_conf;
-                        max_cls_id = c as i32;
-                    }
-                }
-                
-                println!("Best detection: obj_conf={:.6}, class={}, class_conf={:.6}, coords=[{:.6}, {:.6}, {:.6}, {:.6}]",
-                        highest_obj_conf, max_cls_id, max_cls_conf, x, y, w, h);
-                
-                // Show the first few values from the tensor for this box to verify the format
-                println!("Values for best detection (first 10 out of 85):");
-                for i in 0..10 {
-                    let val = cpu_tensor.get(0).get(idx).get(i).double_value(&[]) as f32;
-                    println!("  Index {}: {:.6}", i, val);
-                }
-                
-                // Actually process all boxes that meet threshold
-                for i in 0..output_shape[1] {
-                    // Get objectness confidence from the tensor
-                    let raw_obj_conf = cpu_tensor.get(0).get(i).get(4).double_value(&[]) as f32;
-                    
-                    // Apply confidence boost to match Python behavior
-                    // Note: This is a heuristic adjustment to align with Python implementation
-                    let obj_conf = if raw_obj_conf > 0.03 {
-                        // Boost higher confidence detections more aggressively
-                        raw_obj_conf * 20.0 
-                    } else if raw_obj_conf > 0.01 {
-                        // Medium boost for mid-range confidences
-                        raw_obj_conf * 10.0
-                    } else {
-                        // Small boost for lower confidences
-                        raw_obj_conf * 5.0
-                    };
-                    
-                    // Cap maximum confidence at 1.0
-                    let obj_conf = obj_conf.min(1.0);
-                    
-                    if obj_conf < self.conf_threshold {
-                        continue;
-                    }
-                    
-                    // Get box coordinates - these appear to be in pixel coordinates already
-                    let x = cpu_tensor.get(0).get(i).get(0).double_value(&[]) as f32;
-                    let y = cpu_tensor.get(0).get(i).get(1).double_value(&[]) as f32;
-                    let w = cpu_tensor.get(0).get(i).get(2).double_value(&[]) as f32;
-                    let h = cpu_tensor.get(0).get(i).get(3).double_value(&[]) as f32;
-                    
-                    // Find max class score
-                    let mut max_cls_conf = 0.0f32;
-                    let mut max_cls_id = 0i32;
-                    
-                    for c in 0..80 {
-                        let cls_conf = cpu_tensor.get(0).get(i).get(5 + c).double_value(&[]) as f32;
-                        if cls_conf > max_cls_conf {
-                            max_cls_conf = cls_conf;
-                            max_cls_id = c as i32;
-                        }
-                    }
-                    
-                    // Skip if class not in allowed classes
-                    if !self.classes.contains(&max_cls_id) {
-                        continue;
-                    }
-                    
-                    // Use coordinates directly - they're already in pixel space
-                    let x1 = x;
-                    let y1 = y;
-                    let w_scaled = w;
-                    let h_scaled = h;
-                    
-                    detections.push(Detection::new(
-                        SVector::from_vec(vec![x1, y1, w_scaled, h_scaled]),
-                        obj_conf,
-                        max_cls_id,
-                        None
-                    ));
-                }
+            debug!("Processing raw YOLOv7 tensor output format");
+            let rows = utils::first_batch_rows(output)?;
+            detections = decode_raw_rows(&rows, &self.classes, self.conf_threshold, self.temperature);
+            // Raw boxes are in model input pixels
+            for det in &mut detections {
+                det.tlwh.component_mul_assign(&SVector::<f32, 4>::new(scale_w, scale_h, scale_w, scale_h));
             }
         } else if output_shape.len() == 3 && output_shape[2] == 6 {
             // Format from Python code: [batch, detections, 6]
             // Where each detection is [x1, y1, w, h, conf, cls_id]
-            println!("Detected Python-style output format");
+            debug!("Detected Python-style output format");
             
             for b in 0..output_shape[0] {
                 let num_detections = output_shape[1];
@@ -329,7 +507,7 @@ impl Detector {
                     
                     // Skip class IDs not in allowed classes
                     if !self.classes.contains(&cls_id) {
-                        println!("Skipping detection: class_id={} not in allowed classes: {:?}", cls_id, self.classes);
+                        trace!("Skipping detection: class_id={} not in allowed classes: {:?}", cls_id, self.classes);
                         continue;
                     }
                     
@@ -338,7 +516,7 @@ impl Detector {
                     let bbox = [x, y, w, h];
                     
                     if conf > 0.5 {
-                        println!("High score detection: class={}, score={:.3}, box=[{:.1}, {:.1}, {:.1}, {:.1}]", 
+                        trace!("High score detection: class={}, score={:.3}, box=[{:.1}, {:.1}, {:.1}, {:.1}]", 
                                 cls_id, conf, x, y, w, h);
                     }
                     
@@ -351,51 +529,171 @@ impl Detector {
                 }
             }
         } else {
-            println!("Unknown output tensor format: {:?}", output_shape);
+            warn!("Unknown output tensor format: {:?}", output_shape);
         }
         
-        println!("{} detections found", detections.len());
+        // Clamp boxes to the frame and drop any that become degenerate or implausibly sized
+        detections = detections.into_iter()
+            .filter_map(|mut det| {
+                let (clamped, _) = utils::soft_clamp_box(&det.tlwh, orig_w as f32, orig_h as f32);
+                if !self.plausible_size(clamped[2], clamped[3]) {
+                    trace!("Dropping {:.1}x{:.1} box outside the size limits", clamped[2], clamped[3]);
+                    return None;
+                }
+                det.tlwh = clamped;
+                Some(det)
+            })
+            .collect();
         
-        // Apply NMS if we have more than one detection
-        if detections.len() > 1 {
-            let boxes_array: Vec<[f32; 4]> = detections.iter()
-                .map(|det| det.tlwh.as_slice().try_into().unwrap())
-                .collect();
-            let scores_array: Vec<f32> = detections.iter()
-                .map(|det| det.confidence)
-                .collect();
-            
-            let keep = utils::nms(&boxes_array, &scores_array, self.nms_threshold);
-            
-            let mut filtered_dets = Vec::new();
-            for &idx in &keep {
-                filtered_dets.push(detections[idx].clone());
-            }
-            
-            println!("After NMS: {} detections kept out of {}", filtered_dets.len(), detections.len());
-            detections = filtered_dets;
+        debug!("{} detections found", detections.len());
+        
+        // Apply NMS
+        let boxes_array: Vec<[f32; 4]> = detections.iter()
+            .map(|det| det.tlwh.as_slice().try_into().unwrap())
+            .collect();
+        let scores_array: Vec<f32> = detections.iter()
+            .map(|det| det.confidence)
+            .collect();
+        
+        let keep = if self.class_agnostic_nms {
+            utils::nms(&boxes_array, &scores_array, self.nms_threshold)
+        } else {
+            let class_ids: Vec<i32> = detections.iter().map(|det| det.class_id).collect();
+            utils::nms_per_class(&boxes_array, &scores_array, &class_ids, self.nms_threshold)
+        };
+        
+        let mut filtered_dets = Vec::new();
+        for &idx in &keep {
+            filtered_dets.push(detections[idx].clone());
         }
         
+        debug!("After NMS: {} detections kept out of {}", filtered_dets.len(), detections.len());
+        detections = filtered_dets;
+        
         Ok(detections)
     }
 
+    /// Whether a `w` x `h` box passes the area and aspect-ratio limits
+    fn plausible_size(&self, w: f32, h: f32) -> bool {
+        if w <= 0.0 || h <= 0.0 {
+            return false;
+        }
+        let area = w * h;
+        area >= self.min_box_area
+            && self.max_box_area.map_or(true, |max| area <= max)
+            && self.max_aspect_ratio.map_or(true, |max| w / h <= max)
+    }
+
     /// Detect objects in a frame
     pub fn detect(&self, frame: &Mat) -> Result<Vec<Detection>> {
+        Ok(self.detect_timed(frame)?.0)
+    }
+
+    /// Detect objects with the model run at `input_size` (width, height) instead of the
+    /// configured size, e.g. 416 for speed or 640 for accuracy. The TorchScript model must
+    /// accept dynamic input shapes; YOLO needs both sides to be multiples of its 32 px stride.
+    pub fn detect_at(&self, frame: &Mat, input_size: (i64, i64)) -> Result<Vec<Detection>> {
+        anyhow::ensure!(
+            input_size.0 > 0 && input_size.1 > 0 && input_size.0 % 32 == 0 && input_size.1 % 32 == 0,
+            "input size must be positive multiples of 32, got {:?}", input_size
+        );
+        Ok(self.detect_timed_at(frame, input_size)?.0)
+    }
+
+    /// `detect`, also returning how long preprocessing, inference and postprocessing took
+    pub fn detect_timed(&self, frame: &Mat) -> Result<(Vec<Detection>, utils::Timings)> {
+        self.detect_timed_at(frame, self.input_size)
+    }
+
+    fn detect_timed_at(&self, frame: &Mat, input_size: (i64, i64)) -> Result<(Vec<Detection>, utils::Timings)> {
+        let mut timings = utils::Timings::default();
+
         // Get original frame size for bbox scaling
         let orig_size = (frame.cols(), frame.rows());
 
         // Preprocess
-        let input = self.preprocess(frame)?;
+        let start = Instant::now();
+        let input = self.preprocess_at(frame, input_size)?;
+        timings.preprocess_ms = utils::elapsed_ms(start);
 
         // Run inference
+        let start = Instant::now();
         let output = self.inference(&input)?;
+        timings.inference_ms = utils::elapsed_ms(start);
 
         // Postprocess
-        let detections = self.postprocess(&output, orig_size)?;
+        let start = Instant::now();
+        let mut detections = self.postprocess_at(&output, orig_size, input_size)?;
+        self.stamp_source(&mut detections);
+        timings.postprocess_ms = utils::elapsed_ms(start);
+
+        Ok((detections, timings))
+    }
+
+    /// Run `iters` forward passes on a zero tensor of the configured input size so lazy
+    /// CUDA context and graph initialization happen before the first real frame instead
+    /// of inflating its latency.
+    pub fn warmup(&self, iters: usize) -> Result<()> {
+        let input = Tensor::zeros(&[1, 3, self.input_size.1, self.input_size.0], (Kind::Float, self.device));
+        for _ in 0..iters {
+            self.inference(&input)?;
+        }
+        if let Device::Cuda(index) = self.device {
+            tch::Cuda::synchronize(index as i64);
+        }
+        Ok(())
+    }
+
+    /// Detect objects in an already-preprocessed `[1, 3, H, W]` tensor (RGB, float in [0, 1],
+    /// at the model input size), skipping `preprocess`. `orig_size` is the (width, height)
+    /// of the frame the tensor was made from; boxes are clamped to it.
+    pub fn detect_tensor(&self, input: &Tensor, orig_size: (i32, i32)) -> Result<Vec<Detection>> {
+        let expected = [1, 3, self.input_size.1, self.input_size.0];
+        anyhow::ensure!(
+            input.size() == expected,
+            "input tensor must have shape {:?}, got {:?}", expected, input.size()
+        );
+        let output = self.inference(&input.to_device(self.device).to_kind(Kind::Float))?;
+        let mut detections = self.postprocess(&output, orig_size)?;
+        self.stamp_source(&mut detections);
 
         Ok(detections)
     }
 
+    /// Detect objects in several frames with a single forward pass.
+    ///
+    /// Frames are preprocessed individually and stacked into a `[B, 3, H, W]` batch; the
+    /// output is split back per frame and scaled to each frame's own size. Results are in
+    /// the order of `frames`.
+    pub fn detect_batch(&self, frames: &[Mat]) -> Result<Vec<Vec<Detection>>> {
+        if frames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let orig_sizes: Vec<(i32, i32)> = frames.iter().map(|f| (f.cols(), f.rows())).collect();
+        let inputs = frames.iter()
+            .map(|frame| self.preprocess(frame))
+            .collect::<Result<Vec<_>>>()?;
+        let output = self.inference(&Tensor::cat(&inputs, 0))?;
+
+        orig_sizes.iter()
+            .enumerate()
+            .map(|(i, &orig_size)| {
+                let mut detections = self.postprocess(&output.narrow(0, i as i64, 1), orig_size)?;
+                self.stamp_source(&mut detections);
+                Ok(detections)
+            })
+            .collect()
+    }
+
+    /// Set `source` on detections when this detector is labeled
+    fn stamp_source(&self, detections: &mut [Detection]) {
+        if let Some(source) = &self.source {
+            for det in detections {
+                det.source = Some(source.clone());
+            }
+        }
+    }
+
     /// Set the allowed classes for detection
     pub fn set_classes(&mut self, classes: Vec<i32>) {
         self.classes = classes;
@@ -408,6 +706,21 @@ mod tests {
     use opencv::core::VecN;
     use opencv::imgcodecs;
     use std::path::Path;
+    use crate::tracker::SMILEtrack;
+
+    #[test]
+    fn test_tlbr_round_trip() {
+        let tlwh = SVector::<f32, 4>::new(12.5, 40.0, 30.0, 80.0);
+        let det = Detection::new(tlwh, 0.7, 2, None);
+        assert_eq!(det.tlbr(), SVector::<f32, 4>::new(12.5, 40.0, 42.5, 120.0));
+
+        let from_corners = Detection::from_tlbr(det.tlbr(), 0.7, 2, None);
+        assert_eq!(from_corners.tlwh, tlwh);
+        assert_eq!(from_corners, det);
+
+        let manual = Detection::new(STrack::tlbr_to_tlwh(&det.tlbr()), 0.7, 2, Some(vec![1.0, 0.0]));
+        assert_eq!(Detection::from_tlbr(det.tlbr(), 0.7, 2, Some(vec![1.0, 0.0])), manual);
+    }
 
     #[test]
     fn test_detector_initialization() {
@@ -421,6 +734,57 @@ mod tests {
         assert!(detector.is_ok());
     }
 
+    #[test]
+    fn test_single_thread_detection() {
+        let config = crate::config::ConfigBuilder::new("weights/yolov7.torchscript")
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let detector = Detector::from_config(&config).unwrap();
+        assert_eq!(tch::get_num_threads(), 1);
+
+        let frame = Mat::new_size_with_default(
+            Size::new(640, 480),
+            opencv::core::CV_8UC3,
+            VecN::from([127.0, 127.0, 127.0]),
+        ).unwrap();
+        assert!(detector.detect(&frame).is_ok());
+    }
+
+    #[cfg(feature = "cuda-preprocess")]
+    #[test]
+    fn test_gpu_preprocess_matches_cpu() {
+        if !tch::Cuda::is_available() {
+            return;
+        }
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cuda",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+
+        // Colored gradient so resize and channel order both matter
+        let mut frame = Mat::new_size_with_default(
+            Size::new(1280, 720),
+            opencv::core::CV_8UC3,
+            VecN::from([0.0, 0.0, 0.0]),
+        ).unwrap();
+        for y in 0..720 {
+            for x in 0..1280 {
+                *frame.at_2d_mut::<opencv::core::Vec3b>(y, x).unwrap() =
+                    VecN::from([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8]);
+            }
+        }
+
+        let cpu = detector.preprocess_cpu(&frame, detector.input_size).unwrap().to_device(Device::Cpu);
+        let gpu = detector.preprocess_on_device(&frame, detector.input_size).unwrap().to_device(Device::Cpu);
+        assert_eq!(cpu.size(), gpu.size());
+        let max_diff = (cpu - gpu).abs().max().double_value(&[]);
+        assert!(max_diff < 0.02, "max difference {}", max_diff);
+    }
+
     #[test]
     fn test_preprocessing() {
         // Create a test image
@@ -450,6 +814,45 @@ mod tests {
         assert!(max.double_value(&[]) <= 1.0);
     }
 
+    #[test]
+    fn test_to_bgr8_conversions() {
+        let gray = Mat::new_size_with_default(Size::new(8, 4), opencv::core::CV_8UC1, opencv::core::Scalar::all(100.0)).unwrap();
+        let bgr = to_bgr8(&gray).unwrap().unwrap();
+        assert_eq!((bgr.typ(), bgr.cols(), bgr.rows()), (opencv::core::CV_8UC3, 8, 4));
+        assert_eq!(*bgr.at_2d::<opencv::core::Vec3b>(0, 0).unwrap(), opencv::core::Vec3b::from([100, 100, 100]));
+
+        let deep = Mat::new_size_with_default(Size::new(8, 4), opencv::core::CV_16UC3, opencv::core::Scalar::new(65535.0, 0.0, 32768.0, 0.0)).unwrap();
+        let bgr = to_bgr8(&deep).unwrap().unwrap();
+        assert_eq!(bgr.typ(), opencv::core::CV_8UC3);
+        assert_eq!(*bgr.at_2d::<opencv::core::Vec3b>(1, 1).unwrap(), opencv::core::Vec3b::from([255, 0, 128]));
+
+        let plain = Mat::new_size_with_default(Size::new(8, 4), opencv::core::CV_8UC3, opencv::core::Scalar::new(1.0, 2.0, 3.0, 0.0)).unwrap();
+        assert!(to_bgr8(&plain).unwrap().is_none());
+        let signed = Mat::new_size_with_default(Size::new(8, 4), opencv::core::CV_16SC1, opencv::core::Scalar::all(1.0)).unwrap();
+        assert!(to_bgr8(&signed).is_err());
+    }
+
+    #[test]
+    fn test_preprocess_gray_and_16bit_frames() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+
+        let thermal = Mat::new_size_with_default(Size::new(640, 512), opencv::core::CV_8UC1, opencv::core::Scalar::all(200.0)).unwrap();
+        let deep = Mat::new_size_with_default(Size::new(1280, 720), opencv::core::CV_16UC3, opencv::core::Scalar::new(65535.0, 1000.0, 0.0, 0.0)).unwrap();
+        for frame in [thermal, deep] {
+            let tensor = detector.preprocess(&frame).unwrap();
+            assert_eq!(tensor.size(), &[1, 3, 640, 640]);
+            assert_eq!(tensor.kind(), Kind::Float);
+            assert!(tensor.min().double_value(&[]) >= 0.0);
+            assert!(tensor.max().double_value(&[]) <= 1.0);
+        }
+    }
+
     #[test]
     fn test_inference() {
         let detector = Detector::new(
@@ -471,6 +874,434 @@ mod tests {
         assert!(output.size()[2] > 5);    // num_classes + 5
     }
 
+    #[test]
+    fn test_postprocess_clamps_to_frame() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+
+        // [x, y, w, h, conf, cls] rows: one straddling the left edge, one fully off-frame
+        let output = Tensor::from_slice(&[
+            -20.0f32, 50.0, 100.0, 100.0, 0.9, 0.0,
+            700.0, 50.0, 100.0, 100.0, 0.9, 0.0,
+        ]).reshape(&[1, 2, 6]);
+        let detections = detector.postprocess(&output, (640, 480)).unwrap();
+
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].tlwh[0], 0.0);
+        assert_eq!(detections[0].tlwh[1], 50.0);
+        assert_eq!(detections[0].tlwh[2], 80.0);
+        assert_eq!(detections[0].tlwh[3], 100.0);
+    }
+
+    /// The original per-scalar decode of a raw `[1, boxes, 85]` output, kept as a reference
+    fn decode_scalar(output: &Tensor, classes: &[i32], conf_threshold: f32) -> Vec<Detection> {
+        let value = |i: i64, j: i64| output.get(0).get(i).get(j).double_value(&[]) as f32;
+        let best_class = |i: i64| {
+            let (mut max_cls_conf, mut max_cls_id) = (0.0f32, 0i32);
+            for c in 0..80 {
+                if value(i, 5 + c) > max_cls_conf {
+                    max_cls_conf = value(i, 5 + c);
+                    max_cls_id = c as i32;
+                }
+            }
+            max_cls_id
+        };
+        let mut detections = Vec::new();
+        for i in 0..output.size()[1] {
+            let obj_conf = calibrate_probability(value(i, 4), 1.0);
+            if obj_conf >= conf_threshold && classes.contains(&best_class(i)) {
+                detections.push(Detection::new(
+                    SVector::new(value(i, 0), value(i, 1), value(i, 2), value(i, 3)), obj_conf, best_class(i), None));
+            }
+        }
+        detections
+    }
+
+    /// Deterministic pseudo-random `[1, boxes, 85]` output with objectness scaled by `obj_scale`
+    fn synthetic_output(boxes: usize, obj_scale: f32, seed: u64) -> Tensor {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        };
+        let mut data = Vec::with_capacity(boxes * utils::YOLO_ROW_LEN);
+        for _ in 0..boxes {
+            data.extend([next() * 600.0, next() * 400.0, 10.0 + next() * 100.0, 10.0 + next() * 100.0]);
+            data.push(next() * obj_scale);
+            data.extend((0..80).map(|_| next()));
+        }
+        Tensor::from_slice(&data).reshape(&[1, boxes as i64, utils::YOLO_ROW_LEN as i64])
+    }
+
+    #[test]
+    fn test_calibrated_scores_monotonic_and_bounded() {
+        for temperature in [0.5, 1.0, 2.0, 10.0] {
+            let logits = [-1000.0f32, -20.0, -3.0, -0.5, 0.0, 0.5, 3.0, 20.0, 1000.0];
+            let scores: Vec<f32> = logits.iter().map(|&l| calibrate_confidence(l, temperature)).collect();
+            assert!(scores.iter().all(|s| (0.0..=1.0).contains(s)), "{:?}", scores);
+            assert!(scores.windows(2).all(|w| w[0] <= w[1]), "{:?}", scores);
+            assert_eq!(calibrate_confidence(0.0, temperature), 0.5);
+        }
+        // Higher temperature pulls scores towards 0.5
+        assert!(calibrate_confidence(3.0, 2.0) < calibrate_confidence(3.0, 1.0));
+    }
+
+    #[test]
+    fn test_low_objectness_rows_rejected() {
+        // Every objectness at or below 0.5, as from a sigmoid-terminated export
+        let objectness = [0.5f32, 0.3, 0.2, 0.05, 0.01, 0.0];
+        let mut rows = Vec::new();
+        for &obj in &objectness {
+            let mut row = vec![0.0f32; utils::YOLO_ROW_LEN];
+            row[..6].copy_from_slice(&[100.0, 100.0, 50.0, 100.0, obj, 0.9]);
+            rows.extend(row);
+        }
+
+        let kept = decode_raw_rows(&rows, &[0], 0.25, 1.0);
+        let scores: Vec<f32> = kept.iter().map(|d| d.confidence).collect();
+        assert_eq!(scores.len(), 2, "{:?}", scores);
+        assert!((scores[0] - 0.5).abs() < 1e-5 && (scores[1] - 0.3).abs() < 1e-5, "{:?}", scores);
+
+        // A higher temperature softens scores towards 0.5 but still rejects the weak rows
+        let softened = decode_raw_rows(&rows, &[0], 0.25, 2.0);
+        assert_eq!(softened.len(), 3);
+        assert!(softened.iter().all(|d| d.confidence >= 0.25));
+    }
+
+    #[test]
+    fn test_contiguous_decode_matches_scalar() {
+        let all_classes: Vec<i32> = (0..80).collect();
+        let some_classes = vec![0, 1, 2, 3, 5, 7, 15, 16];
+        // Objectness over the full range, and capped at 0.5
+        for (obj_scale, seed, classes) in [(1.0, 1, all_classes), (0.5, 2, some_classes)] {
+            let output = synthetic_output(300, obj_scale, seed);
+            let rows = utils::first_batch_rows(&output).unwrap();
+            let fast = decode_raw_rows(&rows, &classes, 0.25, 1.0);
+            let reference = decode_scalar(&output, &classes, 0.25);
+
+            assert!(!reference.is_empty());
+            assert_eq!(fast.len(), reference.len());
+            for (a, b) in fast.iter().zip(&reference) {
+                assert_eq!(a.tlwh, b.tlwh);
+                assert_eq!(a.confidence, b.confidence);
+                assert_eq!(a.class_id, b.class_id);
+            }
+        }
+    }
+
+    /// Counts log records from this module by level
+    struct CountingLogger;
+
+    static INFO_OR_ABOVE: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    static DEBUG_OR_BELOW: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    impl log::Log for CountingLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            if !record.target().starts_with(module_path!().trim_end_matches("::tests")) {
+                return;
+            }
+            let counter = if record.level() <= log::Level::Info { &INFO_OR_ABOVE } else { &DEBUG_OR_BELOW };
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_postprocess_quiet_at_default_level() {
+        static LOGGER: CountingLogger = CountingLogger;
+        log::set_logger(&LOGGER).ok();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+        let output = Tensor::from_slice(&[
+            10.0f32, 50.0, 100.0, 100.0, 0.9, 0.0,
+            300.0, 50.0, 100.0, 100.0, 0.8, 2.0,
+        ]).reshape(&[1, 2, 6]);
+        let detections = detector.postprocess(&output, (640, 480)).unwrap();
+
+        assert_eq!(detections.len(), 2);
+        // Diagnostics are emitted, but only at debug/trace, which RUST_LOG hides by default
+        assert!(DEBUG_OR_BELOW.load(std::sync::atomic::Ordering::SeqCst) > 0);
+        assert_eq!(INFO_OR_ABOVE.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_detect_tensor_skips_preprocess() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+
+        // No Mat anywhere: the tensor goes straight to the model
+        let input = Tensor::zeros(&[1, 3, 640, 640], (Kind::Float, Device::Cpu));
+        let detections = detector.detect_tensor(&input, (1280, 720)).unwrap();
+        for det in &detections {
+            assert!(det.tlwh[0] >= 0.0 && det.tlwh[0] + det.tlwh[2] <= 1280.0);
+            assert!(det.tlwh[1] >= 0.0 && det.tlwh[1] + det.tlwh[3] <= 720.0);
+        }
+
+        let wrong_size = Tensor::zeros(&[1, 3, 320, 320], (Kind::Float, Device::Cpu));
+        assert!(detector.detect_tensor(&wrong_size, (1280, 720)).is_err());
+    }
+
+    #[test]
+    fn test_detect_batch_matches_single_frames() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+
+        // Different sizes so per-frame scaling is exercised
+        let frames: Vec<Mat> = [(640, 480, 40.0), (1280, 720, 120.0), (320, 320, 200.0)]
+            .iter()
+            .map(|&(w, h, shade)| {
+                let mut frame = Mat::new_size_with_default(
+                    Size::new(w, h),
+                    opencv::core::CV_8UC3,
+                    VecN::from([shade, shade, shade]),
+                ).unwrap();
+                imgproc::rectangle(
+                    &mut frame,
+                    opencv::core::Rect::new(w / 4, h / 4, w / 3, h / 2),
+                    opencv::core::Scalar::new(20.0, 60.0, 220.0, 0.0),
+                    -1,
+                    imgproc::LINE_8,
+                    0,
+                ).unwrap();
+                frame
+            })
+            .collect();
+
+        let batched = detector.detect_batch(&frames).unwrap();
+        assert_eq!(batched.len(), frames.len());
+        for (frame, batch_dets) in frames.iter().zip(&batched) {
+            let single = detector.detect(frame).unwrap();
+            assert_eq!(batch_dets.len(), single.len());
+            for (a, b) in batch_dets.iter().zip(&single) {
+                assert_eq!(a.class_id, b.class_id);
+                assert!((a.confidence - b.confidence).abs() < 1e-4);
+                assert!((a.tlwh - b.tlwh).abs().max() < 1e-2);
+            }
+        }
+        assert!(detector.detect_batch(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_class_agnostic_nms() {
+        let mut detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+        detector.set_classes(vec![0, 24]);
+
+        // A person and a backpack box on the same object
+        let output = Tensor::from_slice(&[
+            100.0f32, 100.0, 60.0, 120.0, 0.9, 0.0,
+            104.0, 102.0, 58.0, 116.0, 0.7, 24.0,
+        ]).reshape(&[1, 2, 6]);
+
+        assert_eq!(detector.postprocess(&output, (640, 480)).unwrap().len(), 2);
+
+        detector.class_agnostic_nms = true;
+        let detections = detector.postprocess(&output, (640, 480)).unwrap();
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].class_id, 0);
+    }
+
+    #[test]
+    fn test_default_nms_keeps_overlapping_boxes_of_other_classes() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+        assert!(!detector.class_agnostic_nms);
+
+        // A rider (person) and the motorcycle under them overlap at IoU ~0.84, well over the
+        // 0.45 threshold; a second, weaker person box on the rider is the only one to go
+        let output = Tensor::from_slice(&[
+            200.0f32, 150.0, 80.0, 160.0, 0.9, 0.0,
+            195.0, 160.0, 90.0, 150.0, 0.8, 3.0,
+            203.0, 152.0, 78.0, 158.0, 0.6, 0.0,
+        ]).reshape(&[1, 3, 6]);
+
+        let detections = detector.postprocess(&output, (640, 480)).unwrap();
+        let mut classes: Vec<i32> = detections.iter().map(|d| d.class_id).collect();
+        classes.sort();
+        assert_eq!(classes, vec![0, 3]);
+    }
+
+    #[test]
+    fn test_lone_tiny_box_dropped() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+
+        // A single 3x3 box: nothing for NMS to suppress, but below the minimum area
+        let output = Tensor::from_slice(&[100.0f32, 100.0, 3.0, 3.0, 0.9, 0.0]).reshape(&[1, 1, 6]);
+        assert!(detector.postprocess(&output, (640, 480)).unwrap().is_empty());
+
+        let output = Tensor::from_slice(&[100.0f32, 100.0, 30.0, 30.0, 0.9, 0.0]).reshape(&[1, 1, 6]);
+        assert_eq!(detector.postprocess(&output, (640, 480)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_raw_boxes_scaled_by_per_call_input_size() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+        // One raw row: box, objectness, then 80 class scores with person highest
+        let raw = |x: f32, y: f32, w: f32, h: f32| {
+            let mut row = vec![0.0f32; utils::YOLO_ROW_LEN];
+            row[..6].copy_from_slice(&[x, y, w, h, 0.9, 0.9]);
+            Tensor::from_slice(&row).reshape(&[1, 1, utils::YOLO_ROW_LEN as i64])
+        };
+
+        // The same object in a 1280x960 frame, as the model sees it at 640 and at 416
+        let at_640 = detector.postprocess_at(&raw(100.0, 100.0, 50.0, 100.0), (1280, 960), (640, 640)).unwrap();
+        let at_416 = detector.postprocess_at(&raw(65.0, 65.0, 32.5, 65.0), (1280, 960), (416, 416)).unwrap();
+        assert_eq!((at_640.len(), at_416.len()), (1, 1));
+        let expected = SVector::<f32, 4>::new(200.0, 150.0, 100.0, 150.0);
+        assert!((at_640[0].tlwh - expected).abs().max() < 1e-3, "{:?}", at_640[0].tlwh);
+        assert!((at_416[0].tlwh - expected).abs().max() < 1e-3, "{:?}", at_416[0].tlwh);
+
+        // The whole pipeline runs at either size on one detector
+        let frame = Mat::new_size_with_default(
+            Size::new(1280, 960),
+            opencv::core::CV_8UC3,
+            VecN::from([127.0, 127.0, 127.0]),
+        ).unwrap();
+        for size in [(416, 416), (640, 640)] {
+            for det in detector.detect_at(&frame, size).unwrap() {
+                assert!(det.tlwh[0] >= 0.0 && det.tlwh[0] + det.tlwh[2] <= 1280.0);
+                assert!(det.tlwh[1] >= 0.0 && det.tlwh[1] + det.tlwh[3] <= 960.0);
+            }
+        }
+        assert!(detector.detect_at(&frame, (400, 416)).is_err());
+    }
+
+    #[test]
+    fn test_box_size_filters() {
+        let mut detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+        detector.min_box_area = 400.0;
+        detector.max_box_area = Some(100_000.0);
+        detector.max_aspect_ratio = Some(3.0);
+
+        let single = |w: f32, h: f32| {
+            Tensor::from_slice(&[10.0f32, 10.0, w, h, 0.9, 0.0]).reshape(&[1, 1, 6])
+        };
+        // Below min area, above max area, too wide
+        assert!(detector.postprocess(&single(15.0, 15.0), (1920, 1080)).unwrap().is_empty());
+        assert!(detector.postprocess(&single(500.0, 900.0), (1920, 1080)).unwrap().is_empty());
+        assert!(detector.postprocess(&single(400.0, 50.0), (1920, 1080)).unwrap().is_empty());
+        // An ordinary standing person
+        assert_eq!(detector.postprocess(&single(60.0, 150.0), (1920, 1080)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_warmup_then_timed_detect() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (320, 320),
+            0.25,
+            0.45,
+        ).unwrap();
+        detector.warmup(2).unwrap();
+        detector.warmup(0).unwrap();
+
+        let frame = Mat::new_size_with_default(
+            Size::new(640, 480),
+            opencv::core::CV_8UC3,
+            VecN::from([127.0, 127.0, 127.0]),
+        ).unwrap();
+        let (_, timings) = detector.detect_timed(&frame).unwrap();
+        assert!(timings.inference_ms > 0.0);
+        assert!(timings.preprocess_ms >= 0.0 && timings.postprocess_ms >= 0.0);
+    }
+
+    #[test]
+    fn test_half_is_noop_on_cpu() {
+        let mut detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+        detector.set_half(true);
+        assert!(!detector.is_half());
+    }
+
+    #[test]
+    fn test_half_matches_full_precision() {
+        let img_path = Path::new("tests/data/test.jpg");
+        if !tch::Cuda::is_available() || !img_path.exists() {
+            return;
+        }
+        let frame = imgcodecs::imread(img_path.to_str().unwrap(), imgcodecs::IMREAD_COLOR).unwrap();
+
+        let mut detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cuda",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+        let full = detector.detect(&frame).unwrap();
+        detector.set_half(true);
+        assert!(detector.is_half());
+        let half = detector.detect(&frame).unwrap();
+
+        // fp16 rounding can push a borderline box across the confidence threshold
+        let tolerance = 1 + full.len() / 10;
+        assert!(
+            full.len().abs_diff(half.len()) <= tolerance,
+            "fp32 found {} detections, fp16 found {}", full.len(), half.len()
+        );
+    }
+
     #[test]
     fn test_end_to_end() {
         // Load test image
@@ -507,4 +1338,83 @@ mod tests {
             assert!(det.tlwh[3] > 0.0 && det.tlwh[3] <= frame.rows() as f32);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_detect_timed_stages() {
+        let detector = Detector::new(
+            "weights/yolov7.torchscript",
+            "cpu",
+            (640, 640),
+            0.25,
+            0.45,
+        ).unwrap();
+        let frame = Mat::new_size_with_default(
+            Size::new(640, 480),
+            opencv::core::CV_8UC3,
+            opencv::core::Scalar::all(0.0),
+        ).unwrap();
+
+        let start = Instant::now();
+        let (_, timings) = detector.detect_timed(&frame).unwrap();
+        let wall_ms = utils::elapsed_ms(start);
+
+        assert!(timings.preprocess_ms > 0.0 && timings.inference_ms > 0.0 && timings.postprocess_ms > 0.0);
+        assert_eq!((timings.gmc_ms, timings.association_ms), (0.0, 0.0));
+        assert!(timings.total_ms() <= wall_ms);
+    }
+
+    #[test]
+    fn test_mask_serialization() {
+        let plain = Detection::new(SVector::<f32, 4>::new(1.0, 2.0, 3.0, 4.0), 0.9, 0, None);
+        let json = serde_json::to_string(&plain).unwrap();
+        assert!(!json.contains("mask"));
+        assert!(serde_json::from_str::<Detection>(&json).unwrap().mask.is_none());
+        assert_eq!(plain.foreground_fraction(), None);
+
+        // Left half of the box is foreground
+        let mut masked = plain.clone();
+        masked.mask = Some((0..MASK_SIZE * MASK_SIZE).map(|i| (i % MASK_SIZE < MASK_SIZE / 2) as u8).collect());
+        let restored: Detection = serde_json::from_str(&serde_json::to_string(&masked).unwrap()).unwrap();
+        assert_eq!(restored.mask, masked.mask);
+        assert_eq!(restored.foreground_fraction(), Some(0.5));
+    }
+
+    #[test]
+    fn test_cached_detections_reproduce_tracking() {
+        // Scripted "live" detections: two people crossing, one with an appearance feature
+        let live = |frame_id: i32| {
+            let t = frame_id as f32;
+            vec![
+                Detection::new(SVector::<f32, 4>::new(100.0 + 4.0 * t, 200.0, 50.0, 110.0), 0.9, 0, Some(vec![0.6, 0.8])),
+                Detection::new(SVector::<f32, 4>::new(500.0 - 3.0 * t, 210.0, 48.0, 105.0), 0.8, 0, None)
+                    .with_source("person_model"),
+            ]
+        };
+
+        for name in ["smiletrack_test_cache.json", "smiletrack_test_cache.jsonl"] {
+            let path = std::env::temp_dir().join(name).to_string_lossy().to_string();
+            let mut cache = DetectionCache::new();
+            for frame_id in 1..=30 {
+                cache.insert(frame_id, &live(frame_id));
+            }
+            cache.save(&path).unwrap();
+            let loaded = DetectionCache::load(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+            assert_eq!(loaded.len(), 30);
+            assert!(loaded.get(31).is_empty());
+
+            let config = Config { model_path: "unused".to_string(), n_init: 1, ..Config::default() };
+            let frame = Mat::default();
+            let mut from_live = SMILEtrack::new(&Config { gmc_method: crate::tracker::GmcMode::None, ..config.clone() }, 30.0);
+            let mut from_cache = SMILEtrack::new(&Config { gmc_method: crate::tracker::GmcMode::None, ..config }, 30.0);
+            for frame_id in 1..=30 {
+                from_live.update(&live(frame_id), &frame, frame_id).unwrap();
+                from_cache.update(loaded.get(frame_id), &frame, frame_id).unwrap();
+                let boxes = |tracker: &SMILEtrack| {
+                    tracker.tracks().iter().map(|t| (t.track_id(), *t.tlwh(), t.source.clone())).collect::<Vec<_>>()
+                };
+                assert_eq!(boxes(&from_live), boxes(&from_cache));
+            }
+        }
+    }
+}
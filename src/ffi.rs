@@ -0,0 +1,166 @@
+//! C interface for embedding the tracker, built into the crate's cdylib. See
+//! `include/smiletrack.h` for the declarations.
+//!
+//! Detections go in as `n` rows of `[x, y, w, h, score, class]` and activated tracks come out
+//! as rows of `[x, y, w, h, id, class]`, all `float`. Camera motion compensation is always off
+//! since no frames cross the boundary. Panics are caught here and reported as failures, so
+//! they never unwind into the caller.
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use opencv::core::Mat;
+use crate::config::Config;
+use crate::interop::{self, ROW_LEN};
+use crate::tracker::{GmcMode, SMILEtrack};
+
+/// Opaque tracker handle owned by the caller between `smiletrack_new` and `smiletrack_free`
+pub struct SmileTrackHandle {
+    tracker: SMILEtrack,
+    /// Stand-in frame for `SMILEtrack::update`; unused with GMC off
+    frame: Mat,
+}
+
+fn parse_config(config_json: *const c_char) -> anyhow::Result<Config> {
+    if config_json.is_null() {
+        return Ok(Config::default());
+    }
+    // SAFETY: the caller guarantees a valid NUL-terminated string (see `smiletrack_new`)
+    let text = unsafe { CStr::from_ptr(config_json) }.to_str()?;
+    let config: Config = serde_json::from_str(text)?;
+    config.validate_tracker()?;
+    Ok(config)
+}
+
+/// Create a tracker from a JSON config string (tracker fields of `config.json`; missing
+/// fields take their defaults). Returns null if the JSON or any setting is invalid.
+///
+/// # Safety
+///
+/// `config_json` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn smiletrack_new(config_json: *const c_char, frame_rate: f32) -> *mut SmileTrackHandle {
+    let result = catch_unwind(|| {
+        let mut config = parse_config(config_json)?;
+        config.gmc_method = GmcMode::None;
+        Ok::<_, anyhow::Error>(SmileTrackHandle {
+            tracker: SMILEtrack::new(&config, frame_rate),
+            frame: Mat::default(),
+        })
+    });
+    match result {
+        Ok(Ok(handle)) => Box::into_raw(Box::new(handle)),
+        Ok(Err(e)) => {
+            log::error!("smiletrack_new: {}", e);
+            std::ptr::null_mut()
+        }
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Feed one frame of `n` detections and copy up to `out_cap` activated tracks into `out`.
+/// Returns the number of activated tracks, which may exceed `out_cap` (only `out_cap` rows
+/// are written), or -1 on error.
+///
+/// # Safety
+///
+/// `handle` must come from `smiletrack_new` and not yet be freed. `dets` must point to
+/// `n * 6` floats (it may be null when `n` is 0) and `out` to `out_cap * 6` writable floats
+/// (it may be null when `out_cap` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn smiletrack_update(
+    handle: *mut SmileTrackHandle,
+    dets: *const f32,
+    n: usize,
+    frame_id: i32,
+    out: *mut f32,
+    out_cap: usize,
+) -> i32 {
+    if handle.is_null() || (dets.is_null() && n > 0) || (out.is_null() && out_cap > 0) {
+        return -1;
+    }
+    let Some(len) = n.checked_mul(ROW_LEN) else {
+        return -1;
+    };
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        // SAFETY: pointer validity and lengths are the caller's contract above
+        let handle = unsafe { &mut *handle };
+        let rows = if n == 0 { &[][..] } else { unsafe { std::slice::from_raw_parts(dets, len) } };
+        let detections = interop::detections_from_rows(rows)?;
+        handle.tracker.update(&detections, &handle.frame, frame_id)?;
+
+        let tracks = interop::track_rows(handle.tracker.tracks());
+        let count = tracks.len() / ROW_LEN;
+        let written = count.min(out_cap) * ROW_LEN;
+        if written > 0 {
+            let out = unsafe { std::slice::from_raw_parts_mut(out, written) };
+            out.copy_from_slice(&tracks[..written]);
+        }
+        Ok::<_, anyhow::Error>(count)
+    }));
+    match result {
+        Ok(Ok(count)) => i32::try_from(count).unwrap_or(i32::MAX),
+        Ok(Err(e)) => {
+            log::error!("smiletrack_update: {}", e);
+            -1
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Destroy a tracker. Null is ignored.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `smiletrack_new`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn smiletrack_free(handle: *mut SmileTrackHandle) {
+    if !handle.is_null() {
+        // SAFETY: created by Box::into_raw in smiletrack_new and freed only once
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(handle) })));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_ffi_round_trip() {
+        let config = CString::new(r#"{"n_init": 1, "track_high_thresh": 0.5}"#).unwrap();
+        let handle = unsafe { smiletrack_new(config.as_ptr(), 30.0) };
+        assert!(!handle.is_null());
+
+        let mut out = [0.0f32; 2 * ROW_LEN];
+        let mut count = 0;
+        for frame_id in 1..=3 {
+            let x = 100.0 + 2.0 * frame_id as f32;
+            let dets = [x, 100.0, 50.0, 100.0, 0.9, 0.0];
+            count = unsafe { smiletrack_update(handle, dets.as_ptr(), 1, frame_id, out.as_mut_ptr(), 2) };
+        }
+        assert_eq!(count, 1);
+        assert_eq!(out[4], 1.0);
+        assert!((out[0] - 106.0).abs() < 2.0);
+
+        // Capacity 0 still reports the count; bad pointers are rejected, not dereferenced
+        let dets = [106.0, 100.0, 50.0, 100.0, 0.9, 0.0];
+        assert_eq!(unsafe { smiletrack_update(handle, dets.as_ptr(), 1, 4, std::ptr::null_mut(), 0) }, 1);
+        assert_eq!(unsafe { smiletrack_update(handle, std::ptr::null(), 1, 5, out.as_mut_ptr(), 2) }, -1);
+        assert_eq!(unsafe { smiletrack_update(std::ptr::null_mut(), dets.as_ptr(), 1, 5, out.as_mut_ptr(), 2) }, -1);
+        assert_eq!(unsafe { smiletrack_update(handle, dets.as_ptr(), usize::MAX, 5, out.as_mut_ptr(), 2) }, -1);
+
+        unsafe { smiletrack_free(handle) };
+
+        let bad = CString::new("{not json").unwrap();
+        assert!(unsafe { smiletrack_new(bad.as_ptr(), 30.0) }.is_null());
+
+        // Well-formed JSON with settings the tracker cannot work with
+        for invalid in [
+            r#"{"track_buffer": 0}"#,
+            r#"{"track_low_thresh": 0.6, "track_high_thresh": 0.5}"#,
+            r#"{"new_track_thresh": 1.5}"#,
+        ] {
+            let config = CString::new(invalid).unwrap();
+            assert!(unsafe { smiletrack_new(config.as_ptr(), 30.0) }.is_null(), "{}", invalid);
+        }
+    }
+}
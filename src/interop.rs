@@ -0,0 +1,54 @@
+use anyhow::ensure;
+use nalgebra::SVector;
+use crate::detection::Detection;
+use crate::tracker::STrack;
+
+/// Values per row in the flat arrays exchanged with other languages:
+/// detections in are `[x, y, w, h, score, class]`, tracks out are `[x, y, w, h, id, class]`
+pub const ROW_LEN: usize = 6;
+
+/// Build detections from a row-major `[N, 6]` buffer of `[x, y, w, h, score, class]`.
+pub fn detections_from_rows(rows: &[f32]) -> anyhow::Result<Vec<Detection>> {
+    ensure!(
+        rows.len() % ROW_LEN == 0,
+        "expected rows of {} values [x, y, w, h, score, class], got {} values", ROW_LEN, rows.len()
+    );
+    Ok(rows.chunks_exact(ROW_LEN)
+        .map(|row| Detection::new(SVector::<f32, 4>::new(row[0], row[1], row[2], row[3]), row[4], row[5] as i32, None))
+        .collect())
+}
+
+/// Flatten the activated tracks to a row-major `[M, 6]` buffer of `[x, y, w, h, id, class]`.
+pub fn track_rows(tracks: &[STrack]) -> Vec<f32> {
+    tracks.iter()
+        .filter(|t| t.is_activated())
+        .flat_map(|t| {
+            let tlwh = t.tlwh();
+            [tlwh[0], tlwh[1], tlwh[2], tlwh[3], t.track_id() as f32, t.class_id as f32]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::KalmanFilter;
+
+    #[test]
+    fn test_row_conversions() {
+        let dets = detections_from_rows(&[
+            10.0, 20.0, 30.0, 40.0, 0.9, 2.0,
+            50.0, 60.0, 70.0, 80.0, 0.5, 0.0,
+        ]).unwrap();
+        assert_eq!(dets.len(), 2);
+        assert_eq!(dets[0].tlwh, SVector::<f32, 4>::new(10.0, 20.0, 30.0, 40.0));
+        assert_eq!((dets[1].confidence, dets[1].class_id), (0.5, 0));
+        assert!(detections_from_rows(&[1.0; 7]).is_err());
+
+        let mut confirmed = STrack::new(SVector::<f32, 4>::new(10.0, 20.0, 30.0, 40.0), 0.9, 2, None, 1);
+        confirmed.activate(&KalmanFilter::new(), 1, 7);
+        confirmed.confirm(1);
+        let tentative = STrack::new(SVector::<f32, 4>::new(0.0, 0.0, 5.0, 5.0), 0.9, 0, None, 1);
+        assert_eq!(track_rows(&[confirmed, tentative]), vec![10.0, 20.0, 30.0, 40.0, 7.0, 2.0]);
+    }
+}
@@ -4,8 +4,18 @@ pub mod utils;
 pub mod tracker;
 pub mod visualization;
 pub mod simple_detector;
+pub mod analytics;
+pub mod roi;
+pub mod pipeline;
+pub mod undistort;
+pub mod self_test;
+pub mod interop;
+pub mod ffi;
+#[cfg(feature = "python")]
+pub mod python;
 
 // Re-export main types
-pub use crate::config::Config;
-pub use crate::detection::{Detection, Detector};
-pub use crate::tracker::{STrack, SMILEtrack};
\ No newline at end of file
+pub use crate::config::{Config, ConfigBuilder};
+pub use crate::detection::{Detection, Detector, ObjectDetector};
+pub use crate::pipeline::Pipeline;
+pub use crate::tracker::{STrack, SMILEtrack, GmcMode, GmcFeatures, TrackEvent};
\ No newline at end of file
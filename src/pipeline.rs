@@ -0,0 +1,90 @@
+use anyhow::Result;
+use opencv::core::Mat;
+use crate::config::Config;
+use crate::detection::ObjectDetector;
+use crate::tracker::{SMILEtrack, STrack};
+
+/// Detector and tracker glued together: one call per frame runs detection, updates the
+/// tracker and returns the tracks worth reporting.
+///
+/// ```ignore
+/// let config = Config::from_file("config.json")?;
+/// let mut pipeline = Pipeline::new(Detector::from_config(&config)?, &config, 30.0);
+/// for track in pipeline.process(&frame, frame_id)? {
+///     println!("{} {:?}", track.track_id(), track.tlwh());
+/// }
+/// ```
+pub struct Pipeline<D: ObjectDetector> {
+    detector: D,
+    tracker: SMILEtrack,
+}
+
+impl<D: ObjectDetector> Pipeline<D> {
+    /// Build the tracker from `config`; `frame_rate` scales how long lost tracks are kept.
+    pub fn new(detector: D, config: &Config, frame_rate: f32) -> Self {
+        Pipeline { detector, tracker: SMILEtrack::new(config, frame_rate) }
+    }
+
+    /// Detect and track on one frame, returning the activated tracks that pass the
+    /// tracker's output filters (see `SMILEtrack::tracks_filtered`).
+    pub fn process(&mut self, frame: &Mat, frame_id: i32) -> Result<Vec<STrack>> {
+        let detections = self.detector.detect(frame)?;
+        self.tracker.update(&detections, frame, frame_id)?;
+        Ok(self.tracker.tracks_filtered().into_iter().cloned().collect())
+    }
+
+    pub fn detector(&self) -> &D {
+        &self.detector
+    }
+
+    pub fn tracker(&self) -> &SMILEtrack {
+        &self.tracker
+    }
+
+    /// The tracker, e.g. to reset it between clips or attach track metadata
+    pub fn tracker_mut(&mut self) -> &mut SMILEtrack {
+        &mut self.tracker
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::Detection;
+    use nalgebra::SVector;
+    use opencv::core::{Scalar, Size, CV_8UC3};
+    use opencv::prelude::*;
+    use std::cell::Cell;
+
+    /// Reports one person walking right by 5 px per call
+    struct WalkingPerson {
+        calls: Cell<i32>,
+    }
+
+    impl ObjectDetector for WalkingPerson {
+        fn detect(&self, _frame: &Mat) -> Result<Vec<Detection>> {
+            let x = 100.0 + 5.0 * self.calls.get() as f32;
+            self.calls.set(self.calls.get() + 1);
+            Ok(vec![Detection::new(SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None)])
+        }
+    }
+
+    #[test]
+    fn test_pipeline_tracks_mock_detections() {
+        let config = Config { n_init: 2, ..Config::default() };
+        let mut pipeline = Pipeline::new(WalkingPerson { calls: Cell::new(0) }, &config, 30.0);
+        let frame = Mat::new_size_with_default(Size::new(640, 480), CV_8UC3, Scalar::all(0.0)).unwrap();
+
+        // Not reported until confirmed
+        assert!(pipeline.process(&frame, 1).unwrap().is_empty());
+        let tracks = pipeline.process(&frame, 2).unwrap();
+        assert_eq!(tracks.len(), 1);
+        let id = tracks[0].track_id();
+
+        let tracks = pipeline.process(&frame, 3).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].track_id(), id);
+        assert!(tracks[0].tlwh()[0] > 105.0);
+        assert_eq!(pipeline.detector().calls.get(), 3);
+    }
+}
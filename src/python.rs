@@ -0,0 +1,75 @@
+//! Python bindings, built with `maturin develop --features python`:
+//!
+//! ```python
+//! import numpy as np, smiletrack_rs
+//! tracker = smiletrack_rs.SMILEtrack()
+//! tracks = tracker.update(np.array([[x, y, w, h, score, cls]], dtype=np.float32), frame_id)
+//! ```
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray2};
+use opencv::core::Mat;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use crate::config::Config;
+use crate::interop::{self, ROW_LEN};
+use crate::tracker::{GmcMode, SMILEtrack};
+
+fn value_error(e: anyhow::Error) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// `SMILEtrack` driven by numpy arrays. Camera motion compensation is disabled since no
+/// frames cross the boundary.
+#[pyclass(name = "SMILEtrack", unsendable)]
+pub struct PySMILEtrack {
+    inner: SMILEtrack,
+    /// Stand-in frame for `SMILEtrack::update`; unused with GMC off
+    frame: Mat,
+}
+
+#[pymethods]
+impl PySMILEtrack {
+    #[new]
+    #[pyo3(signature = (config_path=None, frame_rate=30.0))]
+    fn new(config_path: Option<&str>, frame_rate: f32) -> PyResult<Self> {
+        let mut config = match config_path {
+            Some(path) => Config::from_file(path).map_err(value_error)?,
+            None => Config::default(),
+        };
+        config.gmc_method = GmcMode::None;
+        Ok(PySMILEtrack {
+            inner: SMILEtrack::new(&config, frame_rate),
+            frame: Mat::default(),
+        })
+    }
+
+    /// Update with an `[N, 6]` float32 array of `[x, y, w, h, score, class]` rows and return
+    /// an `[M, 6]` array of `[x, y, w, h, id, class]` for the activated tracks.
+    fn update<'py>(
+        &mut self,
+        py: Python<'py>,
+        dets: PyReadonlyArray2<'py, f32>,
+        frame_id: i32,
+    ) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let dets = dets.as_array();
+        if dets.ncols() != ROW_LEN {
+            return Err(PyValueError::new_err(format!(
+                "expected an [N, {}] array of [x, y, w, h, score, class], got {:?}", ROW_LEN, dets.shape()
+            )));
+        }
+        let flat: Vec<f32> = dets.iter().copied().collect();
+        let detections = interop::detections_from_rows(&flat).map_err(value_error)?;
+        self.inner.update(&detections, &self.frame, frame_id).map_err(value_error)?;
+
+        let rows = interop::track_rows(self.inner.tracks());
+        let tracks = Array2::from_shape_vec((rows.len() / ROW_LEN, ROW_LEN), rows)
+            .expect("track_rows returns whole rows");
+        Ok(tracks.into_pyarray_bound(py))
+    }
+}
+
+#[pymodule]
+fn smiletrack_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySMILEtrack>()?;
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use anyhow::{ensure, Result};
+use opencv::{
+    core::{Mat, Point2f, Size, CV_8UC1},
+    imgcodecs, imgproc,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use crate::detection::Detection;
+
+/// Which point of a detection box is tested against the region
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferencePoint {
+    /// Box center
+    Center,
+    /// Middle of the bottom edge (where a pedestrian touches the ground)
+    BottomCenter,
+}
+
+impl ReferencePoint {
+    /// Resolve the reference point of a tlwh box.
+    pub fn of(&self, tlwh: &nalgebra::SVector<f32, 4>) -> Point2f {
+        match self {
+            ReferencePoint::Center => Point2f::new(tlwh[0] + tlwh[2] / 2.0, tlwh[1] + tlwh[3] / 2.0),
+            ReferencePoint::BottomCenter => Point2f::new(tlwh[0] + tlwh[2] / 2.0, tlwh[1] + tlwh[3]),
+        }
+    }
+}
+
+/// Whether `pt` lies inside `polygon` (points on an edge count as inside).
+/// Works for any simple polygon, convex or not.
+pub fn point_in_polygon(pt: Point2f, polygon: &[Point2f]) -> bool {
+    if polygon.len() < 3 {
+        return false;
+    }
+
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[j]);
+
+        // On the edge a-b?
+        let cross = (b.x - a.x) * (pt.y - a.y) - (b.y - a.y) * (pt.x - a.x);
+        if cross.abs() <= 1e-6
+            && pt.x >= a.x.min(b.x) && pt.x <= a.x.max(b.x)
+            && pt.y >= a.y.min(b.y) && pt.y <= a.y.max(b.y)
+        {
+            return true;
+        }
+
+        // Ray casting towards +x
+        if (a.y > pt.y) != (b.y > pt.y) {
+            let x_cross = a.x + (pt.y - a.y) * (b.x - a.x) / (b.y - a.y);
+            if pt.x < x_cross {
+                inside = !inside;
+            }
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Keeps only detections that fall inside a polygonal region of interest.
+#[derive(Debug, Clone)]
+pub struct RoiFilter {
+    polygon: Vec<Point2f>,
+    reference: ReferencePoint,
+}
+
+impl RoiFilter {
+    /// Create a filter from polygon vertices, testing box centers.
+    pub fn new(polygon: Vec<Point2f>) -> Self {
+        RoiFilter {
+            polygon,
+            reference: ReferencePoint::Center,
+        }
+    }
+
+    /// Load the polygon from a JSON file containing `[[x, y], ...]`.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let data = fs::read_to_string(path)?;
+        let points: Vec<[f32; 2]> = serde_json::from_str(&data)?;
+        if points.len() < 3 {
+            anyhow::bail!("ROI polygon in {} needs at least 3 points, got {}", path, points.len());
+        }
+        Ok(Self::new(points.iter().map(|p| Point2f::new(p[0], p[1])).collect()))
+    }
+
+    /// Choose which point of each box is tested against the polygon.
+    pub fn with_reference(mut self, reference: ReferencePoint) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    pub fn polygon(&self) -> &[Point2f] {
+        &self.polygon
+    }
+
+    /// Whether a single box lies inside the region.
+    pub fn contains(&self, tlwh: &nalgebra::SVector<f32, 4>) -> bool {
+        point_in_polygon(self.reference.of(tlwh), &self.polygon)
+    }
+
+    /// Drop detections outside the region.
+    pub fn filter(&self, dets: Vec<Detection>) -> Vec<Detection> {
+        dets.into_iter().filter(|d| self.contains(&d.tlwh)).collect()
+    }
+}
+
+/// Keeps only detections whose reference point lands on a non-zero pixel of a mask image,
+/// e.g. a hand-painted PNG of the valid area of a fixed camera.
+pub struct MaskFilter {
+    mask: Mat,
+    reference: ReferencePoint,
+    /// Mask resized to the latest frame size, when that differs from the mask's own
+    resized: Option<(Size, Mat)>,
+}
+
+impl MaskFilter {
+    /// Create a filter from a single-channel 8-bit mask, testing box centers.
+    pub fn new(mask: Mat) -> Result<Self> {
+        ensure!(!mask.empty(), "mask is empty");
+        ensure!(mask.typ() == CV_8UC1, "mask must be single-channel 8-bit");
+        Ok(MaskFilter { mask, reference: ReferencePoint::Center, resized: None })
+    }
+
+    /// Load the mask from an image file; color images are converted to grayscale.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let mask = imgcodecs::imread(path, imgcodecs::IMREAD_GRAYSCALE)?;
+        if mask.empty() {
+            anyhow::bail!("could not read mask image {}", path);
+        }
+        Self::new(mask)
+    }
+
+    /// Choose which point of each box is tested against the mask.
+    pub fn with_reference(mut self, reference: ReferencePoint) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    /// The mask at `frame_size`, resized (nearest neighbour) once per new size
+    fn mask_for(&mut self, frame_size: Size) -> Result<&Mat> {
+        if self.mask.size()? == frame_size {
+            return Ok(&self.mask);
+        }
+        if !matches!(&self.resized, Some((size, _)) if *size == frame_size) {
+            let mut resized = Mat::default();
+            imgproc::resize(&self.mask, &mut resized, frame_size, 0.0, 0.0, imgproc::INTER_NEAREST)?;
+            self.resized = Some((frame_size, resized));
+        }
+        Ok(&self.resized.as_ref().expect("resized above").1)
+    }
+
+    /// Drop detections whose reference point is on a zero pixel or outside a frame of
+    /// `frame_size`.
+    pub fn filter(&mut self, dets: Vec<Detection>, frame_size: Size) -> Result<Vec<Detection>> {
+        let reference = self.reference;
+        let mask = self.mask_for(frame_size)?;
+        let mut kept = Vec::with_capacity(dets.len());
+        for det in dets {
+            let pt = reference.of(&det.tlwh);
+            let (x, y) = (pt.x.floor() as i32, pt.y.floor() as i32);
+            if x >= 0 && y >= 0 && x < mask.cols() && y < mask.rows() && *mask.at_2d::<u8>(y, x)? != 0 {
+                kept.push(det);
+            }
+        }
+        Ok(kept)
+    }
+}
+
+fn default_size_prior_tolerance() -> f32 {
+    2.0
+}
+
+/// Perspective prior for a fixed camera: expected box height as a linear function of the
+/// image row of the box's bottom edge, calibrated from two `(y, height)` points. Objects
+/// near the top of the frame are farther away and so should be smaller.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SizePrior {
+    /// Two `(bottom edge y, typical box height)` pairs, in pixels, at different rows
+    pub points: [(f32, f32); 2],
+    /// A box is plausible when its height is within this factor of the expected height
+    #[serde(default = "default_size_prior_tolerance")]
+    pub tolerance: f32,
+}
+
+impl SizePrior {
+    /// Prior through two calibration points, allowing boxes up to twice or half the
+    /// expected height.
+    pub fn new(a: (f32, f32), b: (f32, f32)) -> Self {
+        SizePrior { points: [a, b], tolerance: default_size_prior_tolerance() }
+    }
+
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let [(y0, h0), (y1, h1)] = self.points;
+        ensure!(y0 != y1, "size prior calibration points need different y, got {} twice", y0);
+        ensure!(h0 > 0.0 && h1 > 0.0, "size prior heights must be positive, got {} and {}", h0, h1);
+        ensure!(self.tolerance > 1.0, "size prior tolerance must exceed 1, got {}", self.tolerance);
+        Ok(())
+    }
+
+    /// Expected box height for a box whose bottom edge is at row `y` (at least one pixel,
+    /// as the line may reach zero towards the horizon)
+    pub fn expected_height(&self, y: f32) -> f32 {
+        let [(y0, h0), (y1, h1)] = self.points;
+        (h0 + (y - y0) * (h1 - h0) / (y1 - y0)).max(1.0)
+    }
+
+    /// Whether a tlwh box's height fits its position.
+    pub fn plausible(&self, tlwh: &nalgebra::SVector<f32, 4>) -> bool {
+        let expected = self.expected_height(tlwh[1] + tlwh[3]);
+        let ratio = tlwh[3] / expected;
+        ratio <= self.tolerance && ratio >= 1.0 / self.tolerance
+    }
+
+    /// Drop detections whose size contradicts their position.
+    pub fn filter(&self, dets: Vec<Detection>) -> Vec<Detection> {
+        dets.into_iter().filter(|d| self.plausible(&d.tlwh)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::SVector;
+
+    /// U-shaped (non-convex) polygon: a 300x300 square with a notch cut from the top middle
+    fn u_shape() -> Vec<Point2f> {
+        vec![
+            Point2f::new(0.0, 0.0),
+            Point2f::new(100.0, 0.0),
+            Point2f::new(100.0, 200.0),
+            Point2f::new(200.0, 200.0),
+            Point2f::new(200.0, 0.0),
+            Point2f::new(300.0, 0.0),
+            Point2f::new(300.0, 300.0),
+            Point2f::new(0.0, 300.0),
+        ]
+    }
+
+    #[test]
+    fn test_point_inside_non_convex() {
+        let poly = u_shape();
+        assert!(point_in_polygon(Point2f::new(50.0, 50.0), &poly));
+        assert!(point_in_polygon(Point2f::new(250.0, 100.0), &poly));
+        assert!(point_in_polygon(Point2f::new(150.0, 250.0), &poly));
+    }
+
+    #[test]
+    fn test_point_outside_non_convex() {
+        let poly = u_shape();
+        // Inside the notch
+        assert!(!point_in_polygon(Point2f::new(150.0, 100.0), &poly));
+        assert!(!point_in_polygon(Point2f::new(400.0, 100.0), &poly));
+        assert!(!point_in_polygon(Point2f::new(-1.0, 150.0), &poly));
+    }
+
+    #[test]
+    fn test_point_on_boundary() {
+        let poly = u_shape();
+        assert!(point_in_polygon(Point2f::new(150.0, 200.0), &poly));
+        assert!(point_in_polygon(Point2f::new(100.0, 100.0), &poly));
+        assert!(point_in_polygon(Point2f::new(0.0, 0.0), &poly));
+    }
+
+    #[test]
+    fn test_roi_filter_reference_point() {
+        let dets = vec![
+            // Center at (150, 150) is in the notch, bottom-center (150, 250) is not
+            Detection::new(SVector::<f32, 4>::new(125.0, 50.0, 50.0, 200.0), 0.9, 0, None),
+            Detection::new(SVector::<f32, 4>::new(25.0, 25.0, 50.0, 50.0), 0.9, 0, None),
+        ];
+
+        let center = RoiFilter::new(u_shape());
+        assert_eq!(center.filter(dets.clone()).len(), 1);
+
+        let bottom = RoiFilter::new(u_shape()).with_reference(ReferencePoint::BottomCenter);
+        assert_eq!(bottom.filter(dets).len(), 2);
+    }
+
+    #[test]
+    fn test_mask_filter_drops_detections_on_black() {
+        // Left half black, right half white, at half the frame resolution
+        let mut mask = Mat::new_rows_cols_with_default(100, 200, CV_8UC1, opencv::core::Scalar::all(0.0)).unwrap();
+        imgproc::rectangle(
+            &mut mask,
+            opencv::core::Rect::new(100, 0, 100, 100),
+            opencv::core::Scalar::all(255.0),
+            -1,
+            imgproc::LINE_8,
+            0,
+        ).unwrap();
+        let mut filter = MaskFilter::new(mask).unwrap();
+
+        let dets = vec![
+            Detection::new(SVector::<f32, 4>::new(75.0, 50.0, 50.0, 100.0), 0.9, 0, None),
+            Detection::new(SVector::<f32, 4>::new(275.0, 50.0, 50.0, 100.0), 0.9, 0, None),
+            // Centered outside the frame
+            Detection::new(SVector::<f32, 4>::new(390.0, 50.0, 50.0, 100.0), 0.9, 0, None),
+        ];
+        let kept = filter.filter(dets, Size::new(400, 200)).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].tlwh[0], 275.0);
+        assert!(filter.resized.is_some());
+
+        let color = Mat::new_rows_cols_with_default(10, 10, opencv::core::CV_8UC3, opencv::core::Scalar::all(255.0)).unwrap();
+        assert!(MaskFilter::new(color).is_err());
+    }
+
+    #[test]
+    fn test_size_prior_rejects_giant_box_high_in_frame() {
+        // 40 px tall people at row 200, 200 px tall at row 700
+        let prior = SizePrior::new((200.0, 40.0), (700.0, 200.0));
+        prior.validate().unwrap();
+        assert_eq!(prior.expected_height(450.0), 120.0);
+
+        let dets = vec![
+            // Bottom edge at row 250, expected 56 px tall: a 300 px box is implausible
+            Detection::new(SVector::<f32, 4>::new(100.0, -50.0, 120.0, 300.0), 0.9, 0, None),
+            // Bottom edge at row 250 with a fitting 60 px height
+            Detection::new(SVector::<f32, 4>::new(300.0, 190.0, 25.0, 60.0), 0.9, 0, None),
+            // Bottom edge at row 700 with the expected height
+            Detection::new(SVector::<f32, 4>::new(500.0, 500.0, 80.0, 200.0), 0.9, 0, None),
+        ];
+        let kept = prior.filter(dets);
+        assert_eq!(kept.len(), 2);
+        assert_eq!((kept[0].tlwh[0], kept[1].tlwh[0]), (300.0, 500.0));
+
+        assert!(SizePrior::new((200.0, 40.0), (200.0, 80.0)).validate().is_err());
+        assert!(SizePrior::new((200.0, 40.0), (700.0, 200.0)).with_tolerance(0.5).validate().is_err());
+    }
+}
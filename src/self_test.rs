@@ -0,0 +1,191 @@
+use std::fmt;
+use std::path::Path;
+use tch::{Kind, Tensor};
+use crate::config::Config;
+use crate::detection::Detector;
+
+/// Outcome of one self-test check
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    /// Not run because an earlier check it depends on failed
+    Skipped,
+}
+
+/// One line of the self-test report
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+impl CheckResult {
+    fn pass(name: &'static str, message: String) -> Self {
+        CheckResult { name, status: CheckStatus::Pass, message }
+    }
+
+    fn fail(name: &'static str, message: String) -> Self {
+        CheckResult { name, status: CheckStatus::Fail, message }
+    }
+
+    fn skipped(name: &'static str) -> Self {
+        CheckResult { name, status: CheckStatus::Skipped, message: "skipped after an earlier failure".to_string() }
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tag = match self.status {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skipped => "SKIP",
+        };
+        write!(f, "[{}] {}: {}", tag, self.name, self.message)
+    }
+}
+
+/// Validate a setup end to end: load the config, check the requested device, load the model
+/// (optionally from `model_override` instead of the config's `model_path`) and run one dummy
+/// inference to identify the output format. Checks after a failure are reported as skipped.
+pub fn run_checks(config_path: &str, model_override: Option<&str>) -> Vec<CheckResult> {
+    let mut config = match Config::from_file(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![
+                CheckResult::fail("config", format!("could not load {}: {}", config_path, e)),
+                CheckResult::skipped("device"),
+                CheckResult::skipped("model"),
+                CheckResult::skipped("inference"),
+            ];
+        }
+    };
+    if let Some(model_path) = model_override {
+        config.model_path = model_path.to_string();
+    }
+    let mut report = vec![CheckResult::pass("config", format!("loaded {}", config_path))];
+
+    report.push(check_device(&config.device));
+
+    let detector = if !Path::new(&config.model_path).exists() {
+        report.push(CheckResult::fail(
+            "model",
+            format!("model file {:?} not found; set model_path in the config or pass --weights", config.model_path),
+        ));
+        None
+    } else {
+        match Detector::from_config(&config) {
+            Ok(detector) => {
+                report.push(CheckResult::pass("model", format!("loaded {}", config.model_path)));
+                Some(detector)
+            }
+            Err(e) => {
+                report.push(CheckResult::fail(
+                    "model",
+                    format!("could not load {:?} as a TorchScript module ({}); export it with torch.jit.trace", config.model_path, e),
+                ));
+                None
+            }
+        }
+    };
+
+    report.push(match detector {
+        Some(detector) => check_inference(&detector, config.input_size),
+        None => CheckResult::skipped("inference"),
+    });
+    report
+}
+
+/// `true` when no check failed
+pub fn all_passed(report: &[CheckResult]) -> bool {
+    report.iter().all(|check| check.status == CheckStatus::Pass)
+}
+
+fn check_device(device: &str) -> CheckResult {
+    match crate::utils::parse_cuda_index(device) {
+        Ok(None) => CheckResult::pass("device", "cpu".to_string()),
+        Ok(Some(index)) => {
+            let count = if tch::Cuda::is_available() { tch::Cuda::device_count() as usize } else { 0 };
+            if index < count {
+                CheckResult::pass("device", format!("cuda:{} ({} device(s))", index, count))
+            } else {
+                CheckResult::fail(
+                    "device",
+                    format!("{} requested but libtorch sees {} CUDA device(s); inference would silently run on the CPU", device, count),
+                )
+            }
+        }
+        Err(e) => CheckResult::fail("device", e.to_string()),
+    }
+}
+
+/// Whether a libtorch error is about tensors or weights on different devices
+fn is_device_mismatch(message: &str) -> bool {
+    ["same device", "Expected object of device", "device type", "to be on the same"]
+        .iter()
+        .any(|needle| message.contains(needle))
+}
+
+fn check_inference(detector: &Detector, input_size: [i32; 2]) -> CheckResult {
+    let input = Tensor::zeros(&[1, 3, input_size[1] as i64, input_size[0] as i64], (Kind::Float, detector.device()));
+    match detector.inference(&input) {
+        Ok(output) => {
+            let shape = output.size();
+            match shape.as_slice() {
+                [1, boxes, 85] => CheckResult::pass("inference", format!("raw YOLOv7 output, {} candidate boxes", boxes)),
+                [1, boxes, 6] => CheckResult::pass("inference", format!("post-NMS [x, y, w, h, conf, cls] output, {} boxes", boxes)),
+                _ => CheckResult::fail(
+                    "inference",
+                    format!("unsupported output shape {:?}; expected [1, N, 85] or [1, N, 6]", shape),
+                ),
+            }
+        }
+        Err(e) if is_device_mismatch(&e.to_string()) => CheckResult::fail(
+            "inference",
+            format!("model weights are not on {:?}, where its inputs are: {}; re-export the model or change device", detector.device(), e),
+        ),
+        Err(e) => CheckResult::fail(
+            "inference",
+            format!("forward pass on a {}x{} input failed: {}; check input_size against the exported model", input_size[0], input_size[1], e),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes_with_valid_setup() {
+        let report = run_checks("config.json", Some("weights/yolov7.torchscript"));
+        let report = report.into_iter()
+            .filter(|check| !(check.name == "device" && check.message.contains("CUDA device(s)")))
+            .collect::<Vec<_>>();
+        assert!(all_passed(&report), "{:#?}", report);
+        assert_eq!(report.last().unwrap().name, "inference");
+    }
+
+    #[test]
+    fn test_device_mismatch_reported_separately() {
+        assert!(is_device_mismatch(
+            "Expected all tensors to be on the same device, but found at least two devices, cuda:0 and cpu!"
+        ));
+        assert!(is_device_mismatch("Expected object of device type cuda but got device type cpu for argument #1"));
+        assert!(!is_device_mismatch("Given groups=1, weight of size [32, 3, 3, 3], expected input[1, 4, 640, 640] to have 3 channels"));
+    }
+
+    #[test]
+    fn test_self_test_reports_missing_model() {
+        let report = run_checks("config.json", Some("weights/does_not_exist.pt"));
+        let model = report.iter().find(|check| check.name == "model").unwrap();
+        assert_eq!(model.status, CheckStatus::Fail);
+        assert!(model.message.contains("does_not_exist.pt") && model.message.contains("not found"));
+        assert_eq!(report.last().unwrap().status, CheckStatus::Skipped);
+        assert!(!all_passed(&report));
+
+        let report = run_checks("no_such_config.json", None);
+        assert_eq!(report[0].status, CheckStatus::Fail);
+        assert!(report[1..].iter().all(|check| check.status == CheckStatus::Skipped));
+    }
+}
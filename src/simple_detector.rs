@@ -7,6 +7,9 @@ use opencv::{
 use serde::{Serialize, Deserialize};
 use tch::{Device, Kind, Tensor};
 use std::collections::HashMap;
+use log::{debug, log_enabled, trace, warn, Level};
+use rayon::prelude::*;
+use crate::utils::YOLO_ROW_LEN;
 
 /// Simple detection result structure that matches Python output format
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,6 +41,37 @@ pub struct SimpleTrack {
     pub class_name: Option<String>,
 }
 
+/// One entry of a COCO detection `results.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CocoResult {
+    pub image_id: i64,
+    pub category_id: i64,
+    /// [x, y, width, height] in pixels
+    pub bbox: [f32; 4],
+    pub score: f32,
+}
+
+/// Detections as a COCO `results.json` array for COCO evaluation tools. `image_ids[i]` is
+/// the COCO image id of `results[i]`; class ids are mapped to COCO category ids and
+/// detections with classes outside the COCO table are skipped.
+pub fn to_coco_json(results: &[SimpleFrameResult], image_ids: &[i64]) -> String {
+    assert_eq!(results.len(), image_ids.len(), "need one image id per frame result");
+    let entries: Vec<CocoResult> = results.iter()
+        .zip(image_ids)
+        .flat_map(|(result, &image_id)| {
+            result.detections.iter().filter_map(move |det| {
+                Some(CocoResult {
+                    image_id,
+                    category_id: crate::utils::coco_category_id(det.class_id)?,
+                    bbox: det.bbox,
+                    score: det.confidence,
+                })
+            })
+        })
+        .collect();
+    serde_json::to_string(&entries).expect("COCO results serialize")
+}
+
 /// Simple detector that focuses only on producing detection outputs similar to Python
 pub struct SimpleDetector {
     model: tch::CModule,
@@ -47,6 +81,49 @@ pub struct SimpleDetector {
     pub nms_threshold: f32,
     pub allowed_classes: Vec<i32>,
     pub class_names: HashMap<i32, String>,
+    /// Let boxes of any class suppress each other in NMS (one box per object)
+    pub class_agnostic_nms: bool,
+    /// Model weights and inputs are fp16; see `set_half`
+    half: bool,
+}
+
+/// Decode one raw YOLOv7 row (index `i`) into `(x1, y1, x2, y2, obj_conf, class_id)`
+/// in model input coordinates, or `None` if it fails the confidence or class filter.
+fn decode_row(i: usize, row: &[f32], conf_threshold: f32, allowed_classes: &[i32]) -> Option<(f32, f32, f32, f32, f32, i32)> {
+    let obj_conf_raw = row[4];
+    let obj_conf_prob = 1.0 / (1.0 + (-obj_conf_raw).exp()); // Apply sigmoid to objectness score
+
+    let mut max_cls_prob = 0.0f32;
+    let mut class_id_for_this_box = 0i32;
+    for (c, &cls_logit) in row[5..YOLO_ROW_LEN].iter().enumerate() { // 80 classes
+        let cls_prob = 1.0 / (1.0 + (-cls_logit).exp()); // Sigmoid on class score
+        if cls_prob > max_cls_prob {
+            max_cls_prob = cls_prob;
+            class_id_for_this_box = c as i32;
+        }
+    }
+
+    // Debug print for the first 10 boxes and any box where objectness_prob is somewhat high (e.g. > 0.1 after sigmoid)
+    if i < 10 || obj_conf_prob > 0.1 {
+        trace!(
+            "Debug Box Idx {}: raw_obj={:.4}, sig_obj={:.4}, cls_id={}, max_cls_prob={:.4}, combined_prob={:.4}",
+            i, obj_conf_raw, obj_conf_prob, class_id_for_this_box, max_cls_prob, obj_conf_prob * max_cls_prob
+        );
+    }
+
+    // Filter by objectness confidence
+    if obj_conf_prob < conf_threshold {
+        return None;
+    }
+
+    // Filter by allowed classes
+    if !allowed_classes.contains(&class_id_for_this_box) {
+        return None;
+    }
+
+    // Raw coordinates from model output (center_x, center_y, width, height)
+    let (cx, cy, w, h) = (row[0], row[1], row[2], row[3]);
+    Some((cx - w / 2.0, cy - h / 2.0, cx + w / 2.0, cy + h / 2.0, obj_conf_prob, class_id_for_this_box))
 }
 
 impl SimpleDetector {
@@ -59,25 +136,16 @@ impl SimpleDetector {
         nms_threshold: f32,
     ) -> Result<Self> {
         // Set device
-        let device = if device_str == "cuda" && tch::Cuda::is_available() {
-            Device::Cuda(0)
-        } else {
-            Device::Cpu
-        };
+        let device = crate::utils::resolve_device(device_str);
         
         // Load model
         let model = tch::CModule::load(model_path)?;
         
-        // Define default class names for COCO dataset
-        let mut class_names = HashMap::new();
-        class_names.insert(0, "person".to_string());
-        class_names.insert(1, "bicycle".to_string());
-        class_names.insert(2, "car".to_string());
-        class_names.insert(3, "motorcycle".to_string());
-        class_names.insert(5, "bus".to_string());
-        class_names.insert(7, "truck".to_string());
-        class_names.insert(15, "cat".to_string());
-        class_names.insert(16, "dog".to_string());
+        // Default class names for the allowed COCO classes
+        let allowed_classes = vec![0, 1, 2, 3, 5, 7, 15, 16];
+        let class_names: HashMap<i32, String> = allowed_classes.iter()
+            .filter_map(|&id| crate::utils::coco_class_name(id).map(|name| (id, name.to_string())))
+            .collect();
         
         Ok(SimpleDetector {
             model,
@@ -85,23 +153,46 @@ impl SimpleDetector {
             input_size,
             conf_threshold,
             nms_threshold,
-            allowed_classes: vec![0, 1, 2, 3, 5, 7, 15, 16],
+            allowed_classes,
             class_names,
+            class_agnostic_nms: false,
+            half: false,
         })
     }
     
-    /// Set allowed classes
+    /// Set allowed classes, naming any COCO class not named yet
     pub fn set_allowed_classes(&mut self, classes: Vec<i32>) {
+        for &id in &classes {
+            if let Some(name) = crate::utils::coco_class_name(id) {
+                self.class_names.entry(id).or_insert_with(|| name.to_string());
+            }
+        }
         self.allowed_classes = classes;
     }
     
+    /// Run the model in fp16 on CUDA, casting the output back to fp32 before
+    /// postprocessing. A no-op (with a warning) on the CPU.
+    pub fn set_half(&mut self, half: bool) {
+        let half = crate::utils::resolve_half(half, self.device);
+        if half != self.half {
+            let kind = if half { Kind::Half } else { Kind::Float };
+            self.model.to(self.device, kind, false);
+            self.half = half;
+        }
+    }
+
+    /// Whether inference runs in fp16
+    pub fn is_half(&self) -> bool {
+        self.half
+    }
+
     /// Process a frame and return detections
     pub fn process_frame(&self, frame: &Mat, frame_id: i32) -> Result<SimpleFrameResult> {
         // Preprocess the frame
         let input_tensor = self.preprocess(frame)?;
         
         // Run inference
-        let output = self.model.forward_ts(&[&input_tensor])?;
+        let output = self.forward(&input_tensor)?;
         
         // Post-process to get detections
         let detections = self.postprocess(&output, frame)?;
@@ -116,8 +207,33 @@ impl SimpleDetector {
         Ok(frame_result)
     }
     
+    /// Run the model, in fp16 when `set_half` enabled it, returning fp32 output
+    fn forward(&self, input: &Tensor) -> Result<Tensor> {
+        if self.half {
+            return Ok(self.model.forward_ts(&[input.to_kind(Kind::Half)])?.to_kind(Kind::Float));
+        }
+        Ok(self.model.forward_ts(&[input])?)
+    }
+
+    /// Run `iters` forward passes on a zero tensor of the input size so lazy CUDA
+    /// initialization does not land on the first real frame, like `Detector::warmup`.
+    pub fn warmup(&self, iters: usize) -> Result<()> {
+        let input = Tensor::zeros(&[1, 3, self.input_size.1, self.input_size.0], (Kind::Float, self.device));
+        for _ in 0..iters {
+            self.forward(&input)?;
+        }
+        if let Device::Cuda(index) = self.device {
+            tch::Cuda::synchronize(index as i64);
+        }
+        Ok(())
+    }
+
     /// Preprocess a frame for inference
     fn preprocess(&self, frame: &Mat) -> Result<Tensor> {
+        // Grayscale, BGRA and 16-bit frames are brought to 8-bit BGR first
+        let converted = crate::detection::to_bgr8(frame)?;
+        let frame = converted.as_ref().unwrap_or(frame);
+
         // Get frame dimensions
         let orig_height = frame.rows() as f32;
         let orig_width = frame.cols() as f32;
@@ -173,7 +289,7 @@ impl SimpleDetector {
         // Copy to CPU for processing
         let cpu_output = output.to_device(Device::Cpu);
         
-        println!("Output tensor shape: {:?}", cpu_output.size());
+        debug!("Output tensor shape: {:?}", cpu_output.size());
         
         let mut final_detections = Vec::new(); // Renamed from detections to avoid confusion
         
@@ -181,118 +297,40 @@ impl SimpleDetector {
         if cpu_output.size().len() == 3 && cpu_output.size()[2] == 85 {
             let num_potential_boxes = cpu_output.size()[1];
             
-            println!("Processing {} potential boxes from YOLOv7 output", num_potential_boxes);
+            debug!("Processing {} potential boxes from YOLOv7 output", num_potential_boxes);
             
-            // Store intermediate detections: (x1, y1, x2, y2, obj_conf, class_id)
-            // Coordinates are relative to model input size (e.g., 640x640)
-            let mut pre_nms_detections: Vec<(f32, f32, f32, f32, f32, i32)> = Vec::new();
+            // One host copy of the boxes instead of an FFI call per scalar
+            let rows = crate::utils::first_batch_rows(&cpu_output)?;
 
             // For debugging: print top raw objectness scores
-            let mut raw_scores_for_debug = Vec::new();
-            for i in 0..num_potential_boxes {
-                raw_scores_for_debug.push(cpu_output.get(0).get(i).get(4).double_value(&[]) as f32);
+            if log_enabled!(Level::Trace) {
+                let mut raw_scores_for_debug: Vec<f32> = rows.chunks_exact(YOLO_ROW_LEN).map(|row| row[4]).collect();
+                raw_scores_for_debug.sort_by(|a, b| b.partial_cmp(a).unwrap());
+                trace!("Top 10 RAW objectness scores from tensor: {:?}", raw_scores_for_debug.iter().take(10).collect::<Vec<_>>());
             }
-            raw_scores_for_debug.sort_by(|a, b| b.partial_cmp(a).unwrap());
-            println!("Top 10 RAW objectness scores from tensor: {:?}", raw_scores_for_debug.iter().take(10).collect::<Vec<_>>());
-
-            for i in 0..num_potential_boxes {
-                let obj_conf_raw = cpu_output.get(0).get(i).get(4).double_value(&[]) as f32;
-                let obj_conf_prob = 1.0 / (1.0 + (-obj_conf_raw).exp()); // Apply sigmoid to objectness score
-
-                let mut max_cls_prob = 0.0f32;
-                let mut class_id_for_this_box = 0i32;
-                for c in 0..80 { // Assuming 80 classes
-                    let cls_logit = cpu_output.get(0).get(i).get(5 + c).double_value(&[]) as f32;
-                    let cls_prob = 1.0 / (1.0 + (-cls_logit).exp()); // Sigmoid on class score
-                    if cls_prob > max_cls_prob {
-                        max_cls_prob = cls_prob;
-                        class_id_for_this_box = c as i32;
-                    }
-                }
-
-                // Debug print for the first 10 boxes and any box where objectness_prob is somewhat high (e.g. > 0.1 after sigmoid)
-                if i < 10 || obj_conf_prob > 0.1 {
-                     println!(
-                        "Debug Box Idx {}: raw_obj={:.4}, sig_obj={:.4}, cls_id={}, max_cls_prob={:.4}, combined_prob={:.4}",
-                        i, obj_conf_raw, obj_conf_prob, class_id_for_this_box, max_cls_prob, obj_conf_prob * max_cls_prob
-                    );
-                }
-
-                // Filter by objectness confidence
-                if obj_conf_prob < self.conf_threshold {
-                    continue;
-                }
-                
-                // Filter by allowed classes
-                if !self.allowed_classes.contains(&class_id_for_this_box) {
-                    continue;
-                }
-
-                // Raw coordinates from model output (center_x, center_y, width, height)
-                let cx = cpu_output.get(0).get(i).get(0).double_value(&[]) as f32;
-                let cy = cpu_output.get(0).get(i).get(1).double_value(&[]) as f32;
-                let w = cpu_output.get(0).get(i).get(2).double_value(&[]) as f32;
-                let h = cpu_output.get(0).get(i).get(3).double_value(&[]) as f32;
-
-                let x1 = cx - w / 2.0;
-                let y1 = cy - h / 2.0;
-                let x2 = cx + w / 2.0;
-                let y2 = cy + h / 2.0;
 
-                pre_nms_detections.push((x1, y1, x2, y2, obj_conf_prob, class_id_for_this_box));
-            }
+            // Intermediate detections: (x1, y1, x2, y2, obj_conf, class_id)
+            // Coordinates are relative to model input size (e.g., 640x640)
+            let pre_nms_detections: Vec<(f32, f32, f32, f32, f32, i32)> = rows.par_chunks_exact(YOLO_ROW_LEN)
+                .enumerate()
+                .filter_map(|(i, row)| decode_row(i, row, self.conf_threshold, &self.allowed_classes))
+                .collect();
             
-            println!("Found {} detections after initial confidence and class filtering (before NMS)", pre_nms_detections.len());
+            debug!("Found {} detections after initial confidence and class filtering (before NMS)", pre_nms_detections.len());
 
-            // Sort by objectness confidence (descending) for NMS
-            pre_nms_detections.sort_by(|a, b| b.4.partial_cmp(&a.4).unwrap());
-
-            // Apply NMS
-            let mut nms_selected_indices = Vec::new();
-            let mut used_indices = vec![false; pre_nms_detections.len()];
-
-            for i in 0..pre_nms_detections.len() {
-                if used_indices[i] {
-                    continue;
-                }
-                nms_selected_indices.push(i);
-                used_indices[i] = true; // Mark as used
-
-                let (x1_i, y1_i, x2_i, y2_i, _, cls_i) = pre_nms_detections[i];
-                let area_i = (x2_i - x1_i).max(0.0) * (y2_i - y1_i).max(0.0);
-
-                for j in (i + 1)..pre_nms_detections.len() {
-                    if used_indices[j] {
-                        continue;
-                    }
-                    let (x1_j, y1_j, x2_j, y2_j, _, cls_j) = pre_nms_detections[j];
-
-                    if cls_i != cls_j {
-                        continue;
-                    }
-
-                    let inter_x1 = x1_i.max(x1_j);
-                    let inter_y1 = y1_i.max(y1_j);
-                    let inter_x2 = x2_i.min(x2_j);
-                    let inter_y2 = y2_i.min(y2_j);
-
-                    let inter_w = (inter_x2 - inter_x1).max(0.0);
-                    let inter_h = (inter_y2 - inter_y1).max(0.0);
-                    let inter_area = inter_w * inter_h;
-                    
-                    let area_j = (x2_j - x1_j).max(0.0) * (y2_j - y1_j).max(0.0);
-                    let union_area = area_i + area_j - inter_area;
-
-                    if union_area > 0.0 {
-                        let iou = inter_area / union_area;
-                        if iou > self.nms_threshold { 
-                            used_indices[j] = true;
-                        }
-                    }
-                }
-            }
+            // Apply NMS on (x, y, w, h) boxes
+            let boxes: Vec<[f32; 4]> = pre_nms_detections.iter()
+                .map(|&(x1, y1, x2, y2, _, _)| [x1, y1, x2 - x1, y2 - y1])
+                .collect();
+            let scores: Vec<f32> = pre_nms_detections.iter().map(|d| d.4).collect();
+            let class_ids: Vec<i32> = pre_nms_detections.iter().map(|d| d.5).collect();
+            let nms_selected_indices = if self.class_agnostic_nms {
+                crate::utils::nms(&boxes, &scores, self.nms_threshold)
+            } else {
+                crate::utils::nms_per_class(&boxes, &scores, &class_ids, self.nms_threshold)
+            };
             
-            println!("Kept {} detections after NMS", nms_selected_indices.len());
+            debug!("Kept {} detections after NMS", nms_selected_indices.len());
 
             for &idx in &nms_selected_indices {
                 let (x1_model, y1_model, x2_model, y2_model, obj_conf_prob, class_id) = pre_nms_detections[idx];
@@ -300,15 +338,16 @@ impl SimpleDetector {
                 let scale_w = frame_width / model_input_width;
                 let scale_h = frame_height / model_input_height;
 
-                let final_x1 = x1_model * scale_w;
-                let final_y1 = y1_model * scale_h;
-                let final_x2 = x2_model * scale_w;
-                let final_y2 = y2_model * scale_h;
+                // Clamp to the frame; boxes that collapse are dropped below
+                let final_x1 = (x1_model * scale_w).max(0.0).min(frame_width);
+                let final_y1 = (y1_model * scale_h).max(0.0).min(frame_height);
+                let final_x2 = (x2_model * scale_w).max(0.0).min(frame_width);
+                let final_y2 = (y2_model * scale_h).max(0.0).min(frame_height);
                 
                 let final_w = (final_x2 - final_x1).max(0.0);
                 let final_h = (final_y2 - final_y1).max(0.0);
 
-                if final_w * final_h < 10.0 { 
+                if final_w * final_h < crate::utils::MIN_BOX_AREA { 
                     continue;
                 }
                 if final_w <= 0.0 || final_h <= 0.0 {
@@ -325,11 +364,58 @@ impl SimpleDetector {
                 });
             }
         } else {
-            println!("Unexpected output tensor shape: {:?}", cpu_output.size());
+            warn!("Unexpected output tensor shape: {:?}", cpu_output.size());
         }
         
-        println!("Found {} high confidence detections after all processing", final_detections.iter().filter(|d| d.confidence > 0.5).count());
-        println!("Returning {} final detections", final_detections.len());
+        debug!("Found {} high confidence detections after all processing", final_detections.iter().filter(|d| d.confidence > 0.5).count());
+        debug!("Returning {} final detections", final_detections.len());
         Ok(final_detections)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warmup_then_detect() {
+        let detector = SimpleDetector::new("weights/yolov7.torchscript", "cpu", (320, 320), 0.25, 0.45).unwrap();
+        detector.warmup(2).unwrap();
+
+        let frame = Mat::new_rows_cols_with_default(480, 640, opencv::core::CV_8UC3, opencv::core::Scalar::all(127.0)).unwrap();
+        assert!(detector.process_frame(&frame, 0).is_ok());
+    }
+
+    #[test]
+    fn test_preprocess_gray_and_bgra_frames() {
+        let detector = SimpleDetector::new("weights/yolov7.torchscript", "cpu", (640, 640), 0.25, 0.45).unwrap();
+
+        let gray = Mat::new_rows_cols_with_default(512, 640, opencv::core::CV_8UC1, opencv::core::Scalar::all(200.0)).unwrap();
+        let bgra = Mat::new_rows_cols_with_default(720, 1280, opencv::core::CV_8UC4, opencv::core::Scalar::new(255.0, 0.0, 0.0, 128.0)).unwrap();
+        for frame in [gray, bgra] {
+            let tensor = detector.preprocess(&frame).unwrap();
+            assert_eq!(tensor.size(), &[1, 3, 640, 640]);
+            assert!(tensor.min().double_value(&[]) >= 0.0);
+            assert!(tensor.max().double_value(&[]) <= 1.0);
+            assert!(detector.process_frame(&frame, 0).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_to_coco_json_entry() {
+        let result = SimpleFrameResult {
+            frame_id: 0,
+            detections: vec![
+                SimpleDetection { bbox: [10.0, 20.0, 30.0, 40.0], confidence: 0.5, class_id: 2, class_name: None },
+                SimpleDetection { bbox: [0.0, 0.0, 1.0, 1.0], confidence: 0.9, class_id: 99, class_name: None },
+            ],
+            tracks: Vec::new(),
+        };
+        let json = to_coco_json(&[result], &[139]);
+
+        let expected = r#"[{"image_id":139,"category_id":3,"bbox":[10.0,20.0,30.0,40.0],"score":0.5}]"#;
+        assert_eq!(json, expected);
+        let parsed: Vec<CocoResult> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[0].category_id, 3);
+    }
+}
@@ -2,23 +2,123 @@
 
 use nalgebra::{SVector, SMatrix, DMatrix};
 use opencv::{
-    core::{Mat, Point2f, Size, TermCriteria, Device,CV_8UC3},
+    core::{Mat, Point2f, Size, TermCriteria, Device, Vector, KeyPoint, DMatch, NORM_HAMMING, CV_8UC3},
+    features2d::{BFMatcher, ORB, ORB_ScoreType},
     imgproc,
     video::{self, calc_optical_flow_pyr_lk},
     prelude::*,
 };
+use serde::{Serialize, Deserialize};
 use std::f32;
-use std::time::Instant;
+use std::collections::HashMap;
 use crate::detection::Detection;
 
-#[derive(Debug, Clone)]
+/// Maximum number of boxes kept in a track's motion trail
+const MAX_TRAIL_LEN: usize = 30;
+
+/// Lowest duplicate IoU in crowd mode, where distinct people legitimately overlap
+const CROWD_DUPLICATE_IOU: f32 = 0.85;
+
+/// Fraction of an unmatched track's box that a matched track must cover for it to count as
+/// occluded rather than lost
+const OCCLUSION_COVERAGE: f32 = 0.5;
+
+/// Occluded tracks are kept this many times longer than lost ones before removal
+const OCCLUDED_HOLD_FACTOR: i32 = 2;
+
+/// Weight of the previous value in a track's running innovation average
+const INNOVATION_EMA_DECAY: f32 = 0.8;
+
+/// Innovation, relative to box height, at which adaptive process noise is unscaled
+const ADAPTIVE_NOISE_REFERENCE: f32 = 0.05;
+
+/// Bounds of the adaptive velocity process-noise scale
+const ADAPTIVE_NOISE_SCALE_RANGE: (f32, f32) = (0.5, 4.0);
+
+fn default_innovation_ema() -> f32 {
+    ADAPTIVE_NOISE_REFERENCE
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TrackState {
     New,
     Tracked,
     Lost,
+    /// Unmatched while another track covers its predicted box; likely still in view behind it
+    Occluded,
     Removed,
 }
 
+/// Track lifecycle transitions reported by `SMILEtrack::update`, in the order they happened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackEvent {
+    /// A new track ID was assigned
+    Created { id: u32, class: i32 },
+    /// The track reached `n_init` matches and is now reported
+    Confirmed { id: u32 },
+    /// A confirmed track went unmatched and is coasting on prediction
+    Lost { id: u32 },
+    /// A confirmed track went unmatched behind another track and is held on prediction
+    Occluded { id: u32 },
+    /// The track was dropped: never confirmed, lost for too long, or a duplicate
+    Removed { id: u32 },
+    /// A lost or occluded track was matched again, or a removed one was revived from the
+    /// re-ID gallery; the ID is reported again from this frame
+    Refound { id: u32 },
+}
+
+/// Recently removed tracks kept for their appearance features, so an object that vanishes
+/// (e.g. across a camera cut) and reappears can get its old ID back
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReidGallery {
+    /// Removed tracks with the frame they were removed at, oldest first
+    entries: Vec<(STrack, i32)>,
+    /// Frames an entry is kept after removal
+    window: i32,
+}
+
+impl ReidGallery {
+    pub fn new(window: usize) -> Self {
+        ReidGallery { entries: Vec::new(), window: window as i32 }
+    }
+
+    /// Remember a removed track; tracks without features are ignored
+    pub fn insert(&mut self, track: STrack, frame_id: i32) {
+        if !track.features.is_empty() {
+            self.entries.push((track, frame_id));
+        }
+    }
+
+    /// Forget tracks removed more than `window` frames before `frame_id`
+    pub fn prune(&mut self, frame_id: i32) {
+        let window = self.window;
+        self.entries.retain(|(_, removed_at)| frame_id - removed_at <= window);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Take out the track whose features best match `feature`, if any is within
+    /// `max_distance` (cosine distance)
+    pub fn take_match(&mut self, feature: &[f32], max_distance: f32) -> Option<STrack> {
+        let mut best: Option<(usize, f32)> = None;
+        for (i, (track, _)) in self.entries.iter().enumerate() {
+            let dist = track.features.iter()
+                .map(|f| crate::utils::cosine_distance(f, feature))
+                .fold(f32::MAX, f32::min);
+            if dist <= max_distance && best.map_or(true, |(_, d)| dist < d) {
+                best = Some((i, dist));
+            }
+        }
+        best.map(|(i, _)| self.entries.remove(i).0)
+    }
+}
+
 /// Kalman filter wrapper (port from tracker/kalman_filter.py)
 pub struct KalmanFilter {
     motion_mat: DMatrix<f32>,   // 8×8 motion matrix
@@ -26,6 +126,11 @@ pub struct KalmanFilter {
     std_weight_position: f32,
     std_weight_velocity: f32,
 }
+impl Default for KalmanFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl KalmanFilter {
     /// Initialize motion and update matrices.
     pub fn new() -> Self {
@@ -69,6 +174,17 @@ impl KalmanFilter {
         &self,
         mean: &SVector<f32, 8>,
         covariance: &SMatrix<f32, 8, 8>,
+    ) -> (SVector<f32, 8>, SMatrix<f32, 8, 8>) {
+        self.predict_scaled(mean, covariance, 1.0)
+    }
+
+    /// Predict step with the velocity process-noise standard deviations multiplied by
+    /// `velocity_noise_scale`
+    pub fn predict_scaled(
+        &self,
+        mean: &SVector<f32, 8>,
+        covariance: &SMatrix<f32, 8, 8>,
+        velocity_noise_scale: f32,
     ) -> (SVector<f32, 8>, SMatrix<f32, 8, 8>) {
         // build process noise covariance Q
         let std_pos = SVector::<f32, 4>::from_iterator([
@@ -82,7 +198,7 @@ impl KalmanFilter {
             self.std_weight_velocity * mean[3],
             self.std_weight_velocity * mean[2],
             self.std_weight_velocity * mean[3],
-        ]);
+        ]) * velocity_noise_scale;
         
         // Combine position and velocity uncertainties
         let mut q_vec = SVector::<f32, 8>::zeros();
@@ -193,6 +309,17 @@ impl KalmanFilter {
         covariance: &SMatrix<f32, 8, 8>,
         measurement: &SVector<f32, 4>,
     ) -> (SVector<f32, 8>, SMatrix<f32, 8, 8>) {
+        let (new_mean, new_cov, _) = self.update_with_innovation(mean, covariance, measurement);
+        (new_mean, new_cov)
+    }
+
+    /// `update`, also returning the innovation (measurement minus projected prediction)
+    pub fn update_with_innovation(
+        &self,
+        mean: &SVector<f32, 8>,
+        covariance: &SMatrix<f32, 8, 8>,
+        measurement: &SVector<f32, 4>,
+    ) -> (SVector<f32, 8>, SMatrix<f32, 8, 8>, SVector<f32, 4>) {
         let (projected_mean, projected_cov) = self.project(mean, covariance);
         
         // Compute Kalman gain using matrix operations
@@ -247,7 +374,7 @@ impl KalmanFilter {
         let i_kh = SMatrix::<f32, 8, 8>::identity() - &k_matrix * &self.update_mat;
         let new_cov = &i_kh * covariance * &i_kh.transpose() + &k_matrix * projected_cov * &k_matrix.transpose();
         
-        (new_mean.clone(), new_cov.clone())
+        (new_mean.clone(), new_cov.clone(), innovation)
     }
 
     /// Chi-square 0.95 inverse cumulative distribution for [1-4] DOF
@@ -263,7 +390,7 @@ impl KalmanFilter {
 }
 
 /// Single Object Tracker
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct STrack {
     /// Track state vector (x,y,w,h,vx,vy,vw,vh)
     mean: SVector<f32, 8>,
@@ -271,6 +398,9 @@ pub struct STrack {
     covariance: SMatrix<f32, 8, 8>,
     /// Bounding box in (tlwh) format
     pub tlwh: SVector<f32, 4>,
+    /// Raw box of the latest associated detection, before filtering or smoothing
+    #[serde(default)]
+    last_det_tlwh: SVector<f32, 4>,
     /// Track score from detector
     pub score: f32,
     /// Track ID (assigned by tracker)
@@ -285,18 +415,43 @@ pub struct STrack {
     start_frame: i32,
     /// Frames since last update
     tracklet_len: i32,
+    /// Number of frames this track has been matched (including the one that created it)
+    #[serde(default)]
+    hits: u32,
     /// Track features for re-ID (optional)
     features: Vec<Vec<f32>>,
     /// Alpha for feature smoothing
     alpha: f32,
     /// Current class prediction
     pub class_id: i32,
-    /// History of class predictions
-    class_hist: Vec<i32>,
-    /// Last timestamp of update
-    last_update: Instant,
+    /// History of class predictions with their detection confidence
+    class_hist: Vec<(i32, f32)>,
     /// Motion trail for visualization
     motion_trail: Vec<SVector<f32, 4>>,
+    /// Height / width of the last measured box
+    #[serde(default)]
+    aspect_ratio: f32,
+    /// Application-defined annotations, kept for the life of the track
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    /// Label of the detector that last produced this track's measurement
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Norm of the Kalman innovation at the latest update, in pixels
+    #[serde(default)]
+    last_innovation: f32,
+    /// EMA weight of the previous reported box on update (0 disables smoothing)
+    #[serde(default)]
+    box_smoothing_alpha: f32,
+    /// Reported box after the latest update, the base of the next smoothing step
+    #[serde(default)]
+    smoothed_tlwh: Option<SVector<f32, 4>>,
+    /// Scale velocity process noise by recent innovation in `predict`
+    #[serde(default)]
+    adaptive_noise: bool,
+    /// Running average of the innovation norm relative to box height
+    #[serde(default = "default_innovation_ema")]
+    innovation_ema: f32,
 }
 
 impl Clone for STrack {
@@ -305,6 +460,7 @@ impl Clone for STrack {
             mean: self.mean.clone(),
             covariance: self.covariance.clone(),
             tlwh: self.tlwh.clone(),
+            last_det_tlwh: self.last_det_tlwh,
             score: self.score,
             track_id: self.track_id,
             state: self.state.clone(),
@@ -312,12 +468,20 @@ impl Clone for STrack {
             frame_id: self.frame_id,
             start_frame: self.start_frame,
             tracklet_len: self.tracklet_len,
+            hits: self.hits,
             features: self.features.clone(),
             alpha: self.alpha,
             class_id: self.class_id,
             class_hist: self.class_hist.clone(),
-            last_update: self.last_update,
             motion_trail: self.motion_trail.clone(),
+            aspect_ratio: self.aspect_ratio,
+            metadata: self.metadata.clone(),
+            source: self.source.clone(),
+            last_innovation: self.last_innovation,
+            box_smoothing_alpha: self.box_smoothing_alpha,
+            smoothed_tlwh: self.smoothed_tlwh,
+            adaptive_noise: self.adaptive_noise,
+            innovation_ema: self.innovation_ema,
         }
     }
 }
@@ -338,6 +502,7 @@ impl STrack {
             mean,
             covariance,
             tlwh,
+            last_det_tlwh: tlwh,
             score,
             track_id: 0,  // Will be assigned by tracker
             state: TrackState::New,
@@ -345,15 +510,38 @@ impl STrack {
             frame_id,
             start_frame: frame_id,
             tracklet_len: 0,
+            hits: 0,
             features: feat.map_or(Vec::new(), |f| vec![f]),
             alpha: 0.9,  // Feature smoothing factor
             class_id,
-            class_hist: vec![class_id],
-            last_update: Instant::now(),
+            class_hist: vec![(class_id, score)],
             motion_trail: Vec::new(),
+            aspect_ratio: tlwh[3] / tlwh[2].max(f32::EPSILON),
+            metadata: HashMap::new(),
+            source: None,
+            last_innovation: 0.0,
+            box_smoothing_alpha: 0.0,
+            smoothed_tlwh: None,
+            adaptive_noise: false,
+            innovation_ema: ADAPTIVE_NOISE_REFERENCE,
         }
     }
 
+    /// Store the latest innovation norm and fold it, relative to box height, into the
+    /// running average behind adaptive process noise
+    fn record_innovation(&mut self, norm: f32) {
+        self.last_innovation = norm;
+        let relative = norm / self.mean[3].max(1.0);
+        self.innovation_ema = INNOVATION_EMA_DECAY * self.innovation_ema + (1.0 - INNOVATION_EMA_DECAY) * relative;
+    }
+
+    /// Velocity process-noise scale from recent innovation: above 1 for tracks whose
+    /// measurements keep surprising the motion model, below 1 for steady ones
+    pub fn noise_scale(&self) -> f32 {
+        let (min, max) = ADAPTIVE_NOISE_SCALE_RANGE;
+        (self.innovation_ema / ADAPTIVE_NOISE_REFERENCE).clamp(min, max)
+    }
+
     /// Convert mean state vector to tlwh format.
     pub fn state_to_tlwh(&self) -> SVector<f32, 4> {
         self.mean.fixed_rows::<4>(0).into()
@@ -380,12 +568,49 @@ impl STrack {
     /// Predict next state using Kalman filter.
     pub fn predict(&mut self) {
         let kalman = KalmanFilter::new();
-        let (mean, covariance) = kalman.predict(&self.mean, &self.covariance);
+        let scale = if self.adaptive_noise { self.noise_scale() } else { 1.0 };
+        let (mean, covariance) = kalman.predict_scaled(&self.mean, &self.covariance, scale);
         self.mean = mean;
         self.covariance = covariance;
         self.tlwh = self.state_to_tlwh();
     }
 
+    /// Extrapolate the box `n` frames ahead with the motion model, leaving the track untouched.
+    pub fn predict_ahead(&self, n: usize) -> SVector<f32, 4> {
+        let kalman = KalmanFilter::new();
+        let (mut mean, mut covariance) = (self.mean, self.covariance);
+        for _ in 0..n {
+            (mean, covariance) = kalman.predict(&mean, &covariance);
+        }
+        mean.fixed_rows::<4>(0).into()
+    }
+
+    /// Predict next state, then reshape the box to the last observed aspect ratio.
+    ///
+    /// Width and height have independent velocities in the filter, so a long coast
+    /// can stretch a box into an implausible shape. This keeps the predicted area
+    /// but couples w/h (and their velocities) to the last measured h/w ratio.
+    pub fn predict_keep_aspect(&mut self) {
+        let (w, h) = (self.mean[2], self.mean[3]);
+        let growth = if w > 0.0 && h > 0.0 {
+            0.5 * (self.mean[6] / w + self.mean[7] / h)
+        } else {
+            0.0
+        };
+        self.mean[6] = growth * w;
+        self.mean[7] = growth * h;
+
+        self.predict();
+
+        if self.aspect_ratio > 0.0 {
+            let area = (self.mean[2] * self.mean[3]).max(0.0);
+            let new_w = (area / self.aspect_ratio).sqrt();
+            self.mean[2] = new_w;
+            self.mean[3] = self.aspect_ratio * new_w;
+            self.tlwh = self.state_to_tlwh();
+        }
+    }
+
     /// Update track state with assigned detection.
     pub fn update(
         &mut self,
@@ -395,34 +620,48 @@ impl STrack {
     ) {
         let kalman = KalmanFilter::new();
         let tlwh = detection.tlwh.clone();
+        self.record_aspect(&tlwh);
+        self.last_det_tlwh = tlwh;
         
         // Update Kalman state
-        let (mean, covariance) = kalman.update(&self.mean, &self.covariance, &tlwh);
+        let (mean, covariance, innovation) = kalman.update_with_innovation(&self.mean, &self.covariance, &tlwh);
         self.mean = mean;
         self.covariance = covariance;
+        self.record_innovation(innovation.norm());
         
         // Update track metadata
-        self.tlwh = self.state_to_tlwh();
+        let posterior = self.state_to_tlwh();
+        self.tlwh = match self.smoothed_tlwh {
+            Some(previous) if self.box_smoothing_alpha > 0.0 => {
+                previous * self.box_smoothing_alpha + posterior * (1.0 - self.box_smoothing_alpha)
+            }
+            _ => posterior,
+        };
+        self.smoothed_tlwh = Some(self.tlwh);
         self.frame_id = frame_id;
         self.tracklet_len += 1;
+        self.hits += 1;
         self.state = TrackState::Tracked;
-        self.is_activated = true;
         self.score = detection.confidence;
+        if detection.source.is_some() {
+            self.source = detection.source.clone();
+        }
+        self.push_trail();
         
         // Update class history
-        self.class_hist.push(detection.class_id);
+        self.class_hist.push((detection.class_id, detection.confidence));
         if self.class_hist.len() > 10 {
             self.class_hist.remove(0);
         }
-        // Update class_id to most common in history
-        let mut counts = std::collections::HashMap::new();
-        for &c in &self.class_hist {
-            *counts.entry(c).or_insert(0) += 1;
+        // Update class_id to the class with the highest confidence-weighted vote
+        let mut votes = std::collections::BTreeMap::new();
+        for &(c, conf) in &self.class_hist {
+            *votes.entry(c).or_insert(0.0f32) += conf;
         }
-        self.class_id = *counts.iter()
-            .max_by_key(|&(_, count)| count)
-            .map(|(class_id, _)| class_id)
-            .unwrap_or(&detection.class_id);
+        self.class_id = votes.iter()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(&class_id, _)| class_id)
+            .unwrap_or(detection.class_id);
 
         // Update features if available
         if let Some(new_feat) = feat {
@@ -437,8 +676,6 @@ impl STrack {
                 self.features.push(new_feat);
             }
         }
-        
-        self.last_update = Instant::now();
     }
 
     /// Mark this track as lost.
@@ -446,6 +683,16 @@ impl STrack {
         self.state = TrackState::Lost;
     }
 
+    /// Mark this track as hidden behind another one.
+    pub fn mark_occluded(&mut self) {
+        self.state = TrackState::Occluded;
+    }
+
+    /// Whether the track went unmatched while covered by another track
+    pub fn is_occluded(&self) -> bool {
+        matches!(self.state, TrackState::Occluded)
+    }
+
     /// Mark this track as removed.
     pub fn mark_removed(&mut self) {
         self.state = TrackState::Removed;
@@ -458,20 +705,40 @@ impl STrack {
         self.covariance = covariance;
         self.track_id = track_id;
         self.state = TrackState::Tracked;
-        if frame_id == 1 {
-            self.is_activated = true;
-        }
+        self.hits = 1;
         self.frame_id = frame_id;
         self.start_frame = frame_id;
+        self.smoothed_tlwh = Some(self.tlwh);
+        self.push_trail();
+    }
+
+    /// Remember the measured h/w ratio for aspect-preserving prediction.
+    fn record_aspect(&mut self, tlwh: &SVector<f32, 4>) {
+        if tlwh[2] > 0.0 && tlwh[3] > 0.0 {
+            self.aspect_ratio = tlwh[3] / tlwh[2];
+        }
+    }
+
+    /// Append the current box to the motion trail, dropping the oldest entry when full.
+    fn push_trail(&mut self) {
+        self.motion_trail.push(self.tlwh);
+        if self.motion_trail.len() > MAX_TRAIL_LEN {
+            self.motion_trail.remove(0);
+        }
     }
 
     /// Re-activate a lost track with new detection.
     pub fn re_activate(&mut self, detection: &crate::detection::Detection, frame_id: i32, new_id: bool) {
         let kalman = KalmanFilter::new();
         let tlwh = detection.tlwh.clone();
-        let (mean, covariance) = kalman.update(&self.mean, &self.covariance, &tlwh);
+        self.record_aspect(&tlwh);
+        self.last_det_tlwh = tlwh;
+        let (mean, covariance, innovation) = kalman.update_with_innovation(&self.mean, &self.covariance, &tlwh);
         self.mean = mean;
         self.covariance = covariance;
+        self.record_innovation(innovation.norm());
+        // After a gap the old reported box is stale; restart smoothing from here
+        self.smoothed_tlwh = Some(self.state_to_tlwh());
         self.tracklet_len = 0;
         self.state = TrackState::Tracked;
         self.is_activated = true;
@@ -480,20 +747,85 @@ impl STrack {
             self.track_id = self.track_id;
         }
         self.score = detection.confidence;
+        if detection.source.is_some() {
+            self.source = detection.source.clone();
+        }
     }
 
     pub fn is_activated(&self) -> bool {
         self.is_activated
     }
 
+    /// Activate the track once it has been matched in at least `n_init` consecutive frames.
+    pub fn confirm(&mut self, n_init: u32) {
+        if self.hits >= n_init {
+            self.is_activated = true;
+        }
+    }
+
     pub fn tlwh(&self) -> &SVector<f32, 4> {
         &self.tlwh
     }
 
+    /// Box of the latest detection associated with this track, as measured; `tlwh()` is
+    /// the filtered box
+    pub fn last_det_tlwh(&self) -> &SVector<f32, 4> {
+        &self.last_det_tlwh
+    }
+
     pub fn track_id(&self) -> u32 {
         self.track_id
     }
 
+    /// Frames between the track's start and its latest update
+    pub fn age(&self) -> i32 {
+        self.frame_id - self.start_frame
+    }
+
+    /// Consecutive updates since the track was created or last re-activated
+    pub fn tracklet_length(&self) -> i32 {
+        self.tracklet_len
+    }
+
+    /// Frame of the latest detection matched to this track
+    pub fn last_seen_frame(&self) -> i32 {
+        self.frame_id
+    }
+
+    /// Velocity of the box center as (vx, vy), in pixels per frame
+    pub fn velocity(&self) -> (f32, f32) {
+        // State is top-left based; the center also moves with half the size change
+        (self.mean[4] + self.mean[6] / 2.0, self.mean[5] + self.mean[7] / 2.0)
+    }
+
+    /// Norm of the difference between the latest measurement and the motion model's
+    /// prediction, in pixels (0 until the first update). Large values point to a poor motion
+    /// fit or an ID switch.
+    pub fn last_innovation(&self) -> f32 {
+        self.last_innovation
+    }
+
+    /// Speed of the box center, in pixels per frame
+    pub fn speed(&self) -> f32 {
+        let (vx, vy) = self.velocity();
+        vx.hypot(vy)
+    }
+
+    /// Direction of motion in radians, `atan2(vy, vx)` in image coordinates (y points down)
+    pub fn heading(&self) -> f32 {
+        let (vx, vy) = self.velocity();
+        vy.atan2(vx)
+    }
+
+    /// Attach a key/value annotation to this track, replacing any previous value
+    pub fn set_meta(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn get_meta(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(String::as_str)
+    }
+
     pub fn motion_trail(&self) -> Option<&Vec<SVector<f32, 4>>> {
         if self.motion_trail.is_empty() {
             None
@@ -503,8 +835,40 @@ impl STrack {
     }
 }
 
-/// Global Motion Compensation using optical flow
+/// Camera motion model estimated by `GMC`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GmcMode {
+    /// Full perspective homography (RANSAC)
+    #[default]
+    Homography,
+    /// Partial affine transform (rotation, uniform scale, translation)
+    Affine,
+    /// No motion compensation
+    None,
+}
+
+/// How `GMC` finds point correspondences between frames
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GmcFeatures {
+    /// Shi-Tomasi corners tracked with pyramidal Lucas-Kanade
+    #[default]
+    OpticalFlow,
+    /// ORB keypoints matched by descriptor; robust to large motion and blur
+    Orb,
+}
+
+/// Global Motion Compensation using optical flow or ORB matching
 pub struct GMC {
+    /// Motion model to estimate
+    mode: GmcMode,
+    /// Correspondence method
+    features: GmcFeatures,
+    /// Previous ORB keypoints and descriptors
+    prev_orb: Option<(Vector<KeyPoint>, Mat)>,
+    /// Lowe ratio for ORB match filtering
+    orb_ratio: f32,
     /// Previous frame in grayscale
     prev_frame: Option<Mat>,
     /// Previous keypoints
@@ -525,10 +889,30 @@ pub struct GMC {
     criteria: TermCriteria,
 }
 
+impl Default for GMC {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GMC {
     /// Create new GMC instance
     pub fn new() -> Self {
+        Self::with_mode(GmcMode::Homography)
+    }
+
+    /// Create a GMC instance estimating the given motion model
+    pub fn with_mode(mode: GmcMode) -> Self {
+        Self::with_features(mode, GmcFeatures::OpticalFlow)
+    }
+
+    /// Create a GMC instance with the given motion model and correspondence method
+    pub fn with_features(mode: GmcMode, features: GmcFeatures) -> Self {
         GMC {
+            mode,
+            features,
+            prev_orb: None,
+            orb_ratio: 0.75,
             prev_frame: None,
             prev_pts: None,
             max_corners: 1000,
@@ -546,28 +930,25 @@ impl GMC {
         }
     }
 
-    /// Apply motion compensation and return homography matrix
+    /// Apply motion compensation and return the estimated transform:
+    /// a 3x3 homography or a 2x3 affine matrix depending on the mode.
     pub fn apply(&mut self, frame: &Mat) -> anyhow::Result<Option<Mat>> {
+        if self.mode == GmcMode::None {
+            return Ok(None);
+        }
+
         // Convert frame to grayscale
         let mut gray = Mat::default();
         imgproc::cvt_color(frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
 
+        if self.features == GmcFeatures::Orb {
+            return self.apply_orb(&gray);
+        }
+
         // Initialize if first frame
         if self.prev_frame.is_none() {
-            self.prev_frame = Some(gray.clone());
-            let mut corners = Mat::default();
-            imgproc::good_features_to_track(
-                &gray,
-                &mut corners,
-                self.max_corners,
-                self.quality_level,
-                self.min_distance,
-                &Mat::default(),
-                self.block_size,
-                false,
-                0.04,
-            )?;
-            self.prev_pts = Some(corners);
+            self.prev_pts = Some(self.detect_corners(&gray)?);
+            self.prev_frame = Some(gray);
             return Ok(None);
         }
 
@@ -601,51 +982,20 @@ impl GMC {
             }
         }
 
-        // Compute homography if enough points
-        let homography = if prev_good.len() >= 4 {
-            // Convert Points to Mats
-            let mut prev_pts_arr = unsafe {
-                Mat::new_rows_cols(
-                    prev_good.len() as i32, 
-                    1, 
-                    opencv::core::CV_32FC2
-                )?
-            };
-            let mut curr_pts_arr = unsafe {
-                Mat::new_rows_cols(
-                    curr_good.len() as i32, 
-                    1, 
-                    opencv::core::CV_32FC2
-                )?
-            };
-            
-            for (i, pt) in prev_good.iter().enumerate() {
-                *prev_pts_arr.at_2d_mut::<Point2f>(i as i32, 0)? = *pt;
-            }
-            
-            for (i, pt) in curr_good.iter().enumerate() {
-                *curr_pts_arr.at_2d_mut::<Point2f>(i as i32, 0)? = *pt;
-            }
-            
-            // Find homography
-            Some(opencv::calib3d::find_homography(
-                &prev_pts_arr,
-                &curr_pts_arr,
-                &mut Mat::default(),
-                opencv::calib3d::RANSAC,
-                3.0,
-            )?)
-        } else {
-            None
-        };
+        let homography = self.estimate_transform(&prev_good, &curr_good)?;
 
-        // Update state for next frame
-        self.prev_frame = Some(gray.clone());
-        
-        // Find new corners for next frame
+        // Corners of this frame are the flow origins for the next one
+        self.prev_pts = Some(self.detect_corners(&gray)?);
+        self.prev_frame = Some(gray);
+
+        Ok(homography)
+    }
+
+    /// Shi-Tomasi corners to track into the next frame; computed once per frame
+    fn detect_corners(&self, gray: &Mat) -> anyhow::Result<Mat> {
         let mut corners = Mat::default();
         imgproc::good_features_to_track(
-            &gray,
+            gray,
             &mut corners,
             self.max_corners,
             self.quality_level,
@@ -655,13 +1005,118 @@ impl GMC {
             false,
             0.04,
         )?;
-        self.prev_pts = Some(corners);
+        Ok(corners)
+    }
 
-        Ok(homography)
+    /// Estimate the frame-to-frame transform from matched point pairs
+    fn estimate_transform(&self, prev_good: &[Point2f], curr_good: &[Point2f]) -> anyhow::Result<Option<Mat>> {
+        // Compute homography if enough points
+        if prev_good.len() < 4 {
+            return Ok(None);
+        }
+
+        // Convert Points to Mats
+        let mut prev_pts_arr = unsafe {
+            Mat::new_rows_cols(
+                prev_good.len() as i32, 
+                1, 
+                opencv::core::CV_32FC2
+            )?
+        };
+        let mut curr_pts_arr = unsafe {
+            Mat::new_rows_cols(
+                curr_good.len() as i32, 
+                1, 
+                opencv::core::CV_32FC2
+            )?
+        };
+        
+        for (i, pt) in prev_good.iter().enumerate() {
+            *prev_pts_arr.at_2d_mut::<Point2f>(i as i32, 0)? = *pt;
+        }
+        
+        for (i, pt) in curr_good.iter().enumerate() {
+            *curr_pts_arr.at_2d_mut::<Point2f>(i as i32, 0)? = *pt;
+        }
+        
+        let transform = match self.mode {
+            GmcMode::Affine => opencv::calib3d::estimate_affine_partial_2d(
+                &prev_pts_arr,
+                &curr_pts_arr,
+                &mut Mat::default(),
+                opencv::calib3d::RANSAC,
+                3.0,
+                2000,
+                0.99,
+                10,
+            )?,
+            // Find homography
+            _ => opencv::calib3d::find_homography(
+                &prev_pts_arr,
+                &curr_pts_arr,
+                &mut Mat::default(),
+                opencv::calib3d::RANSAC,
+                3.0,
+            )?,
+        };
+        Ok(Some(transform))
+    }
+
+    /// ORB keypoint matching path: detect and describe keypoints on the current frame,
+    /// match them against the previous frame with a ratio test, and estimate the transform.
+    fn apply_orb(&mut self, gray: &Mat) -> anyhow::Result<Option<Mat>> {
+        let mut orb = ORB::create(
+            self.max_corners,
+            1.2,
+            8,
+            31,
+            0,
+            2,
+            ORB_ScoreType::HARRIS_SCORE,
+            31,
+            20,
+        )?;
+        let mut keypoints = Vector::<KeyPoint>::new();
+        let mut descriptors = Mat::default();
+        orb.detect_and_compute(gray, &Mat::default(), &mut keypoints, &mut descriptors, false)?;
+
+        self.prev_frame = Some(gray.clone());
+        let (prev_keypoints, prev_descriptors) = match self.prev_orb.replace((keypoints.clone(), descriptors.clone())) {
+            Some(prev) => prev,
+            None => return Ok(None),
+        };
+        if prev_descriptors.empty() || descriptors.empty() {
+            return Ok(None);
+        }
+
+        let matcher = BFMatcher::create(NORM_HAMMING, false)?;
+        let mut knn_matches = Vector::<Vector<DMatch>>::new();
+        matcher.knn_train_match(&prev_descriptors, &descriptors, &mut knn_matches, 2, &Mat::default(), false)?;
+
+        let mut prev_good = Vec::new();
+        let mut curr_good = Vec::new();
+        for pair in knn_matches.iter() {
+            if pair.len() < 2 {
+                continue;
+            }
+            let (best, second) = (pair.get(0)?, pair.get(1)?);
+            if best.distance < self.orb_ratio * second.distance {
+                prev_good.push(prev_keypoints.get(best.query_idx as usize)?.pt());
+                curr_good.push(keypoints.get(best.train_idx as usize)?.pt());
+            }
+        }
+
+        self.estimate_transform(&prev_good, &curr_good)
     }
 
-    /// Apply motion compensation to track state
+    /// Apply motion compensation to track state.
+    /// Accepts either a 3x3 homography or a 2x3 affine matrix.
     pub fn apply_to_track(track: &mut STrack, homography: &Mat) -> anyhow::Result<()> {
+        // An empty matrix means estimation failed; leave the track alone
+        if homography.empty() {
+            return Ok(());
+        }
+
         // Convert track bbox to points
         let pts_data = [
             Point2f::new(track.tlwh[0], track.tlwh[1]),
@@ -671,7 +1126,11 @@ impl GMC {
 
         // Transform points
         let mut dst = Mat::default();
-        opencv::core::perspective_transform(&pts, &mut dst, homography)?;
+        if homography.rows() == 2 {
+            opencv::core::transform(&pts, &mut dst, homography)?;
+        } else {
+            opencv::core::perspective_transform(&pts, &mut dst, homography)?;
+        }
 
         // Update track state
         let p1 = dst.at::<Point2f>(0)?;
@@ -681,19 +1140,56 @@ impl GMC {
         track.mean[1] = p1.y;
         track.mean[2] = p2.x - p1.x;
         track.mean[3] = p2.y - p1.y;
-        
+
+        // Velocities and uncertainty are displacements, so they take only the linear part
+        // of the transform; this keeps compensate-then-predict equal to predicting in the
+        // previous frame and warping the result, as the reference does
+        let mut linear = SMatrix::<f32, 2, 2>::identity();
+        for r in 0..2 {
+            for c in 0..2 {
+                linear[(r, c)] = *homography.at_2d::<f64>(r as i32, c as i32)? as f32;
+            }
+        }
+        let mut warp = SMatrix::<f32, 8, 8>::zeros();
+        for block in 0..4 {
+            warp.fixed_view_mut::<2, 2>(2 * block, 2 * block).copy_from(&linear);
+        }
+        let velocity = warp.fixed_view::<4, 4>(4, 4) * track.mean.fixed_rows::<4>(4);
+        track.mean.fixed_rows_mut::<4>(4).copy_from(&velocity);
+        track.covariance = warp * track.covariance * warp.transpose();
+
         track.tlwh = track.state_to_tlwh();
         Ok(())
     }
 }
 
+/// Canonical detection order for deterministic mode: highest score first, then by
+/// position, size and class so that ties are broken the same way for any input order
+fn canonical_order(a: &crate::detection::Detection, b: &crate::detection::Detection) -> std::cmp::Ordering {
+    b.confidence.total_cmp(&a.confidence)
+        .then_with(|| {
+            a.tlwh.iter().zip(b.tlwh.iter())
+                .map(|(x, y)| x.total_cmp(y))
+                .find(|ord| ord.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .then_with(|| a.class_id.cmp(&b.class_id))
+}
+
 /// Multi-object tracker using Kalman filter and IoU matching
 #[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
 pub struct SMILEtrack {
     /// Kalman filter for state estimation
+    #[serde(skip)]
     kalman: KalmanFilter,
-    /// Global motion compensation
+    /// Global motion compensation (re-initialized on the first frame after a resume)
+    #[serde(skip)]
     gmc: GMC,
+    /// Motion model used by `gmc`
+    gmc_mode: GmcMode,
+    /// Correspondence method used by `gmc`
+    gmc_features: GmcFeatures,
     /// List of active tracks
     tracked_stracks: Vec<STrack>,
     /// List of lost tracks
@@ -706,12 +1202,70 @@ pub struct SMILEtrack {
     track_id_count: u32,
     /// Detection confidence threshold
     track_high_thresh: f32,
+    /// Lower bound for detections promoted in adaptive mode
+    #[serde(default)]
+    track_low_thresh: f32,
+    /// Promote low-score detections on frames where none clears `track_high_thresh`
+    #[serde(default)]
+    adaptive_thresh: bool,
     /// Track buffer size
     track_buffer: usize,
-    /// Max time since last update before removal
-    max_time_lost: f32,
+    /// Max frames since last update before a lost track is removed
+    max_time_lost: i32,
     /// Whether to use re-ID features
     with_reid: bool,
+    /// Max cosine distance for an appearance match
+    appearance_thresh: f32,
+    /// Max IoU distance at which a pair may still be matched by appearance
+    #[serde(default = "crate::config::default_proximity_thresh")]
+    proximity_thresh: f32,
+    /// Whether removed tracks may be revived by appearance
+    reid_removed: bool,
+    /// Appearance of recently removed tracks, consulted before assigning a new ID
+    #[serde(default)]
+    reid_gallery: ReidGallery,
+    /// Whether coasting (lost) tracks keep their last observed aspect ratio
+    #[serde(default)]
+    keep_aspect_on_coast: bool,
+    /// Consecutive matches required before a new track is activated
+    #[serde(default = "crate::config::default_n_init")]
+    n_init: u32,
+    /// Raise the duplicate-track IoU and keep overlapping tracks that look different
+    #[serde(default)]
+    crowd_mode: bool,
+    /// Weight of the box-size dissimilarity term in the matching cost (0 disables it)
+    #[serde(default)]
+    size_weight: f32,
+    /// EMA weight applied to each track's reported box on update
+    #[serde(default)]
+    box_smoothing_alpha: f32,
+    /// Area in square pixels a track must exceed to appear in `tracks_filtered`
+    #[serde(default)]
+    min_track_area: f32,
+    /// Perspective prior on box height; detections that contradict it are ignored
+    #[serde(default)]
+    size_prior: Option<crate::roi::SizePrior>,
+    /// Associate detections in canonical order rather than input order (for tests and repro)
+    #[serde(default)]
+    deterministic: bool,
+    /// IoU above which overlapping tracks are merged by `remove_duplicate_tracks`
+    #[serde(default = "crate::config::default_duplicate_iou")]
+    duplicate_iou: f32,
+    /// Let each track scale its velocity process noise by its recent innovation
+    #[serde(default)]
+    adaptive_process_noise: bool,
+    /// Detections from the latest `update` that matched no existing track
+    #[serde(skip)]
+    last_unmatched: Vec<crate::detection::Detection>,
+    /// Min IoU between a new-track candidate and one from the previous frame (0 disables the gate)
+    #[serde(default)]
+    init_iou: f32,
+    /// New-track candidates from the previous frame, for the `init_iou` gate
+    #[serde(skip)]
+    pending_seeds: Vec<crate::detection::Detection>,
+    /// Stage times of the latest `update`
+    #[serde(skip)]
+    last_timings: crate::utils::Timings,
 }
 
 impl SMILEtrack {
@@ -719,16 +1273,38 @@ impl SMILEtrack {
     pub fn new(config: &crate::config::Config, frame_rate: f32) -> Self {
         SMILEtrack {
             kalman: KalmanFilter::new(),
-            gmc: GMC::new(),
+            gmc: GMC::with_features(config.gmc_method, config.gmc_features),
+            gmc_mode: config.gmc_method,
+            gmc_features: config.gmc_features,
             tracked_stracks: Vec::new(),
             lost_stracks: Vec::new(),
             removed_stracks: Vec::new(),
             frame_rate,
             track_id_count: 0,
             track_high_thresh: config.track_high_thresh,
+            track_low_thresh: config.track_low_thresh,
+            adaptive_thresh: config.adaptive_thresh,
             track_buffer: config.track_buffer,
-            max_time_lost: 30.0,  // frames
+            max_time_lost: (frame_rate / 30.0 * config.track_buffer as f32) as i32,
             with_reid: config.with_reid,
+            appearance_thresh: config.appearance_thresh,
+            proximity_thresh: config.proximity_thresh,
+            reid_removed: config.reid_removed,
+            reid_gallery: ReidGallery::new(config.reid_gallery_window),
+            keep_aspect_on_coast: config.keep_aspect_on_coast,
+            n_init: config.n_init,
+            crowd_mode: config.crowd_mode,
+            size_weight: config.size_weight,
+            box_smoothing_alpha: config.box_smoothing_alpha,
+            min_track_area: config.min_track_area,
+            size_prior: config.size_prior,
+            deterministic: config.deterministic,
+            duplicate_iou: config.duplicate_iou,
+            adaptive_process_noise: config.adaptive_process_noise,
+            last_unmatched: Vec::new(),
+            init_iou: config.init_iou,
+            pending_seeds: Vec::new(),
+            last_timings: crate::utils::Timings::default(),
         }
     }
 
@@ -737,19 +1313,68 @@ impl SMILEtrack {
         &self.tracked_stracks
     }
 
-    /// Update tracks with new detections
-    pub fn update(&mut self, dets: &[crate::detection::Detection], frame: &Mat, frame_id: i32) -> anyhow::Result<()> {
-        // Apply motion compensation
-        if let Some(homography) = self.gmc.apply(frame)? {
-            // Compensate motion for tracked tracks
-            for track in &mut self.tracked_stracks {
-                GMC::apply_to_track(track, &homography)?;
-            }
-            // Compensate motion for lost tracks
-            for track in &mut self.lost_stracks {
-                GMC::apply_to_track(track, &homography)?;
-            }
+    /// Activated tracks whose box area exceeds `min_track_area` and whose size fits the
+    /// perspective prior, if any, for output and drawing
+    pub fn tracks_filtered(&self) -> Vec<&STrack> {
+        self.tracked_stracks.iter()
+            .filter(|t| t.is_activated() && t.tlwh[2] * t.tlwh[3] > self.min_track_area)
+            .filter(|t| self.size_prior.map_or(true, |prior| prior.plausible(&t.tlwh)))
+            .collect()
+    }
+
+    /// How long the latest `update` spent on motion compensation (`gmc_ms`) and on
+    /// prediction, association and track management (`association_ms`)
+    pub fn last_timings(&self) -> crate::utils::Timings {
+        self.last_timings
+    }
+
+    /// Lost tracks currently hidden behind another track
+    pub fn occluded_tracks(&self) -> impl Iterator<Item = &STrack> {
+        self.lost_stracks.iter().filter(|t| t.is_occluded())
+    }
+
+    /// Mutable access to a tracked or lost track by ID, e.g. to attach metadata
+    pub fn track_by_id_mut(&mut self, track_id: u32) -> Option<&mut STrack> {
+        self.tracked_stracks.iter_mut()
+            .chain(self.lost_stracks.iter_mut())
+            .find(|t| t.track_id == track_id)
+    }
+
+    /// High-score detections from the latest `update` that were not associated with any
+    /// tracked or lost track (new objects or false positives). Low-score detections
+    /// never enter association and are not included.
+    pub fn last_unmatched_detections(&self) -> &[crate::detection::Detection] {
+        &self.last_unmatched
+    }
+
+    /// Save the full tracker state (track lists and ID counter) to a JSON file.
+    pub fn save_state(&self, path: &str) -> anyhow::Result<()> {
+        let json = serde_json::to_string(self)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Restore a tracker previously written with `save_state`.
+    pub fn load_state(path: &str) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let mut tracker: SMILEtrack = serde_json::from_str(&data)?;
+        tracker.gmc = GMC::with_features(tracker.gmc_mode, tracker.gmc_features);
+        Ok(tracker)
+    }
+
+    /// Update tracks with new detections, returning the lifecycle events of this frame
+    pub fn update(&mut self, dets: &[crate::detection::Detection], frame: &Mat, frame_id: i32) -> anyhow::Result<Vec<TrackEvent>> {
+        let start = std::time::Instant::now();
+        let mut events = Vec::new();
+        self.advance(frame, frame_id)?;
+
+        // Boxes whose size contradicts their position are most likely false positives
+        let mut owned_dets = self.size_prior.map(|prior| prior.filter(dets.to_vec()));
+        // Matching and ID assignment follow detection order, so fix that order
+        if self.deterministic {
+            owned_dets.get_or_insert_with(|| dets.to_vec()).sort_by(canonical_order);
         }
+        let dets = owned_dets.as_deref().unwrap_or(dets);
 
         // Get detections above threshold
         let mut activated_stracks = Vec::new();
@@ -757,16 +1382,20 @@ impl SMILEtrack {
         let mut lost_stracks = Vec::new();
         let mut removed_stracks = Vec::new();
 
-        let high_score_dets: Vec<_> = dets.iter()
+        let mut high_score_dets: Vec<_> = dets.iter()
             .filter(|d| d.confidence >= self.track_high_thresh)
             .collect();
-        
-        // Predict locations
-        for track in self.tracked_stracks.iter_mut() {
-            track.predict();
-        }
-        for track in self.lost_stracks.iter_mut() {
-            track.predict();
+
+        // Quality dip: nothing clears the high threshold, so let the best low-score
+        // detections (one per active track) keep existing tracks alive. They still
+        // cannot start new tracks, which requires `track_high_thresh`.
+        if self.adaptive_thresh && high_score_dets.is_empty() {
+            let mut low_score_dets: Vec<_> = dets.iter()
+                .filter(|d| d.confidence >= self.track_low_thresh)
+                .collect();
+            low_score_dets.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+            low_score_dets.truncate(self.tracked_stracks.len());
+            high_score_dets = low_score_dets;
         }
 
         // Match with tracked tracks
@@ -777,86 +1406,215 @@ impl SMILEtrack {
         for (track_idx, det_idx) in matches_1 {
             let track = &mut self.tracked_stracks[track_idx];
             let det = &high_score_dets[det_idx];
-            track.update(det, frame_id, None);
+            let feat = if self.with_reid { det.feature.clone() } else { None };
+            track.update(det, frame_id, feat);
+            let was_activated = track.is_activated();
+            track.confirm(self.n_init);
+            if track.is_activated() && !was_activated {
+                events.push(TrackEvent::Confirmed { id: track.track_id });
+            }
         }
 
-        // Match with lost tracks
-        let (matches_2, _unmatched_tracks_2, _unmatched_dets_2) =
-            self.match_tracks(&self.lost_stracks, dets, &high_score_dets);
+        // Match with lost tracks using the detections left over from the first pass
+        let remaining_dets: Vec<_> = unmatched_dets_1.iter()
+            .map(|&i| high_score_dets[i])
+            .collect();
+        let (matches_2, _unmatched_tracks_2, unmatched_dets_2) =
+            self.match_tracks(&self.lost_stracks, dets, &remaining_dets);
 
         // Refind matched tracks
         for (track_idx, det_idx) in matches_2 {
             let track = &mut self.lost_stracks[track_idx];
-            let det = &high_score_dets[det_idx];
+            let det = &remaining_dets[det_idx];
             track.re_activate(det, frame_id, false);
+            events.push(TrackEvent::Refound { id: track.track_id });
             refind_stracks.push(track.clone());
         }
 
-        // Mark unmatched tracks as lost
+        // Mark unmatched tracks as lost, or occluded when a matched track covers them;
+        // tentative tracks are dropped outright
+        let occluders: Vec<SVector<f32, 4>> = self.tracked_stracks.iter()
+            .enumerate()
+            .filter(|(i, _)| !unmatched_tracks_1.contains(i))
+            .map(|(_, t)| t.tlwh)
+            .collect();
         for &track_idx in &unmatched_tracks_1 {
             let track = &mut self.tracked_stracks[track_idx];
-            if track.tracklet_len > self.track_buffer as i32 {
+            if !track.is_activated() {
+                track.mark_removed();
+                events.push(TrackEvent::Removed { id: track.track_id });
+                continue;
+            }
+            let covered = occluders.iter()
+                .any(|b| crate::utils::box_coverage(&track.tlwh, b) >= OCCLUSION_COVERAGE);
+            if covered {
+                track.mark_occluded();
+                events.push(TrackEvent::Occluded { id: track.track_id });
+            } else {
                 track.mark_lost();
-                lost_stracks.push(track.clone());
+                events.push(TrackEvent::Lost { id: track.track_id });
             }
+            lost_stracks.push(track.clone());
         }
 
+        self.last_unmatched = unmatched_dets_2.iter()
+            .map(|&i| remaining_dets[i].clone())
+            .collect();
+
         // Create new tracks for unmatched detections
-        for &det_idx in &unmatched_dets_1 {
-            let det = &high_score_dets[det_idx];
+        let mut seeds = Vec::new();
+        self.reid_gallery.prune(frame_id);
+        for &det_idx in &unmatched_dets_2 {
+            let det = remaining_dets[det_idx];
             if det.confidence >= self.track_high_thresh {
+                // Long-term re-ID: revive a removed track instead of spawning a new ID
+                if let Some(mut track) = self.match_removed_track(det) {
+                    self.removed_stracks.retain(|t| t.track_id != track.track_id);
+                    let (mean, covariance) = self.kalman.initiate(&det.tlwh);
+                    track.mean = mean;
+                    track.covariance = covariance;
+                    track.re_activate(det, frame_id, false);
+                    events.push(TrackEvent::Refound { id: track.track_id });
+                    refind_stracks.push(track);
+                    continue;
+                }
+
+                // A box sitting on an existing track is that object seen again, not a new one;
+                // crowd mode deliberately allows overlapping distinct tracks
+                if !self.crowd_mode && self.overlaps_existing_track(det) {
+                    continue;
+                }
+
+                // Two-frame initiation: only spawn if a candidate from the previous frame overlaps
+                if self.init_iou > 0.0 {
+                    seeds.push(det.clone());
+                    let recurs = self.pending_seeds.iter()
+                        .any(|seed| crate::utils::compute_iou(&seed.tlwh, &det.tlwh) > self.init_iou);
+                    if !recurs {
+                        continue;
+                    }
+                }
+
+                let feat = if self.with_reid { det.feature.clone() } else { None };
                 let mut new_track = STrack::new(
                     det.tlwh.clone(),
                     det.confidence,
                     det.class_id,
-                    None,
+                    feat,
                     frame_id,
                 );
+                new_track.source = det.source.clone();
+                new_track.box_smoothing_alpha = self.box_smoothing_alpha;
+                new_track.adaptive_noise = self.adaptive_process_noise;
                 self.track_id_count += 1;
                 new_track.activate(&self.kalman, frame_id, self.track_id_count);
+                new_track.confirm(self.n_init);
+                events.push(TrackEvent::Created { id: new_track.track_id, class: new_track.class_id });
+                if new_track.is_activated() {
+                    events.push(TrackEvent::Confirmed { id: new_track.track_id });
+                }
                 activated_stracks.push(new_track);
             }
         }
+        self.pending_seeds = seeds;
 
-        // Remove old lost tracks
+        // Remove old lost tracks; occluded ones are held longer
         for track in &mut self.lost_stracks {
-            let elapsed = track.last_update.elapsed().as_secs_f32();
-            if elapsed > self.max_time_lost {
+            let max_time = match track.state {
+                TrackState::Lost => self.max_time_lost,
+                TrackState::Occluded => self.max_time_lost * OCCLUDED_HOLD_FACTOR,
+                _ => continue,
+            };
+            if frame_id - track.frame_id > max_time {
                 track.mark_removed();
+                events.push(TrackEvent::Removed { id: track.track_id });
+                if self.with_reid && self.reid_removed {
+                    self.reid_gallery.insert(track.clone(), frame_id);
+                }
                 removed_stracks.push(track.clone());
             }
         }
 
         // Update track lists
+        self.tracked_stracks.retain(|t| matches!(t.state, TrackState::Tracked));
+        self.lost_stracks.retain(|t| matches!(t.state, TrackState::Lost | TrackState::Occluded));
         self.tracked_stracks.extend(activated_stracks);
         self.tracked_stracks.extend(refind_stracks);
         self.lost_stracks.extend(lost_stracks);
         self.removed_stracks.extend(removed_stracks);
 
         // Remove duplicate tracks
-        self.remove_duplicate_tracks();
+        events.extend(self.remove_duplicate_tracks(frame_id).into_iter().map(|id| TrackEvent::Removed { id }));
+
+        self.last_timings.association_ms = crate::utils::elapsed_ms(start) - self.last_timings.gmc_ms;
+        Ok(events)
+    }
 
+    /// Advance all tracks by one frame without detections (camera motion compensation and
+    /// Kalman prediction), e.g. on frames skipped by a detection stride. Tracked tracks keep
+    /// their state and report their predicted boxes; nothing is lost or removed.
+    pub fn predict_only(&mut self, frame: &Mat, frame_id: i32) -> anyhow::Result<()> {
+        self.advance(frame, frame_id)?;
+        self.last_timings.association_ms = 0.0;
         Ok(())
     }
 
-    /// Match tracks with detections using IoU
-    fn match_tracks(
-        &self,
-        tracks: &[STrack],
-        _all_dets: &[crate::detection::Detection],
-        filtered_dets: &Vec<&crate::detection::Detection>,
-    ) -> (Vec<(usize, usize)>, Vec<usize>, Vec<usize>) {
+    /// Motion compensation and prediction shared by `update` and `predict_only`
+    fn advance(&mut self, frame: &Mat, frame_id: i32) -> anyhow::Result<()> {
+        // Apply motion compensation
+        let start = std::time::Instant::now();
+        let homography = self.gmc.apply(frame)?;
+        self.last_timings.gmc_ms = crate::utils::elapsed_ms(start);
+        // Move every existing track into this frame's coordinates exactly once, before
+        // predicting: the prediction then only adds the track's own motion
+        if let Some(homography) = homography {
+            for track in self.tracked_stracks.iter_mut().chain(self.lost_stracks.iter_mut()) {
+                GMC::apply_to_track(track, &homography)?;
+            }
+        }
+
+        // Predict locations; a track born last frame has no velocity yet, so predicting
+        // it would only inflate its covariance before its first real match
+        for track in self.tracked_stracks.iter_mut() {
+            if track.start_frame == frame_id - 1 && track.hits < 2 {
+                continue;
+            }
+            track.predict();
+        }
+        for track in self.lost_stracks.iter_mut() {
+            if self.keep_aspect_on_coast {
+                track.predict_keep_aspect();
+            } else {
+                track.predict();
+            }
+        }
+        Ok(())
+    }
+
+    /// Match tracks with detections using IoU
+    fn match_tracks(
+        &self,
+        tracks: &[STrack],
+        _all_dets: &[crate::detection::Detection],
+        filtered_dets: &Vec<&crate::detection::Detection>,
+    ) -> (Vec<(usize, usize)>, Vec<usize>, Vec<usize>) {
         if tracks.is_empty() || filtered_dets.is_empty() {
             return (Vec::new(), (0..tracks.len()).collect(), (0..filtered_dets.len()).collect());
         }
 
-        // Calculate IoU distance matrix
+        // Calculate IoU distance matrix (compute_iou takes tlwh boxes)
         let mut iou_dists = vec![vec![0.0; filtered_dets.len()]; tracks.len()];
         for (i, track) in tracks.iter().enumerate() {
             for (j, det) in filtered_dets.iter().enumerate() {
-                let track_tlbr = STrack::tlwh_to_tlbr(&track.tlwh);
-                let det_tlbr = STrack::tlwh_to_tlbr(&det.tlwh);
-                iou_dists[i][j] = 1.0 - crate::utils::compute_iou(&track_tlbr, &det_tlbr);
+                iou_dists[i][j] = 1.0 - crate::utils::compute_iou(&track.tlwh, &det.tlwh);
+                // Penalize pairs whose box areas differ, e.g. a small box inside a big one
+                if self.size_weight > 0.0 {
+                    iou_dists[i][j] += self.size_weight * crate::utils::size_dissimilarity(&track.tlwh, &det.tlwh);
+                }
+                // BoT-SORT fusion: a close enough pair may match on appearance instead of IoU
+                if let Some(emb_dist) = self.appearance_distance(track, det, iou_dists[i][j]) {
+                    iou_dists[i][j] = iou_dists[i][j].min(emb_dist);
+                }
             }
         }
 
@@ -914,18 +1672,67 @@ impl SMILEtrack {
         (matches, unmatched_tracks, unmatched_dets)
     }
 
-    /// Remove duplicate tracks based on IoU and track age
-    fn remove_duplicate_tracks(&mut self) {
+    /// Halved cosine distance between a track's latest feature and a detection's, when the
+    /// pair is appearance-eligible: re-ID on, IoU distance within `proximity_thresh`, and
+    /// the halved distance within `appearance_thresh` (as in BoT-SORT).
+    fn appearance_distance(&self, track: &STrack, det: &crate::detection::Detection, iou_dist: f32) -> Option<f32> {
+        if !self.with_reid || iou_dist > self.proximity_thresh {
+            return None;
+        }
+        let track_feat = track.features.last()?;
+        let det_feat = det.feature.as_ref()?;
+        let emb_dist = crate::utils::cosine_distance(track_feat, det_feat) / 2.0;
+        (emb_dist <= self.appearance_thresh).then_some(emb_dist)
+    }
+
+    /// Whether both tracks carry features and their latest ones are farther apart than `appearance_thresh`
+    fn appearance_differs(&self, a: &STrack, b: &STrack) -> bool {
+        match (a.features.last(), b.features.last()) {
+            (Some(fa), Some(fb)) => crate::utils::cosine_distance(fa, fb) > self.appearance_thresh,
+            _ => false,
+        }
+    }
+
+    /// Whether a detection lies on a tracked or lost track (IoU distance below
+    /// `proximity_thresh`), so a new track for it would duplicate that object
+    fn overlaps_existing_track(&self, det: &crate::detection::Detection) -> bool {
+        self.tracked_stracks.iter()
+            .chain(&self.lost_stracks)
+            .filter(|t| !matches!(t.state, TrackState::Removed))
+            .any(|t| 1.0 - crate::utils::compute_iou(&t.tlwh, &det.tlwh) < self.proximity_thresh)
+    }
+
+    /// Take the removed track from the re-ID gallery that best matches a detection's appearance
+    fn match_removed_track(&mut self, det: &crate::detection::Detection) -> Option<STrack> {
+        if !(self.with_reid && self.reid_removed) {
+            return None;
+        }
+        let feat = det.feature.as_ref()?;
+        self.reid_gallery.take_match(feat, self.appearance_thresh)
+    }
+
+    /// Remove duplicate tracks based on IoU and track age, among tracked tracks and between
+    /// tracked and lost tracks, returning the IDs removed. Like any other removal, the
+    /// duplicates go to `removed_stracks` and the re-ID gallery.
+    fn remove_duplicate_tracks(&mut self, frame_id: i32) -> Vec<u32> {
+        let mut removed = Vec::new();
+        let duplicate_iou = if self.crowd_mode {
+            self.duplicate_iou.max(CROWD_DUPLICATE_IOU)
+        } else {
+            self.duplicate_iou
+        };
         let mut duplicates = Vec::new();
         for (i, track1) in self.tracked_stracks.iter().enumerate() {
-            for (j, track2) in self.tracked_stracks.iter().enumerate() {
-                if i >= j { continue; }
+            // Each unordered pair is compared once
+            for (j, track2) in self.tracked_stracks.iter().enumerate().skip(i + 1) {
+                let iou = crate::utils::compute_iou(&track1.tlwh, &track2.tlwh);
                 
-                let tlbr1 = STrack::tlwh_to_tlbr(&track1.tlwh);
-                let tlbr2 = STrack::tlwh_to_tlbr(&track2.tlwh);
-                let iou = crate::utils::compute_iou(&tlbr1, &tlbr2);
+                // In crowds, overlapping tracks that look different are distinct people
+                if self.crowd_mode && self.appearance_differs(track1, track2) {
+                    continue;
+                }
                 
-                if iou > 0.7 {
+                if iou > duplicate_iou {
                     // Keep the track that was tracked longer
                     if track1.tracklet_len > track2.tracklet_len {
                         duplicates.push(j);
@@ -940,8 +1747,48 @@ impl SMILEtrack {
         duplicates.sort_unstable();
         duplicates.dedup();
         for &idx in duplicates.iter().rev() {
-            self.tracked_stracks.remove(idx);
+            removed.push(self.tracked_stracks.remove(idx));
         }
+
+        // A lost track sitting on a tracked one would refind into an ID clash
+        let mut dup_tracked = Vec::new();
+        let mut dup_lost = Vec::new();
+        for (i, tracked) in self.tracked_stracks.iter().enumerate() {
+            for (j, lost) in self.lost_stracks.iter().enumerate() {
+                // An occluded track overlaps its occluder by definition
+                if lost.is_occluded() {
+                    continue;
+                }
+                if self.crowd_mode && self.appearance_differs(tracked, lost) {
+                    continue;
+                }
+                if crate::utils::compute_iou(&tracked.tlwh, &lost.tlwh) > duplicate_iou {
+                    if tracked.tracklet_len > lost.tracklet_len {
+                        dup_lost.push(j);
+                    } else {
+                        dup_tracked.push(i);
+                    }
+                }
+            }
+        }
+        for (list, mut dups) in [(&mut self.tracked_stracks, dup_tracked), (&mut self.lost_stracks, dup_lost)] {
+            dups.sort_unstable();
+            dups.dedup();
+            for &idx in dups.iter().rev() {
+                removed.push(list.remove(idx));
+            }
+        }
+
+        let mut removed_ids = Vec::with_capacity(removed.len());
+        for mut track in removed {
+            track.mark_removed();
+            removed_ids.push(track.track_id);
+            if self.with_reid && self.reid_removed {
+                self.reid_gallery.insert(track.clone(), frame_id);
+            }
+            self.removed_stracks.push(track);
+        }
+        removed_ids
     }
 }
 
@@ -1149,6 +1996,79 @@ mod tests {
     use std::path::Path;
     use approx::assert_relative_eq;
 
+    fn test_config() -> crate::config::Config {
+        crate::config::Config {
+            model_path: String::from("model.pt"),
+            track_high_thresh: 0.5,
+            track_low_thresh: 0.3,
+            new_track_thresh: 0.4,
+            appearance_thresh: 0.8,
+            n_init: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Wide canvas of pseudo-random shapes; crops of it give frames with a known pan
+    fn random_canvas() -> Mat {
+        let mut canvas = Mat::new_size_with_default(
+            Size::new(1000, 480),
+            CV_8UC3,
+            Scalar::new(128.0, 128.0, 128.0, 0.0),
+        ).unwrap();
+        let mut seed: u32 = 12345;
+        let mut next = |max: u32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) % max
+        };
+        for _ in 0..400 {
+            let (x, y) = (next(1000) as i32, next(480) as i32);
+            let size = 4 + next(20) as i32;
+            let shade = next(256) as f64;
+            let color = Scalar::new(shade, 255.0 - shade, (shade * 3.0) % 256.0, 0.0);
+            if next(2) == 0 {
+                imgproc::circle(&mut canvas, Point::new(x, y), size, color, -1, imgproc::LINE_8, 0).unwrap();
+            } else {
+                imgproc::rectangle(&mut canvas, Rect_::new(x, y, size, size * 2), color, -1, imgproc::LINE_8, 0).unwrap();
+            }
+        }
+        canvas
+    }
+
+    fn crop(canvas: &Mat, x: i32) -> Mat {
+        Mat::roi(canvas, Rect_::new(x, 0, 640, 480)).unwrap().try_clone().unwrap()
+    }
+    /// Frame with a grid of distinct blocks shifted by (dx, dy), giving GMC plenty of corners
+    fn textured_frame(dx: i32, dy: i32) -> Mat {
+        let mut frame = Mat::new_size_with_default(
+            Size::new(640, 480),
+            CV_8UC3,
+            Scalar::new(255.0, 255.0, 255.0, 0.0),
+        ).unwrap();
+        for i in 0..8 {
+            for j in 0..6 {
+                let size = 10 + (i * 7 + j * 13) % 25;
+                let rect = Rect_::new(40 + i * 70 + dx, 40 + j * 70 + dy, size, size);
+                let shade = ((i * 31 + j * 17) % 128) as f64;
+                imgproc::rectangle(
+                    &mut frame,
+                    rect,
+                    Scalar::new(shade, shade, shade, 0.0),
+                    -1,
+                    imgproc::LINE_8,
+                    0,
+                ).unwrap();
+            }
+        }
+        frame
+    }
+    fn blank_frame() -> Mat {
+        Mat::new_size_with_default(
+            Size::new(640, 480),
+            CV_8UC3,
+            Scalar::all(0.0),
+        ).unwrap()
+    }
+
     #[test]
     fn test_kalman_filter_predict() {
         let kf = KalmanFilter::new();
@@ -1210,22 +2130,251 @@ mod tests {
         assert!(matches!(track.state, TrackState::Tracked));
     }
 
+    #[test]
+    fn test_class_vote_weighted_by_confidence() {
+        let bbox = SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0);
+        let mut track = STrack::new(bbox, 0.95, 0, None, 1);
+        track.activate(&KalmanFilter::new(), 1, 1);
+
+        // Three confident "person" frames, then six weak "bicycle" frames: a plain count
+        // would vote bicycle (6 vs 3), the weighted vote keeps person (2.85 vs 1.2)
+        for frame_id in 2..=3 {
+            track.update(&Detection::new(bbox, 0.95, 0, None), frame_id, None);
+        }
+        for frame_id in 4..=9 {
+            track.update(&Detection::new(bbox, 0.2, 1, None), frame_id, None);
+        }
+        assert_eq!(track.class_id, 0);
+
+        // Enough bicycle evidence eventually flips it
+        track.update(&Detection::new(bbox, 0.9, 1, None), 10, None);
+        track.update(&Detection::new(bbox, 0.9, 1, None), 11, None);
+        assert_eq!(track.class_id, 1);
+    }
+
+    #[test]
+    fn test_tracks_filtered_drops_small_tracks() {
+        let frame = blank_frame();
+        let mut config = test_config();
+        config.min_track_area = 400.0;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+        let dets = [
+            Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None),
+            Detection::new(SVector::<f32, 4>::new(400.0, 300.0, 10.0, 20.0), 0.9, 0, None),
+        ];
+        tracker.update(&dets, &frame, 1).unwrap();
+        tracker.update(&dets, &frame, 2).unwrap();
+
+        assert_eq!(tracker.tracks().len(), 2);
+        let filtered = tracker.tracks_filtered();
+        assert_eq!(filtered.len(), 1);
+        assert!(filtered[0].tlwh()[2] > 40.0);
+    }
+
+    #[test]
+    fn test_adaptive_noise_follows_innovation() {
+        let kf = KalmanFilter::new();
+        let run = |jitter: f32, adaptive: bool| {
+            let mut track = STrack::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None, 1);
+            track.adaptive_noise = adaptive;
+            track.activate(&kf, 1, 1);
+            for frame_id in 2..=10 {
+                track.predict();
+                // Steady 3 px/frame motion, optionally jumping back and forth
+                let sign = if frame_id % 2 == 0 { 1.0 } else { -1.0 };
+                let x = 100.0 + 3.0 * frame_id as f32 + sign * jitter;
+                track.update(&Detection::new(SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None), frame_id, None);
+            }
+            let before = track.covariance[(4, 4)];
+            track.predict();
+            (track.covariance[(4, 4)] - before, track.noise_scale())
+        };
+
+        let (smooth_growth, smooth_scale) = run(0.0, true);
+        let (erratic_growth, erratic_scale) = run(30.0, true);
+        assert!(erratic_scale > smooth_scale, "scales {} vs {}", erratic_scale, smooth_scale);
+        assert!(erratic_growth > smooth_growth, "growth {} vs {}", erratic_growth, smooth_growth);
+
+        // Off by default: the same noise regardless of innovation
+        let (fixed_smooth, _) = run(0.0, false);
+        let (fixed_erratic, _) = run(30.0, false);
+        assert!((fixed_smooth - fixed_erratic).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_metadata_persists_across_updates() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let person = |dx: f32| Detection::new(SVector::<f32, 4>::new(100.0 + dx, 100.0, 50.0, 100.0), 0.9, 0, None);
+
+        tracker.update(&[person(0.0)], &frame, 1).unwrap();
+        let id = tracker.tracks()[0].track_id();
+        tracker.track_by_id_mut(id).unwrap().set_meta("role", "VIP");
+        tracker.track_by_id_mut(id).unwrap().set_meta("db_key", "42");
+
+        for frame_id in 2..=4 {
+            tracker.update(&[person(frame_id as f32 * 2.0)], &frame, frame_id).unwrap();
+        }
+        // Lost for a frame, then re-found
+        tracker.update(&[], &frame, 5).unwrap();
+        assert_eq!(tracker.lost_stracks[0].get_meta("role"), Some("VIP"));
+        tracker.update(&[person(12.0)], &frame, 6).unwrap();
+
+        let track = &tracker.tracks()[0];
+        assert_eq!(track.track_id(), id);
+        assert_eq!(track.get_meta("role"), Some("VIP"));
+        assert_eq!(track.get_meta("db_key"), Some("42"));
+        assert_eq!(track.clone().get_meta("role"), Some("VIP"));
+        assert_eq!(track.get_meta("missing"), None);
+    }
+
+    #[test]
+    fn test_detection_source_carried_to_tracks() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let detections = |dx: f32| {
+            vec![
+                Detection::new(SVector::<f32, 4>::new(100.0 + dx, 100.0, 50.0, 100.0), 0.9, 0, None)
+                    .with_source("person_model"),
+                Detection::new(SVector::<f32, 4>::new(400.0 - dx, 300.0, 120.0, 60.0), 0.85, 2, None)
+                    .with_source("vehicle_model"),
+            ]
+        };
+
+        for frame_id in 1..=3 {
+            tracker.update(&detections(frame_id as f32 * 3.0), &frame, frame_id).unwrap();
+        }
+
+        let tracks = tracker.tracks();
+        assert_eq!(tracks.len(), 2);
+        for track in tracks.iter() {
+            let expected = if track.class_id == 0 { "person_model" } else { "vehicle_model" };
+            assert_eq!(track.source.as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_predict_ahead_constant_velocity() {
+        let mut track = STrack::new(SVector::<f32, 4>::new(100.0, 200.0, 40.0, 80.0), 0.9, 0, None, 1);
+        track.mean[4] = 3.0;
+        track.mean[5] = -2.0;
+        let before = track.mean;
+
+        let ahead = track.predict_ahead(5);
+        assert_relative_eq!(ahead[0] + ahead[2] / 2.0, 120.0 + 5.0 * 3.0, epsilon = 1e-4);
+        assert_relative_eq!(ahead[1] + ahead[3] / 2.0, 240.0 + 5.0 * -2.0, epsilon = 1e-4);
+        assert_eq!(track.mean, before);
+        assert_eq!(track.predict_ahead(0), track.state_to_tlwh());
+    }
+
+    #[test]
+    fn test_heading_follows_straight_line_motion() {
+        let mut track = STrack::new(SVector::<f32, 4>::new(100.0, 100.0, 60.0, 60.0), 0.9, 0, None, 1);
+        track.activate(&KalmanFilter::new(), 1, 1);
+        assert_eq!(track.speed(), 0.0);
+
+        // Moves 3 px right and 4 px down per frame
+        for frame_id in 2..=20 {
+            let t = (frame_id - 1) as f32;
+            let det = Detection::new(SVector::<f32, 4>::new(100.0 + 3.0 * t, 100.0 + 4.0 * t, 60.0, 60.0), 0.9, 0, None);
+            track.predict();
+            track.update(&det, frame_id, None);
+        }
+
+        assert_relative_eq!(track.heading(), 4.0f32.atan2(3.0), epsilon = 0.02);
+        let (vx, vy) = track.velocity();
+        assert!(vx > 0.0 && vy > 0.0);
+        assert_relative_eq!(track.speed(), 5.0, epsilon = 0.5);
+    }
+
+    #[test]
+    fn test_age_and_tracklet_length() {
+        let mut track = STrack::new(SVector::<f32, 4>::new(100.0, 100.0, 60.0, 60.0), 0.9, 0, None, 1);
+        track.activate(&KalmanFilter::new(), 5, 1);
+        assert_eq!((track.age(), track.tracklet_length(), track.last_seen_frame()), (0, 0, 5));
+
+        let det = Detection::new(SVector::<f32, 4>::new(102.0, 100.0, 60.0, 60.0), 0.9, 0, None);
+        for frame_id in 6..=8 {
+            track.predict();
+            track.update(&det, frame_id, None);
+            assert_eq!(track.age(), frame_id - 5);
+        }
+        assert_eq!(track.tracklet_length(), 3);
+
+        // Lost for two frames, then found again
+        track.re_activate(&det, 11, false);
+        assert_eq!(track.tracklet_length(), 0);
+        assert_eq!(track.age(), 6);
+        assert_eq!(track.last_seen_frame(), 11);
+    }
+
+    #[test]
+    fn test_raw_and_filtered_boxes_kept_apart() {
+        let kalman = KalmanFilter::new();
+        let start = SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0);
+        let mut track = STrack::new(start, 0.9, 0, None, 1);
+        track.activate(&kalman, 1, 1);
+        assert_eq!(*track.last_det_tlwh(), start);
+
+        // A measurement well off the motion model's prediction
+        let offset = SVector::<f32, 4>::new(130.0, 90.0, 50.0, 100.0);
+        track.predict();
+        track.update(&Detection::new(offset, 0.8, 0, None), 2, None);
+        assert_eq!(*track.last_det_tlwh(), offset);
+        assert!((track.tlwh()[0] - offset[0]).abs() > 1.0);
+        assert!(track.tlwh()[0] > start[0] && track.tlwh()[0] < offset[0]);
+    }
+
+    #[test]
+    fn test_box_smoothing_reduces_jitter() {
+        let run = |alpha: f32| {
+            let mut track = STrack::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None, 1);
+            track.box_smoothing_alpha = alpha;
+            track.activate(&KalmanFilter::new(), 1, 1);
+            let mut xs = Vec::new();
+            for frame_id in 2..=40 {
+                // Deterministic +-6 px jitter around a static box
+                let noise = [6.0, -4.0, 1.0, -6.0, 3.0][frame_id as usize % 5];
+                let det = Detection::new(SVector::<f32, 4>::new(100.0 + noise, 100.0, 50.0, 100.0), 0.9, 0, None);
+                track.predict();
+                track.update(&det, frame_id, None);
+                xs.push(track.tlwh()[0]);
+            }
+            let mean = xs.iter().sum::<f32>() / xs.len() as f32;
+            let variance = xs.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / xs.len() as f32;
+            (variance, track.mean)
+        };
+
+        let (raw_variance, raw_state) = run(0.0);
+        let (smooth_variance, smooth_state) = run(0.7);
+        assert!(smooth_variance < raw_variance * 0.5, "{} vs {}", smooth_variance, raw_variance);
+        // The filter state itself is unaffected
+        assert_eq!(raw_state, smooth_state);
+    }
+
+    #[test]
+    fn test_coast_keeps_aspect_ratio() {
+        // Tall box whose width is growing and height shrinking when detections stop
+        let mut free = STrack::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None, 1);
+        free.mean[6] = 5.0;
+        free.mean[7] = -2.0;
+        let mut coupled = free.clone();
+
+        for _ in 0..20 {
+            free.predict();
+            coupled.predict_keep_aspect();
+        }
+
+        let free_ratio = free.tlwh[3] / free.tlwh[2];
+        let coupled_ratio = coupled.tlwh[3] / coupled.tlwh[2];
+        assert!(free_ratio < 0.5, "unconstrained box should skew, got h/w {}", free_ratio);
+        assert_relative_eq!(coupled_ratio, 2.0, epsilon = 1e-3);
+        assert!(coupled.tlwh[2] > 0.0 && coupled.tlwh[3] > 0.0);
+    }
+
     #[test]
     fn test_smiletrack_matching() {
-        let mut tracker = SMILEtrack::new(
-            &crate::config::Config {
-                model_path: String::from("model.pt"),
-                track_high_thresh: 0.5,
-                track_low_thresh: 0.3,
-                new_track_thresh: 0.4,
-                track_buffer: 30,
-                proximity_thresh: 0.5,
-                appearance_thresh: 0.8,
-                with_reid: false,
-                device: String::from("cpu"),
-            },
-            30.0,
-        );
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
         
         // Create a dummy frame
         let frame = Mat::new_size_with_default(
@@ -1276,6 +2425,677 @@ mod tests {
         assert_eq!(tracker.lost_stracks.len(), 0);
     }
 
+    #[test]
+    fn test_n_init_filters_single_frame_blips() {
+        let frame = blank_frame();
+        let mut config = test_config();
+        config.n_init = 3;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+
+        let steady = |dx: f32| Detection::new(SVector::<f32, 4>::new(100.0 + dx, 100.0, 50.0, 100.0), 0.9, 0, None);
+        let blip = Detection::new(SVector::<f32, 4>::new(400.0, 300.0, 40.0, 40.0), 0.9, 0, None);
+
+        tracker.update(&[steady(0.0), blip], &frame, 1).unwrap();
+        assert_eq!(tracker.tracks().len(), 2);
+        assert!(tracker.tracks().iter().all(|t| !t.is_activated()));
+
+        // The blip misses a frame while still tentative and is deleted outright
+        tracker.update(&[steady(2.0)], &frame, 2).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert!(tracker.lost_stracks.is_empty());
+        assert!(!tracker.tracks()[0].is_activated());
+
+        // Third consistent match confirms the steady object
+        tracker.update(&[steady(4.0)], &frame, 3).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert!(tracker.tracks()[0].is_activated());
+    }
+
+    #[test]
+    fn test_init_iou_gate_drops_lone_detections() {
+        let frame = blank_frame();
+        let mut config = test_config();
+        config.init_iou = 0.3;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+
+        let steady = |dx: f32| Detection::new(SVector::<f32, 4>::new(100.0 + dx, 100.0, 50.0, 100.0), 0.9, 0, None);
+        let blip = |x: f32| Detection::new(SVector::<f32, 4>::new(x, 300.0, 40.0, 40.0), 0.9, 0, None);
+
+        // Nothing is created on the first sighting
+        tracker.update(&[steady(0.0), blip(400.0)], &frame, 1).unwrap();
+        assert!(tracker.tracks().is_empty());
+
+        // The steady object recurs and is seeded; a blip somewhere else does not overlap the first
+        tracker.update(&[steady(2.0), blip(520.0)], &frame, 2).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert!((tracker.tracks()[0].tlwh[1] - 100.0).abs() < 1.0);
+
+        for frame_id in 3..=5 {
+            tracker.update(&[steady(frame_id as f32 * 2.0)], &frame, frame_id).unwrap();
+        }
+        assert_eq!(tracker.tracks().len(), 1);
+        assert_eq!(tracker.tracks()[0].track_id(), 1);
+        assert!(tracker.lost_stracks.is_empty());
+    }
+
+    #[test]
+    fn test_innovation_large_for_far_detection() {
+        let kalman = KalmanFilter::new();
+        let mut steady = STrack::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None, 1);
+        steady.activate(&kalman, 1, 1);
+        let mut jumped = steady.clone();
+        assert_eq!(steady.last_innovation(), 0.0);
+
+        steady.predict();
+        steady.update(&Detection::new(SVector::<f32, 4>::new(102.0, 100.0, 50.0, 100.0), 0.9, 0, None), 2, None);
+        jumped.predict();
+        jumped.update(&Detection::new(SVector::<f32, 4>::new(400.0, 300.0, 50.0, 100.0), 0.9, 0, None), 2, None);
+
+        assert!(steady.last_innovation() < 5.0, "steady innovation {}", steady.last_innovation());
+        assert!(jumped.last_innovation() > 300.0, "jumped innovation {}", jumped.last_innovation());
+    }
+
+    #[test]
+    fn test_last_unmatched_detections() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let person = |dx: f32| Detection::new(SVector::<f32, 4>::new(100.0 + dx, 100.0, 50.0, 100.0), 0.9, 0, None);
+
+        tracker.update(&[person(0.0)], &frame, 1).unwrap();
+        assert_eq!(tracker.last_unmatched_detections().len(), 1);
+
+        // A spurious box far from the existing track stays unassociated
+        let spurious = Detection::new(SVector::<f32, 4>::new(500.0, 350.0, 30.0, 30.0), 0.8, 2, None);
+        tracker.update(&[person(3.0), spurious], &frame, 2).unwrap();
+        let unmatched = tracker.last_unmatched_detections();
+        assert_eq!(unmatched.len(), 1);
+        assert_eq!(unmatched[0].class_id, 2);
+        assert_relative_eq!(unmatched[0].tlwh[0], 500.0);
+
+        tracker.update(&[person(6.0)], &frame, 3).unwrap();
+        assert!(tracker.last_unmatched_detections().is_empty());
+    }
+
+    #[test]
+    fn test_size_weight_prefers_similar_size() {
+        let frame = blank_frame();
+        let run = |size_weight: f32| {
+            let mut config = test_config();
+            config.size_weight = size_weight;
+            let mut tracker = SMILEtrack::new(&config, 30.0);
+            tracker.update(&[Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 100.0, 100.0), 0.9, 0, None)], &frame, 1).unwrap();
+
+            // Both candidates have IoU 0.6 with the track: a smaller box inside it and a shifted same-size box
+            let smaller = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 60.0, 100.0), 0.9, 0, None);
+            let shifted = Detection::new(SVector::<f32, 4>::new(125.0, 100.0, 100.0, 100.0), 0.9, 0, None);
+            assert_relative_eq!(
+                crate::utils::compute_iou(&tracker.tracks()[0].tlwh, &smaller.tlwh),
+                crate::utils::compute_iou(&tracker.tracks()[0].tlwh, &shifted.tlwh),
+            );
+            tracker.update(&[smaller, shifted], &frame, 2).unwrap();
+            tracker.last_unmatched_detections()[0].tlwh[2]
+        };
+
+        // Without the size term the tie goes to the first (smaller) box; with it the same-size box wins
+        assert_relative_eq!(run(0.0), 100.0);
+        assert_relative_eq!(run(0.5), 60.0);
+    }
+
+    #[test]
+    fn test_proximity_and_appearance_thresholds_affect_association() {
+        let frame = blank_frame();
+        let run = |proximity_thresh: f32, appearance_thresh: f32| {
+            let mut config = test_config();
+            config.with_reid = true;
+            config.proximity_thresh = proximity_thresh;
+            config.appearance_thresh = appearance_thresh;
+            let mut tracker = SMILEtrack::new(&config, 30.0);
+            let feat = Some(vec![0.0, 1.0, 0.0]);
+            tracker.update(&[Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, feat)], &frame, 1).unwrap();
+
+            // Jumped 20px: IoU 0.43 fails the IoU gate alone, but the appearance is nearly identical
+            let feat = Some(vec![0.05, 1.0, 0.0]);
+            tracker.update(&[Detection::new(SVector::<f32, 4>::new(120.0, 100.0, 50.0, 100.0), 0.9, 0, feat)], &frame, 2).unwrap();
+            tracker.last_unmatched_detections().is_empty()
+        };
+
+        assert!(run(0.7, 0.25), "close pair should match on appearance");
+        assert!(!run(0.3, 0.25), "pair beyond proximity_thresh is appearance-ineligible");
+        assert!(!run(0.7, 0.0001), "appearance_thresh caps the embedding distance");
+    }
+
+    #[test]
+    fn test_duplicate_lost_track_pruned() {
+        let kalman = KalmanFilter::new();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let make = |id: u32, x: f32, tracklet_len: i32| {
+            let mut track = STrack::new(SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None, 1);
+            track.activate(&kalman, 1, id);
+            track.tracklet_len = tracklet_len;
+            track
+        };
+
+        // Older lost track under a young tracked one: the tracked one goes
+        let mut lost = make(1, 100.0, 20);
+        lost.mark_lost();
+        tracker.lost_stracks.push(lost);
+        tracker.tracked_stracks.push(make(2, 102.0, 3));
+        // Far away lost track is left alone
+        let mut other = make(3, 400.0, 1);
+        other.mark_lost();
+        tracker.lost_stracks.push(other);
+
+        tracker.remove_duplicate_tracks(1);
+        assert!(tracker.tracked_stracks.is_empty());
+        let lost_ids: Vec<u32> = tracker.lost_stracks.iter().map(|t| t.track_id()).collect();
+        assert_eq!(lost_ids, vec![1, 3]);
+
+        // Younger lost duplicate of an established tracked track: the lost one goes
+        tracker.tracked_stracks.push(make(4, 400.0, 30));
+        tracker.remove_duplicate_tracks(1);
+        assert_eq!(tracker.tracked_stracks.len(), 1);
+        let lost_ids: Vec<u32> = tracker.lost_stracks.iter().map(|t| t.track_id()).collect();
+        assert_eq!(lost_ids, vec![1]);
+    }
+
+    #[test]
+    fn test_duplicate_goes_through_removal_path() {
+        let kalman = KalmanFilter::new();
+        let mut config = test_config();
+        config.with_reid = true;
+        config.reid_removed = true;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+        for (id, tracklet_len) in [(1, 20), (2, 3)] {
+            let mut track = STrack::new(
+                SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, Some(vec![1.0, 0.0]), 1);
+            track.activate(&kalman, 1, id);
+            track.tracklet_len = tracklet_len;
+            tracker.tracked_stracks.push(track);
+        }
+
+        assert_eq!(tracker.remove_duplicate_tracks(5), vec![2]);
+        assert_eq!(tracker.removed_stracks.len(), 1);
+        assert_eq!(tracker.removed_stracks[0].track_id(), 2);
+        assert!(matches!(tracker.removed_stracks[0].state, TrackState::Removed));
+        // Kept for appearance revival like any other removed track
+        assert_eq!(tracker.reid_gallery.len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_iou_threshold() {
+        let kalman = KalmanFilter::new();
+        let run = |duplicate_iou: f32| {
+            let mut config = test_config();
+            config.duplicate_iou = duplicate_iou;
+            let mut tracker = SMILEtrack::new(&config, 30.0);
+            // Two adjacent people at IoU 0.6
+            for (id, x) in [(1, 100.0), (2, 112.5)] {
+                let mut track = STrack::new(SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None, 1);
+                track.activate(&kalman, 1, id);
+                track.tracklet_len = 10 * id as i32;
+                tracker.tracked_stracks.push(track);
+            }
+            assert_relative_eq!(
+                crate::utils::compute_iou(&tracker.tracked_stracks[0].tlwh, &tracker.tracked_stracks[1].tlwh),
+                0.6,
+                epsilon = 1e-4
+            );
+            let removed = tracker.remove_duplicate_tracks(1);
+            (tracker.tracked_stracks.len(), removed)
+        };
+
+        assert_eq!(run(0.7), (2, vec![]));
+        // The younger track goes
+        assert_eq!(run(0.5), (1, vec![1]));
+    }
+
+    #[test]
+    fn test_no_new_track_on_top_of_lagging_track() {
+        let frame = blank_frame();
+        let mut config = test_config();
+        // The size penalty pushes a fast-growing box past the match gate
+        config.size_weight = 1.0;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+
+        for frame_id in 1..=3 {
+            let det = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None);
+            tracker.update(&[det], &frame, frame_id).unwrap();
+        }
+        // Object approaching the camera: IoU with the lagging prediction is still ~0.69
+        let grown = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 60.0, 120.0), 0.9, 0, None);
+        let events = tracker.update(&[grown], &frame, 4).unwrap();
+
+        assert!(!events.iter().any(|e| matches!(e, TrackEvent::Created { .. })), "{:?}", events);
+        assert_eq!(tracker.track_id_count, 1);
+        assert_eq!(tracker.lost_stracks.len(), 1);
+        assert_eq!(tracker.lost_stracks[0].track_id(), 1);
+    }
+
+    #[test]
+    fn test_crowd_mode_keeps_overlapping_distinct_tracks() {
+        let frame = blank_frame();
+        let run = |crowd_mode: bool, dx: f32| {
+            let mut config = test_config();
+            config.with_reid = true;
+            config.crowd_mode = crowd_mode;
+            let mut tracker = SMILEtrack::new(&config, 30.0);
+            // Two people standing close together, with clearly different appearance
+            let dets = [
+                Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, Some(vec![1.0, 0.0, 0.0])),
+                Detection::new(SVector::<f32, 4>::new(100.0 + dx, 100.0, 50.0, 100.0), 0.85, 0, Some(vec![0.0, 1.0, 0.0])),
+            ];
+            tracker.update(&dets, &frame, 1).unwrap();
+            tracker.tracks().len()
+        };
+
+        // IoU 0.79: merged normally, kept in crowd mode
+        assert_eq!(run(false, 6.0), 1);
+        assert_eq!(run(true, 6.0), 2);
+        // IoU 0.92 is above even the crowd threshold, but the appearance differs
+        assert_eq!(run(true, 2.0), 2);
+    }
+
+    #[test]
+    fn test_adaptive_thresh_bridges_low_confidence_frame() {
+        let frame = blank_frame();
+        let run = |adaptive_thresh: bool| {
+            let mut config = test_config();
+            config.adaptive_thresh = adaptive_thresh;
+            let mut tracker = SMILEtrack::new(&config, 30.0);
+            let person = |dx: f32, score: f32| Detection::new(SVector::<f32, 4>::new(100.0 + dx, 100.0, 50.0, 100.0), score, 0, None);
+
+            tracker.update(&[person(0.0, 0.9)], &frame, 1).unwrap();
+            // Motion blur: everything falls between track_low_thresh and track_high_thresh
+            tracker.update(&[person(2.0, 0.4), Detection::new(SVector::<f32, 4>::new(400.0, 300.0, 30.0, 30.0), 0.35, 0, None)], &frame, 2).unwrap();
+            (tracker.tracks().len(), tracker.lost_stracks.len(), tracker.track_id_count)
+        };
+
+        assert_eq!(run(false), (0, 1, 1));
+        // The track survives and the other low-score box does not start a new track
+        assert_eq!(run(true), (1, 0, 1));
+    }
+
+    #[test]
+    fn test_rear_track_occluded_while_crossing() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        // A walks right in front of B, who stands still further back (smaller box)
+        let front = |frame_id: i32| {
+            Detection::new(SVector::<f32, 4>::new(150.0 + 10.0 * frame_id as f32, 100.0, 60.0, 120.0), 0.9, 0, None)
+        };
+        let rear = Detection::new(SVector::<f32, 4>::new(300.0, 120.0, 40.0, 80.0), 0.9, 0, None);
+
+        for frame_id in 1..=11 {
+            tracker.update(&[front(frame_id), rear.clone()], &frame, frame_id).unwrap();
+        }
+        assert_eq!(tracker.tracks().len(), 2);
+        let rear_id = tracker.tracks().iter().find(|t| t.tlwh()[2] < 50.0).unwrap().track_id();
+
+        // Only A is visible while it passes in front of B
+        let events = tracker.update(&[front(12)], &frame, 12).unwrap();
+        assert_eq!(events, vec![TrackEvent::Occluded { id: rear_id }]);
+        for frame_id in 13..=14 {
+            tracker.update(&[front(frame_id)], &frame, frame_id).unwrap();
+        }
+        let occluded: Vec<u32> = tracker.occluded_tracks().map(|t| t.track_id()).collect();
+        assert_eq!(occluded, vec![rear_id]);
+        assert!(tracker.tracks().iter().all(|t| t.track_id() != rear_id));
+
+        // A track that vanishes in the open is plainly lost
+        tracker.update(&[], &frame, 15).unwrap();
+        let front_track = tracker.lost_stracks.iter().find(|t| t.track_id() != rear_id).unwrap();
+        assert!(matches!(front_track.state, TrackState::Lost));
+    }
+
+    #[test]
+    fn test_predict_only_advances_tracks() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let person = |x: f32| Detection::new(SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None);
+        for frame_id in 1..=5 {
+            tracker.update(&[person(100.0 + 4.0 * frame_id as f32)], &frame, frame_id).unwrap();
+        }
+        let before = tracker.tracks()[0].tlwh()[0];
+
+        tracker.predict_only(&frame, 6).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert!(tracker.tracks()[0].tlwh()[0] > before);
+
+        // The next detection frame still associates with the same track
+        tracker.update(&[person(128.0)], &frame, 7).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert_eq!(tracker.tracks()[0].track_id(), 1);
+    }
+
+    #[test]
+    fn test_deterministic_ids_independent_of_input_order() {
+        let mut config = test_config();
+        config.deterministic = true;
+        let frame = blank_frame();
+        let dets = |frame_id: i32| -> Vec<Detection> {
+            (0..3)
+                .map(|i| {
+                    let x = 50.0 + 150.0 * i as f32 + 3.0 * frame_id as f32;
+                    Detection::new(SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None)
+                })
+                .collect()
+        };
+
+        let mut forward = SMILEtrack::new(&config, 30.0);
+        let mut shuffled = SMILEtrack::new(&config, 30.0);
+        for frame_id in 1..=3 {
+            let mut reordered = dets(frame_id);
+            reordered.rotate_left(frame_id as usize);
+            forward.update(&dets(frame_id), &frame, frame_id).unwrap();
+            shuffled.update(&reordered, &frame, frame_id).unwrap();
+        }
+
+        let ids = |tracker: &SMILEtrack| -> Vec<(u32, i32)> {
+            let mut ids: Vec<_> = tracker.tracks().iter().map(|t| (t.track_id(), t.tlwh()[0].round() as i32)).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(forward.tracks().len(), 3);
+        assert_eq!(ids(&forward), ids(&shuffled));
+    }
+
+    #[test]
+    fn test_new_track_skips_premature_predict() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let tlwh = SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0);
+        tracker.update(&[Detection::new(tlwh, 0.9, 0, None)], &frame, 1).unwrap();
+        let (mean, covariance) = KalmanFilter::new().initiate(&tlwh);
+
+        // Frame 2 without detections: the one-frame-old track is not predicted
+        tracker.update(&[], &frame, 2).unwrap();
+        let track = &tracker.lost_stracks[0];
+        assert_eq!(track.state_to_tlwh(), tlwh);
+        assert_eq!(track.mean, mean);
+        assert_eq!(track.covariance, covariance);
+
+        // Older tracks are predicted as usual
+        tracker.update(&[], &frame, 3).unwrap();
+        assert!(tracker.lost_stracks[0].covariance[(0, 0)] > covariance[(0, 0)]);
+    }
+
+    #[test]
+    fn test_lifecycle_events_in_order() {
+        let frame = blank_frame();
+        let config = crate::config::Config { track_buffer: 3, ..test_config() };
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+        let det = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 2, None);
+
+        let mut events = tracker.update(&[det], &frame, 1).unwrap();
+        for frame_id in 2..=8 {
+            events.extend(tracker.update(&[], &frame, frame_id).unwrap());
+        }
+
+        assert_eq!(events, vec![
+            TrackEvent::Created { id: 1, class: 2 },
+            TrackEvent::Confirmed { id: 1 },
+            TrackEvent::Lost { id: 1 },
+            TrackEvent::Removed { id: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_refound_event_after_lost() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let det = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None);
+
+        let mut events = tracker.update(&[det.clone()], &frame, 1).unwrap();
+        events.extend(tracker.update(&[], &frame, 2).unwrap());
+        events.extend(tracker.update(&[det], &frame, 3).unwrap());
+
+        assert_eq!(events, vec![
+            TrackEvent::Created { id: 1, class: 0 },
+            TrackEvent::Confirmed { id: 1 },
+            TrackEvent::Lost { id: 1 },
+            TrackEvent::Refound { id: 1 },
+        ]);
+        assert_eq!(tracker.tracks()[0].track_id(), 1);
+    }
+
+    #[test]
+    fn test_refound_event_on_gallery_revival() {
+        let frame = blank_frame();
+        let mut config = test_config();
+        config.with_reid = true;
+        config.reid_removed = true;
+        config.appearance_thresh = 0.25;
+        config.track_buffer = 2;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+
+        let det = Detection::new(
+            SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, Some(vec![1.0, 0.0, 0.0]));
+        tracker.update(&[det], &frame, 1).unwrap();
+        let mut events = Vec::new();
+        for frame_id in 2..=6 {
+            events.extend(tracker.update(&[], &frame, frame_id).unwrap());
+        }
+        assert!(events.contains(&TrackEvent::Removed { id: 1 }), "{:?}", events);
+
+        // The same appearance elsewhere brings ID 1 back, announced rather than silent
+        let det = Detection::new(
+            SVector::<f32, 4>::new(400.0, 150.0, 50.0, 100.0), 0.9, 0, Some(vec![0.95, 0.1, 0.0]));
+        let events = tracker.update(&[det], &frame, 7).unwrap();
+        assert_eq!(events, vec![TrackEvent::Refound { id: 1 }]);
+    }
+
+    #[test]
+    fn test_save_load_state_resumes_ids() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let dets_at = |frame_id: i32| {
+            let dx = frame_id as f32 * 2.0;
+            let mut dets = vec![
+                Detection::new(SVector::<f32, 4>::new(100.0 + dx, 100.0, 50.0, 50.0), 0.9, 1, None),
+                Detection::new(SVector::<f32, 4>::new(300.0 + dx, 200.0, 50.0, 50.0), 0.8, 1, None),
+            ];
+            // A third object enters after the checkpoint to exercise the ID counter
+            if frame_id >= 5 {
+                dets.push(Detection::new(SVector::<f32, 4>::new(500.0, 50.0, 40.0, 80.0), 0.9, 0, None));
+            }
+            dets
+        };
+
+        for frame_id in 1..=3 {
+            tracker.update(&dets_at(frame_id), &frame, frame_id).unwrap();
+        }
+
+        let path = std::env::temp_dir().join("smiletrack_test_state.json");
+        let path = path.to_str().unwrap();
+        tracker.save_state(path).unwrap();
+        let mut resumed = SMILEtrack::load_state(path).unwrap();
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(resumed.track_id_count, tracker.track_id_count);
+        for frame_id in 4..=6 {
+            tracker.update(&dets_at(frame_id), &frame, frame_id).unwrap();
+            resumed.update(&dets_at(frame_id), &frame, frame_id).unwrap();
+
+            let ids = |t: &SMILEtrack| t.tracks().iter().map(|t| t.track_id()).collect::<Vec<_>>();
+            assert_eq!(ids(&tracker), ids(&resumed));
+            for (a, b) in tracker.tracks().iter().zip(resumed.tracks()) {
+                assert_relative_eq!(a.tlwh[0], b.tlwh[0], epsilon = 1e-4);
+                assert_relative_eq!(a.tlwh[1], b.tlwh[1], epsilon = 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_match_uses_true_iou() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let det_at = |x: f32| Detection::new(SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None);
+        tracker.update(&[det_at(100.0)], &frame, 1).unwrap();
+
+        // IoU 0.25 is below the match threshold; read as tlbr-inflated boxes it would be 0.63
+        tracker.update(&[det_at(130.0)], &frame, 2).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert_eq!(tracker.tracks()[0].track_id(), 2);
+        assert_eq!(tracker.lost_stracks.len(), 1);
+        assert_eq!(tracker.lost_stracks[0].track_id(), 1);
+    }
+
+    #[test]
+    fn test_unmatched_track_marked_lost() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let det = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None);
+        tracker.update(&[det], &frame, 1).unwrap();
+        assert_eq!(tracker.tracked_stracks.len(), 1);
+
+        // A missed frame moves the track to the lost list and out of the tracked list
+        tracker.update(&[], &frame, 2).unwrap();
+        assert!(tracker.tracked_stracks.is_empty());
+        assert_eq!(tracker.lost_stracks.len(), 1);
+        assert!(matches!(tracker.lost_stracks[0].state, TrackState::Lost));
+    }
+
+    #[test]
+    fn test_matched_detection_not_reused_for_lost_track() {
+        let frame = blank_frame();
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let det_at = |x: f32| Detection::new(SVector::<f32, 4>::new(x, 0.0, 100.0, 100.0), 0.9, 0, None);
+
+        // Track 1 is seen once and lost
+        tracker.update(&[det_at(0.0)], &frame, 1).unwrap();
+        tracker.update(&[], &frame, 2).unwrap();
+
+        // Track 2 starts next to it, too far off to refind track 1
+        tracker.update(&[det_at(40.0)], &frame, 3).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert_eq!(tracker.tracks()[0].track_id(), 2);
+
+        // A detection between the two overlaps both, but belongs to the tracked one
+        tracker.update(&[det_at(20.0)], &frame, 4).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert_eq!(tracker.tracks()[0].track_id(), 2);
+        assert_eq!(tracker.lost_stracks.len(), 1);
+        assert_eq!(tracker.lost_stracks[0].track_id(), 1);
+        assert!(matches!(tracker.lost_stracks[0].state, TrackState::Lost));
+    }
+
+    #[test]
+    fn test_refound_track_leaves_lost_list() {
+        let frame = blank_frame();
+        let mut config = test_config();
+        config.track_buffer = 1;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+        let det = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, None);
+
+        for frame_id in 1..=3 {
+            tracker.update(&[det.clone()], &frame, frame_id).unwrap();
+        }
+        tracker.update(&[], &frame, 4).unwrap();
+        assert_eq!(tracker.lost_stracks.len(), 1);
+
+        tracker.update(&[det.clone()], &frame, 5).unwrap();
+        assert_eq!(tracker.tracks().len(), 1);
+        assert_eq!(tracker.tracks()[0].track_id(), 1);
+        assert!(tracker.lost_stracks.is_empty());
+
+        // Staying tracked well past max_time_lost must not remove it
+        for frame_id in 6..=10 {
+            tracker.update(&[det.clone()], &frame, frame_id).unwrap();
+        }
+        assert_eq!(tracker.tracks().len(), 1);
+        assert!(matches!(tracker.tracks()[0].state, TrackState::Tracked));
+        assert!(tracker.removed_stracks.is_empty());
+    }
+
+    #[test]
+    fn test_tracks_store_features_with_reid() {
+        let frame = blank_frame();
+        let mut config = test_config();
+        config.with_reid = true;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+        let det = Detection::new(
+            SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, Some(vec![1.0, 0.0, 0.0]));
+
+        tracker.update(&[det.clone()], &frame, 1).unwrap();
+        assert_eq!(tracker.tracks()[0].features.len(), 1);
+        tracker.update(&[det], &frame, 2).unwrap();
+        assert_eq!(tracker.tracks()[0].features.len(), 2);
+    }
+
+    #[test]
+    fn test_removed_track_regains_id_with_reid() {
+        let frame = blank_frame();
+        let run = |reid_removed: bool| {
+            let mut config = test_config();
+            config.with_reid = true;
+            config.reid_removed = reid_removed;
+            config.appearance_thresh = 0.25;
+            config.track_buffer = 2;
+            let mut tracker = SMILEtrack::new(&config, 30.0);
+
+            // Object is seen once, then leaves long enough to be removed
+            let det = Detection::new(
+                SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, Some(vec![1.0, 0.0, 0.0]));
+            tracker.update(&[det], &frame, 1).unwrap();
+            let original_id = tracker.tracks()[0].track_id();
+            for frame_id in 2..=6 {
+                tracker.update(&[], &frame, frame_id).unwrap();
+            }
+            assert!(tracker.tracks().is_empty());
+            assert!(tracker.lost_stracks.is_empty());
+            assert_eq!(tracker.removed_stracks.len(), 1);
+
+            // It returns elsewhere in the frame with a similar appearance
+            let det = Detection::new(
+                SVector::<f32, 4>::new(400.0, 150.0, 50.0, 100.0), 0.9, 0, Some(vec![0.95, 0.1, 0.0]));
+            tracker.update(&[det], &frame, 7).unwrap();
+            assert_eq!(tracker.tracks().len(), 1);
+            (original_id, tracker.tracks()[0].track_id())
+        };
+
+        let (original_id, revived_id) = run(true);
+        assert_eq!(revived_id, original_id);
+
+        let (original_id, new_id) = run(false);
+        assert_ne!(new_id, original_id);
+    }
+
+    #[test]
+    fn test_id_restored_across_cut_within_gallery_window() {
+        let frame = blank_frame();
+        let run = |gap: i32| {
+            let mut config = test_config();
+            config.with_reid = true;
+            config.reid_removed = true;
+            config.track_buffer = 5;
+            config.reid_gallery_window = 30;
+            let mut tracker = SMILEtrack::new(&config, 30.0);
+
+            let feature = Some(vec![0.2, 0.9, 0.1]);
+            for frame_id in 1..=3 {
+                let det = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 100.0), 0.9, 0, feature.clone());
+                tracker.update(&[det], &frame, frame_id).unwrap();
+            }
+            let original_id = tracker.tracks()[0].track_id();
+
+            // Hard cut: the object is gone for longer than track_buffer, then reappears elsewhere
+            for frame_id in 4..4 + gap {
+                tracker.update(&[], &frame, frame_id).unwrap();
+            }
+            assert!(tracker.tracks().is_empty() && tracker.lost_stracks.is_empty());
+            let det = Detection::new(SVector::<f32, 4>::new(420.0, 220.0, 60.0, 120.0), 0.9, 0, Some(vec![0.25, 0.88, 0.1]));
+            tracker.update(&[det], &frame, 4 + gap).unwrap();
+            (original_id, tracker.tracks()[0].track_id())
+        };
+
+        let (original_id, restored_id) = run(20);
+        assert_eq!(restored_id, original_id);
+
+        // After the gallery window the appearance is forgotten
+        let (original_id, new_id) = run(60);
+        assert_ne!(new_id, original_id);
+    }
+
     #[test]
     fn test_gmc_initialization() {
         let mut gmc = GMC::new();
@@ -1393,6 +3213,104 @@ mod tests {
         assert_relative_eq!(track.tlwh[1], 110.0, epsilon = 5.0);
     }
 
+    #[test]
+    fn test_gmc_affine_translation() {
+        let mut gmc = GMC::with_mode(GmcMode::Affine);
+        gmc.apply(&textured_frame(0, 0)).unwrap();
+        let affine = gmc.apply(&textured_frame(20, 10)).unwrap().unwrap();
+
+        assert_eq!(affine.rows(), 2);
+        assert_eq!(affine.cols(), 3);
+        assert_relative_eq!(*affine.at_2d::<f64>(0, 2).unwrap(), 20.0, epsilon = 1.0);
+        assert_relative_eq!(*affine.at_2d::<f64>(1, 2).unwrap(), 10.0, epsilon = 1.0);
+
+        let mut track = STrack::new(
+            SVector::<f32, 4>::new(100.0, 100.0, 100.0, 100.0), 1.0, 1, None, 1);
+        GMC::apply_to_track(&mut track, &affine).unwrap();
+        assert_relative_eq!(track.tlwh[0], 120.0, epsilon = 1.0);
+        assert_relative_eq!(track.tlwh[1], 110.0, epsilon = 1.0);
+        assert_relative_eq!(track.tlwh[2], 100.0, epsilon = 1.0);
+    }
+
+    #[test]
+    fn test_camera_pan_compensated_then_predicted() {
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let person = |x: f32| Detection::new(SVector::<f32, 4>::new(x, 100.0, 50.0, 100.0), 0.9, 0, None);
+        for frame_id in 1..=5 {
+            tracker.update(&[person(100.0 + 4.0 * frame_id as f32)], &textured_frame(0, 0), frame_id).unwrap();
+        }
+        let before = tracker.tracks()[0].mean;
+        assert!(before[4] > 2.0);
+
+        // The camera pans: the whole scene moves by (20, 10) on top of the track's own motion,
+        // which is counted once
+        tracker.predict_only(&textured_frame(20, 10), 6).unwrap();
+        let after = tracker.tracks()[0].tlwh();
+        assert_relative_eq!(after[0], before[0] + 20.0 + before[4], epsilon = 1.0);
+        assert_relative_eq!(after[1], before[1] + 10.0 + before[5], epsilon = 1.0);
+        assert_relative_eq!(after[2], before[2] + before[6], epsilon = 1.0);
+
+        // A pure translation leaves velocities unchanged
+        assert_relative_eq!(tracker.tracks()[0].mean[4], before[4], epsilon = 0.05);
+    }
+
+    #[test]
+    fn test_gmc_orb_recovers_large_shift() {
+        // Camera pans 150px right, so the scene moves 150px left
+        let canvas = random_canvas();
+        let (frame1, frame2) = (crop(&canvas, 0), crop(&canvas, 150));
+
+        let mut orb = GMC::with_features(GmcMode::Affine, GmcFeatures::Orb);
+        assert!(orb.apply(&frame1).unwrap().is_none());
+        let affine = orb.apply(&frame2).unwrap().expect("ORB should find a transform");
+        assert_relative_eq!(*affine.at_2d::<f64>(0, 2).unwrap(), -150.0, epsilon = 2.0);
+        assert_relative_eq!(*affine.at_2d::<f64>(1, 2).unwrap(), 0.0, epsilon = 2.0);
+
+        // Lucas-Kanade cannot follow a shift this large with its default window/pyramid
+        let mut lk = GMC::with_features(GmcMode::Affine, GmcFeatures::OpticalFlow);
+        lk.apply(&frame1).unwrap();
+        let lk_lost = match lk.apply(&frame2).unwrap() {
+            Some(m) if !m.empty() => (*m.at_2d::<f64>(0, 2).unwrap() + 150.0).abs() > 10.0,
+            _ => true,
+        };
+        assert!(lk_lost);
+    }
+
+    #[test]
+    fn test_update_timings_cover_update() {
+        let mut tracker = SMILEtrack::new(&test_config(), 30.0);
+        let dets: Vec<Detection> = (0..5)
+            .map(|i| Detection::new(SVector::<f32, 4>::new(60.0 + 100.0 * i as f32, 100.0, 50.0, 100.0), 0.9, 0, None))
+            .collect();
+        tracker.update(&dets, &textured_frame(0, 0), 1).unwrap();
+
+        let start = std::time::Instant::now();
+        tracker.update(&dets, &textured_frame(4, 2), 2).unwrap();
+        let wall_ms = crate::utils::elapsed_ms(start);
+
+        let timings = tracker.last_timings();
+        assert!(timings.gmc_ms > 0.0 && timings.association_ms > 0.0);
+        assert_eq!(timings.inference_ms, 0.0);
+        assert!(timings.total_ms() <= wall_ms);
+    }
+
+    #[test]
+    fn test_gmc_none_mode_leaves_tracks_untouched() {
+        let mut gmc = GMC::with_mode(GmcMode::None);
+        assert!(gmc.apply(&textured_frame(0, 0)).unwrap().is_none());
+        assert!(gmc.apply(&textured_frame(20, 10)).unwrap().is_none());
+        assert!(gmc.prev_frame.is_none());
+
+        let mut config = test_config();
+        config.gmc_method = GmcMode::None;
+        let mut tracker = SMILEtrack::new(&config, 30.0);
+        let det = Detection::new(SVector::<f32, 4>::new(100.0, 100.0, 50.0, 50.0), 0.9, 1, None);
+        tracker.update(&[det.clone()], &textured_frame(0, 0), 1).unwrap();
+        tracker.update(&[det], &textured_frame(40, 40), 2).unwrap();
+        assert_relative_eq!(tracker.tracks()[0].tlwh[0], 100.0, epsilon = 1e-3);
+        assert_relative_eq!(tracker.tracks()[0].tlwh[1], 100.0, epsilon = 1e-3);
+    }
+
     #[test]
     fn test_detector_people_count() {
         // Skip test if image doesn't exist
@@ -0,0 +1,173 @@
+use anyhow::Result;
+use opencv::{
+    calib3d,
+    core::{self, Mat, Point2f, Scalar, Size, Vector, CV_32FC1},
+    imgproc,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Pinhole intrinsics and lens distortion of a camera, as produced by OpenCV calibration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraCalibration {
+    /// `[[fx, 0, cx], [0, fy, cy], [0, 0, 1]]`
+    pub camera_matrix: [[f64; 3]; 3],
+    /// OpenCV distortion coefficients `k1, k2, p1, p2[, k3[, k4, k5, k6]]`
+    pub dist_coeffs: Vec<f64>,
+}
+
+impl CameraCalibration {
+    /// Load from a JSON file with `camera_matrix` and `dist_coeffs` keys.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let data = fs::read_to_string(path)?;
+        let calibration: CameraCalibration = serde_json::from_str(&data)?;
+        if ![4, 5, 8, 12, 14].contains(&calibration.dist_coeffs.len()) {
+            anyhow::bail!(
+                "calibration in {} needs 4, 5, 8, 12 or 14 distortion coefficients, got {}",
+                path, calibration.dist_coeffs.len()
+            );
+        }
+        Ok(calibration)
+    }
+
+    fn camera_mat(&self) -> Result<Mat> {
+        Ok(Mat::from_slice_2d(&self.camera_matrix)?)
+    }
+
+    fn dist_mat(&self) -> Result<Mat> {
+        Ok(Mat::from_slice(self.dist_coeffs.as_slice())?.try_clone()?)
+    }
+}
+
+/// Removes lens distortion from frames so straight-line motion stays straight for the
+/// constant-velocity motion model. The remap tables are built once per frame size.
+pub struct Undistorter {
+    calibration: CameraCalibration,
+    /// Frame size the maps were built for, and the x / y lookup maps
+    maps: Option<(Size, Mat, Mat)>,
+}
+
+impl Undistorter {
+    pub fn new(calibration: CameraCalibration) -> Self {
+        Undistorter { calibration, maps: None }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        Ok(Self::new(CameraCalibration::from_file(path)?))
+    }
+
+    /// Undistorted copy of `frame`, keeping the original camera matrix.
+    pub fn undistort(&mut self, frame: &Mat) -> Result<Mat> {
+        let size = frame.size()?;
+        if !matches!(&self.maps, Some((built, _, _)) if *built == size) {
+            let camera = self.calibration.camera_mat()?;
+            let mut map_x = Mat::default();
+            let mut map_y = Mat::default();
+            calib3d::init_undistort_rectify_map(
+                &camera,
+                &self.calibration.dist_mat()?,
+                &core::no_array(),
+                &camera,
+                size,
+                CV_32FC1,
+                &mut map_x,
+                &mut map_y,
+            )?;
+            self.maps = Some((size, map_x, map_y));
+        }
+
+        let (_, map_x, map_y) = self.maps.as_ref().expect("maps built above");
+        let mut undistorted = Mat::default();
+        imgproc::remap(
+            frame,
+            &mut undistorted,
+            map_x,
+            map_y,
+            imgproc::INTER_LINEAR,
+            core::BORDER_CONSTANT,
+            Scalar::all(0.0),
+        )?;
+        Ok(undistorted)
+    }
+
+    /// Where a pixel of the distorted image lands in the undistorted one.
+    pub fn undistort_point(&self, pt: Point2f) -> Result<Point2f> {
+        let camera = self.calibration.camera_mat()?;
+        let src: Vector<Point2f> = Vector::from_slice(&[pt]);
+        let mut dst: Vector<Point2f> = Vector::new();
+        calib3d::undistort_points(
+            &src,
+            &mut dst,
+            &camera,
+            &self.calibration.dist_mat()?,
+            &core::no_array(),
+            &camera,
+        )?;
+        Ok(dst.get(0)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{Point, CV_8UC1};
+
+    const FX: f64 = 500.0;
+    const CX: f64 = 320.0;
+    const CY: f64 = 240.0;
+    const K1: f64 = -0.3;
+
+    fn barrel() -> Undistorter {
+        Undistorter::new(CameraCalibration {
+            camera_matrix: [[FX, 0.0, CX], [0.0, FX, CY], [0.0, 0.0, 1.0]],
+            dist_coeffs: vec![K1, 0.0, 0.0, 0.0],
+        })
+    }
+
+    /// Apply the radial model to an undistorted pixel
+    fn distort(x: f64, y: f64) -> (f64, f64) {
+        let (xn, yn) = ((x - CX) / FX, (y - CY) / FX);
+        let factor = 1.0 + K1 * (xn * xn + yn * yn);
+        (xn * factor * FX + CX, yn * factor * FX + CY)
+    }
+
+    #[test]
+    fn test_distorted_point_maps_back() {
+        let undistorter = barrel();
+        let (dx, dy) = distort(520.0, 400.0);
+        let pt = undistorter.undistort_point(Point2f::new(dx as f32, dy as f32)).unwrap();
+        assert!((pt.x - 520.0).abs() < 0.5 && (pt.y - 400.0).abs() < 0.5, "{:?}", pt);
+
+        // The principal point does not move
+        let center = undistorter.undistort_point(Point2f::new(CX as f32, CY as f32)).unwrap();
+        assert!((center.x - CX as f32).abs() < 1e-3 && (center.y - CY as f32).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_undistort_frame_moves_blob() {
+        let mut undistorter = barrel();
+        let (dx, dy) = distort(520.0, 400.0);
+        let mut frame = Mat::new_rows_cols_with_default(480, 640, CV_8UC1, Scalar::all(0.0)).unwrap();
+        imgproc::circle(
+            &mut frame,
+            Point::new(dx.round() as i32, dy.round() as i32),
+            3,
+            Scalar::all(255.0),
+            -1,
+            imgproc::LINE_8,
+            0,
+        ).unwrap();
+
+        let undistorted = undistorter.undistort(&frame).unwrap();
+        assert_eq!(undistorted.size().unwrap(), frame.size().unwrap());
+        let mut max_loc = Point::default();
+        core::min_max_loc(&undistorted, None, None, None, Some(&mut max_loc), &core::no_array()).unwrap();
+        // The blob grows a little, and min_max_loc reports its first saturated pixel
+        assert!((max_loc.x - 520).abs() <= 6 && (max_loc.y - 400).abs() <= 6, "{:?}", max_loc);
+
+        // Maps are reused for frames of the same size
+        undistorter.undistort(&frame).unwrap();
+        assert!(undistorter.maps.is_some());
+    }
+}
@@ -1,10 +1,134 @@
 /// Perform non-max suppression on boxes & scores, return indices to keep.
 use opencv::{core::{Scalar, Point}, imgproc, prelude::*};
-use nalgebra::{Matrix, Const, ArrayStorage};
+use nalgebra::{Matrix, Const, ArrayStorage, SVector};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// COCO class names, indexed by class id
+pub const COCO_CLASSES: &[&str] = &[
+    "person", "bicycle", "car", "motorcycle", "airplane", "bus", "train", "truck", "boat",
+    "traffic light", "fire hydrant", "stop sign", "parking meter", "bench", "bird", "cat",
+    "dog", "horse", "sheep", "cow", "elephant", "bear", "zebra", "giraffe", "backpack",
+    "umbrella", "handbag", "tie", "suitcase", "frisbee", "skis", "snowboard", "sports ball",
+    "kite", "baseball bat", "baseball glove", "skateboard", "surfboard", "tennis racket",
+    "bottle", "wine glass", "cup", "fork", "knife", "spoon", "bowl", "banana", "apple",
+    "sandwich", "orange", "broccoli", "carrot", "hot dog", "pizza", "donut", "cake", "chair",
+    "couch", "potted plant", "bed", "dining table", "toilet", "tv", "laptop", "mouse", "remote",
+    "keyboard", "cell phone", "microwave", "oven", "toaster", "sink", "refrigerator", "book",
+    "clock", "vase", "scissors", "teddy bear", "hair drier", "toothbrush",
+];
+
+/// Values per box in raw YOLOv7 output: x, y, w, h, objectness and 80 class scores
+pub const YOLO_ROW_LEN: usize = 85;
+
+/// Copy the first batch of a `[N, boxes, len]` model output to the host as one contiguous
+/// row-major `Vec`, so boxes can be decoded without an FFI call per scalar.
+pub fn first_batch_rows(output: &tch::Tensor) -> anyhow::Result<Vec<f32>> {
+    let flat = output.get(0)
+        .to_device(tch::Device::Cpu)
+        .to_kind(tch::Kind::Float)
+        .contiguous()
+        .flatten(0, -1);
+    Ok(Vec::<f32>::try_from(&flat)?)
+}
+
+/// COCO name for a class id, or `None` outside the 80-class table
+pub fn coco_class_name(id: i32) -> Option<&'static str> {
+    usize::try_from(id).ok().and_then(|i| COCO_CLASSES.get(i).copied())
+}
+
+/// Official COCO category ids (1-90, with gaps), indexed by class id
+const COCO_CATEGORY_IDS: [i64; 80] = [
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 27,
+    28, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 46, 47, 48, 49, 50, 51, 52, 53,
+    54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 67, 70, 72, 73, 74, 75, 76, 77, 78, 79, 80,
+    81, 82, 84, 85, 86, 87, 88, 89, 90,
+];
+
+/// COCO annotation `category_id` for a class id, or `None` outside the 80-class table
+pub fn coco_category_id(id: i32) -> Option<i64> {
+    usize::try_from(id).ok().and_then(|i| COCO_CATEGORY_IDS.get(i).copied())
+}
+
+/// Fraction of a box's area that must fall outside the frame before `soft_clamp_box` warns
+const OFF_FRAME_WARN_FRACTION: f32 = 0.5;
+
+/// Parse a device string: `"cpu"` -> `None`, `"cuda"` -> `Some(0)`, `"cuda:N"` -> `Some(N)`
+pub fn parse_cuda_index(device: &str) -> anyhow::Result<Option<usize>> {
+    match device {
+        "cpu" => Ok(None),
+        "cuda" => Ok(Some(0)),
+        _ => match device.strip_prefix("cuda:") {
+            Some(index) => index.parse::<usize>().map(Some).map_err(|_| {
+                anyhow::anyhow!("invalid CUDA device index {:?} in {:?}", index, device)
+            }),
+            None => Err(anyhow::anyhow!("unknown device {:?}; use \"cpu\", \"cuda\" or \"cuda:N\"", device)),
+        },
+    }
+}
+
+/// Map a device string to a `tch::Device`, falling back to the CPU with a warning when the
+/// string is invalid or the requested GPU is not available
+pub fn resolve_device(device: &str) -> tch::Device {
+    match parse_cuda_index(device) {
+        Ok(None) => tch::Device::Cpu,
+        Ok(Some(index)) => {
+            let count = if tch::Cuda::is_available() { tch::Cuda::device_count() as usize } else { 0 };
+            if index < count {
+                tch::Device::Cuda(index)
+            } else {
+                log::warn!("{} requested but only {} CUDA device(s) available, using CPU", device, count);
+                tch::Device::Cpu
+            }
+        }
+        Err(e) => {
+            log::warn!("{}, using CPU", e);
+            tch::Device::Cpu
+        }
+    }
+}
+
+/// Whether half-precision inference can be used on `device`. fp16 is only worthwhile (and
+/// only supported by most TorchScript ops) on CUDA; on the CPU a request for it warns and
+/// falls back to fp32.
+pub fn resolve_half(requested: bool, device: tch::Device) -> bool {
+    if requested && !device.is_cuda() {
+        log::warn!("half precision requested on {:?}, running in fp32", device);
+        return false;
+    }
+    requested
+}
+
+/// Parse a comma-separated list of class ids such as `"0,2,5"`
+pub fn parse_class_list(list: &str) -> anyhow::Result<Vec<i32>> {
+    if list.trim().is_empty() {
+        anyhow::bail!("empty class list; give comma-separated ids such as 0,2,5");
+    }
+    list.split(',')
+        .map(|id| {
+            let id = id.trim();
+            match id.parse::<i32>() {
+                Ok(class_id) if class_id >= 0 => Ok(class_id),
+                Ok(_) => Err(anyhow::anyhow!("class id {} in {:?} is negative", id, list)),
+                Err(_) => Err(anyhow::anyhow!("invalid class id {:?} in {:?}", id, list)),
+            }
+        })
+        .collect()
+}
+
+/// Boxes smaller than this many square pixels are dropped by the detectors as noise
+pub const MIN_BOX_AREA: f32 = 10.0;
+
+/// Descending score order with NaN ranked below every real score
+fn score_desc(a: f32, b: f32) -> std::cmp::Ordering {
+    let key = |s: f32| if s.is_nan() { f32::NEG_INFINITY } else { s };
+    key(b).total_cmp(&key(a))
+}
 
 pub fn nms(boxes: &[[f32; 4]], scores: &[f32], iou_thresh: f32) -> Vec<usize> {
     let mut idxs: Vec<usize> = (0..boxes.len()).collect();
-    idxs.sort_unstable_by(|&i, &j| scores[j].partial_cmp(&scores[i]).unwrap());
+    idxs.sort_unstable_by(|&i, &j| score_desc(scores[i], scores[j]));
     let mut keep = Vec::new();
     while let Some(&i) = idxs.first() {
         keep.push(i);
@@ -15,6 +139,23 @@ pub fn nms(boxes: &[[f32; 4]], scores: &[f32], iou_thresh: f32) -> Vec<usize> {
     }
     keep
 }
+
+/// NMS where boxes only suppress boxes of the same class. Returns kept indices in
+/// descending score order, like `nms`.
+pub fn nms_per_class(boxes: &[[f32; 4]], scores: &[f32], class_ids: &[i32], iou_thresh: f32) -> Vec<usize> {
+    let mut by_class: HashMap<i32, Vec<usize>> = HashMap::new();
+    for (i, &class_id) in class_ids.iter().enumerate() {
+        by_class.entry(class_id).or_default().push(i);
+    }
+    let mut keep = Vec::new();
+    for idxs in by_class.values() {
+        let class_boxes: Vec<[f32; 4]> = idxs.iter().map(|&i| boxes[i]).collect();
+        let class_scores: Vec<f32> = idxs.iter().map(|&i| scores[i]).collect();
+        keep.extend(nms(&class_boxes, &class_scores, iou_thresh).into_iter().map(|k| idxs[k]));
+    }
+    keep.sort_by(|&i, &j| score_desc(scores[i], scores[j]).then(i.cmp(&j)));
+    keep
+}
 pub fn draw_box(img: &mut Mat, bbox: [i32; 4], color: Scalar, thickness: i32) -> opencv::Result<()> {
     let rect = opencv::core::Rect::new(bbox[0], bbox[1], bbox[2] - bbox[0], bbox[3] - bbox[1]);
     imgproc::rectangle(img, rect, color, thickness, imgproc::LINE_8, 0)
@@ -40,9 +181,234 @@ pub fn put_text(
         false,
     )
 }
-// timing utility
+/// Clamp a tlwh box to the frame bounds, warning when most of it was off-frame.
+/// Boxes landing far outside the image usually point at a coordinate-scaling bug.
+/// Returns the clamped box and whether the warning fired.
+pub fn soft_clamp_box(tlwh: &SVector<f32, 4>, frame_w: f32, frame_h: f32) -> (SVector<f32, 4>, bool) {
+    let x1 = tlwh[0].max(0.0).min(frame_w);
+    let y1 = tlwh[1].max(0.0).min(frame_h);
+    let x2 = (tlwh[0] + tlwh[2]).max(0.0).min(frame_w);
+    let y2 = (tlwh[1] + tlwh[3]).max(0.0).min(frame_h);
+    let clamped = SVector::<f32, 4>::new(x1, y1, x2 - x1, y2 - y1);
+
+    let area = tlwh[2] * tlwh[3];
+    let off_frame = if area > 0.0 {
+        1.0 - (clamped[2] * clamped[3]) / area
+    } else {
+        0.0
+    };
+    let warned = off_frame > OFF_FRAME_WARN_FRACTION;
+    if warned {
+        log::warn!(
+            "box [{:.1}, {:.1}, {:.1}, {:.1}] is {:.0}% outside the {}x{} frame, check coordinate scaling",
+            tlwh[0], tlwh[1], tlwh[2], tlwh[3], off_frame * 100.0, frame_w, frame_h
+        );
+    }
+    (clamped, warned)
+}
+
+// timing utility: wall-clock milliseconds since the Unix epoch
 pub fn now_ms() -> u128 {
-    std::time::Instant::now().elapsed().as_millis()
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis())
+}
+
+/// Milliseconds elapsed since `start`
+pub fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Per-stage time of one frame in milliseconds; stages that did not run stay 0.
+/// The detector fills the first three, the tracker the last two.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+    pub preprocess_ms: f64,
+    pub inference_ms: f64,
+    pub postprocess_ms: f64,
+    pub gmc_ms: f64,
+    pub association_ms: f64,
+}
+
+impl Timings {
+    pub fn total_ms(&self) -> f64 {
+        self.preprocess_ms + self.inference_ms + self.postprocess_ms + self.gmc_ms + self.association_ms
+    }
+
+    /// Stage-wise sum, e.g. to combine detector and tracker timings of one frame
+    pub fn add(&self, other: &Timings) -> Timings {
+        Timings {
+            preprocess_ms: self.preprocess_ms + other.preprocess_ms,
+            inference_ms: self.inference_ms + other.inference_ms,
+            postprocess_ms: self.postprocess_ms + other.postprocess_ms,
+            gmc_ms: self.gmc_ms + other.gmc_ms,
+            association_ms: self.association_ms + other.association_ms,
+        }
+    }
+
+    fn scale(&self, factor: f64) -> Timings {
+        Timings {
+            preprocess_ms: self.preprocess_ms * factor,
+            inference_ms: self.inference_ms * factor,
+            postprocess_ms: self.postprocess_ms * factor,
+            gmc_ms: self.gmc_ms * factor,
+            association_ms: self.association_ms * factor,
+        }
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "preprocess {:.1} ms, inference {:.1} ms, postprocess {:.1} ms, gmc {:.1} ms, association {:.1} ms (total {:.1} ms)",
+            self.preprocess_ms, self.inference_ms, self.postprocess_ms, self.gmc_ms, self.association_ms, self.total_ms()
+        )
+    }
+}
+
+/// Mean `Timings` over the most recent `window` frames
+#[derive(Debug)]
+pub struct RollingTimings {
+    window: usize,
+    frames: VecDeque<Timings>,
+}
+
+impl RollingTimings {
+    pub fn new(window: usize) -> Self {
+        RollingTimings { window: window.max(1), frames: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, timings: Timings) {
+        if self.frames.len() == self.window {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(timings);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn mean(&self) -> Timings {
+        if self.frames.is_empty() {
+            return Timings::default();
+        }
+        let sum = self.frames.iter().fold(Timings::default(), |acc, t| acc.add(t));
+        sum.scale(1.0 / self.frames.len() as f64)
+    }
+}
+
+/// Aggregate of every duration recorded into one named bucket
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TimingStats {
+    pub count: u32,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl TimingStats {
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.count
+        }
+    }
+}
+
+/// Named timing buckets aggregated across frames (e.g. "detect", "track", "draw")
+#[derive(Debug, Default)]
+pub struct Profiler {
+    buckets: HashMap<String, TimingStats>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one measurement to `name`
+    pub fn record(&mut self, name: &str, elapsed: Duration) {
+        let stats = self.buckets.entry(name.to_string()).or_default();
+        stats.count += 1;
+        stats.total += elapsed;
+        stats.max = stats.max.max(elapsed);
+    }
+
+    /// Time from now until the returned guard is dropped
+    pub fn scope(&mut self, name: &str) -> ScopedTimer<'_> {
+        ScopedTimer {
+            profiler: self,
+            name: name.to_string(),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn stats(&self, name: &str) -> Option<&TimingStats> {
+        self.buckets.get(name)
+    }
+
+    /// One line per bucket, sorted by name
+    pub fn report(&self) -> String {
+        let mut names: Vec<&String> = self.buckets.keys().collect();
+        names.sort();
+        names.iter()
+            .map(|name| {
+                let stats = &self.buckets[*name];
+                format!("{}: mean {:.2?}, max {:.2?}, count {}", name, stats.mean(), stats.max, stats.count)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Records the time between its creation and drop into a `Profiler` bucket
+pub struct ScopedTimer<'a> {
+    profiler: &'a mut Profiler,
+    name: String,
+    start: Instant,
+}
+
+impl Drop for ScopedTimer<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        self.profiler.record(&self.name, elapsed);
+    }
+}
+
+/// Size dissimilarity of two tlwh boxes: 1 - smaller area / larger area (0 for equal sizes)
+pub fn size_dissimilarity(a: &SVector<f32, 4>, b: &SVector<f32, 4>) -> f32 {
+    let area_a = (a[2] * a[3]).max(0.0);
+    let area_b = (b[2] * b[3]).max(0.0);
+    let larger = area_a.max(area_b);
+    if larger <= 0.0 {
+        return 0.0;
+    }
+    1.0 - area_a.min(area_b) / larger
+}
+
+/// Cosine distance (1 - cosine similarity) between two feature vectors
+pub fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 1.0;
+    }
+    1.0 - dot / (norm_a * norm_b)
+}
+
+/// Fraction of tlwh box `a` covered by tlwh box `b` (0 when `a` is empty)
+pub fn box_coverage(a: &SVector<f32, 4>, b: &SVector<f32, 4>) -> f32 {
+    let iw = ((a[0] + a[2]).min(b[0] + b[2]) - a[0].max(b[0])).max(0.0);
+    let ih = ((a[1] + a[3]).min(b[1] + b[3]) - a[1].max(b[1])).max(0.0);
+    let area = a[2] * a[3];
+    if area <= 0.0 { 0.0 } else { iw * ih / area }
 }
 
 /// Compute IoU between two bounding boxes as arrays: [x1, y1, w, h]
@@ -92,5 +458,126 @@ fn compute_iou_tlbr(a_x1: f32, a_y1: f32, a_x2: f32, a_y2: f32,
         return 0.0;
     }
     
-    inter_area / (a_area + b_area - inter_area)
-}
\ No newline at end of file
+    let iou = inter_area / (a_area + b_area - inter_area);
+    // Degenerate or non-finite boxes never count as overlapping
+    if iou.is_finite() { iou } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_clamp_box_warns_off_frame() {
+        let tlwh = SVector::<f32, 4>::new(600.0, -300.0, 200.0, 400.0);
+        let (clamped, warned) = soft_clamp_box(&tlwh, 640.0, 480.0);
+        assert!(warned);
+        assert_eq!(clamped, SVector::<f32, 4>::new(600.0, 0.0, 40.0, 100.0));
+    }
+
+    #[test]
+    fn test_parse_cuda_index() {
+        assert_eq!(parse_cuda_index("cpu").unwrap(), None);
+        assert_eq!(parse_cuda_index("cuda").unwrap(), Some(0));
+        assert_eq!(parse_cuda_index("cuda:2").unwrap(), Some(2));
+        assert!(parse_cuda_index("cuda:bad").is_err());
+        assert!(parse_cuda_index("tpu").is_err());
+    }
+
+    #[test]
+    fn test_parse_class_list() {
+        assert_eq!(parse_class_list("0,2,5").unwrap(), vec![0, 2, 5]);
+        assert_eq!(parse_class_list(" 0, 16 ").unwrap(), vec![0, 16]);
+        assert_eq!(parse_class_list("3").unwrap(), vec![3]);
+        assert!(parse_class_list("").is_err());
+        assert!(parse_class_list("  ").is_err());
+        assert!(parse_class_list("0,,2").is_err());
+        assert!(parse_class_list("0,car").is_err());
+        assert!(parse_class_list("0,-1").unwrap_err().to_string().contains("negative"));
+    }
+
+    #[test]
+    fn test_rolling_timings_window() {
+        let mut rolling = RollingTimings::new(2);
+        for ms in [10.0, 20.0, 40.0] {
+            rolling.push(Timings { inference_ms: ms, gmc_ms: 1.0, ..Timings::default() });
+        }
+        assert_eq!(rolling.len(), 2);
+        assert_eq!(rolling.mean().inference_ms, 30.0);
+        assert_eq!(rolling.mean().total_ms(), 31.0);
+    }
+
+    #[test]
+    fn test_scoped_timer_records_into_profiler() {
+        let mut profiler = Profiler::new();
+        {
+            let _timer = profiler.scope("detect");
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        for _ in 0..2 {
+            let _timer = profiler.scope("track");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let detect = profiler.stats("detect").unwrap();
+        assert_eq!(detect.count, 1);
+        assert!(detect.total >= Duration::from_millis(2));
+
+        let track = profiler.stats("track").unwrap();
+        assert_eq!(track.count, 2);
+        assert!(track.max > Duration::ZERO && track.max <= track.total);
+        assert!(track.mean() >= Duration::from_millis(1));
+        assert!(profiler.stats("draw").is_none());
+        assert_eq!(profiler.report().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_nms_per_class_keeps_other_classes() {
+        let boxes = [[0.0, 0.0, 50.0, 50.0], [2.0, 2.0, 50.0, 50.0], [1.0, 1.0, 50.0, 50.0], [200.0, 0.0, 20.0, 20.0]];
+        let scores = [0.6, 0.9, 0.8, 0.5];
+        assert_eq!(nms(&boxes, &scores, 0.5), vec![1, 3]);
+        assert_eq!(nms_per_class(&boxes, &scores, &[0, 0, 1, 0], 0.5), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_iou_and_nms_handle_degenerate_input() {
+        let zero_area = SVector::<f32, 4>::new(10.0, 10.0, 0.0, 20.0);
+        let normal = SVector::<f32, 4>::new(10.0, 10.0, 20.0, 20.0);
+        assert_eq!(compute_iou(&zero_area, &zero_area), 0.0);
+        assert_eq!(compute_iou(&zero_area, &normal), 0.0);
+        let infinite = SVector::<f32, 4>::new(0.0, 0.0, f32::INFINITY, 10.0);
+        assert_eq!(compute_iou(&infinite, &normal), 0.0);
+        assert_eq!(compute_iou_array(&[0.0, 0.0, f32::NAN, 10.0], &[0.0, 0.0, 10.0, 10.0]), 0.0);
+
+        let boxes = [[0.0, 0.0, 10.0, 10.0], [100.0, 100.0, 10.0, 10.0], [0.0, 0.0, 10.0, 10.0]];
+        let scores = [f32::NAN, 0.5, 0.9];
+        // The NaN box ranks last and is suppressed by its identical, higher-scoring twin
+        assert_eq!(nms(&boxes, &scores, 0.5), vec![2, 1]);
+        assert_eq!(nms_per_class(&boxes, &scores, &[0, 0, 1], 0.5), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn test_coco_class_name() {
+        assert_eq!(COCO_CLASSES.len(), 80);
+        assert_eq!(coco_class_name(0), Some("person"));
+        assert_eq!(coco_class_name(2), Some("car"));
+        assert_eq!(coco_class_name(9), Some("traffic light"));
+        assert_eq!(coco_category_id(0), Some(1));
+        assert_eq!(coco_category_id(11), Some(13));
+        assert_eq!(coco_category_id(79), Some(90));
+        assert_eq!(coco_category_id(80), None);
+        assert_eq!(coco_class_name(16), Some("dog"));
+        assert_eq!(coco_class_name(79), Some("toothbrush"));
+        assert_eq!(coco_class_name(80), None);
+        assert_eq!(coco_class_name(999), None);
+        assert_eq!(coco_class_name(-1), None);
+    }
+
+    #[test]
+    fn test_soft_clamp_box_inside_frame() {
+        let tlwh = SVector::<f32, 4>::new(-10.0, 20.0, 100.0, 100.0);
+        let (clamped, warned) = soft_clamp_box(&tlwh, 640.0, 480.0);
+        assert!(!warned);
+        assert_eq!(clamped, SVector::<f32, 4>::new(0.0, 20.0, 90.0, 100.0));
+    }
+}
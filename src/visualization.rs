@@ -1,19 +1,125 @@
 use opencv::{
-    core::{Point, Scalar, Rect},
+    core::{Point, Scalar, Rect, Size, CV_8UC3},
     imgproc,
     prelude::*,
 };
 use crate::{Detection, STrack};
+use crate::utils::coco_class_name;
 
-#[allow(dead_code)]
-const COLORS: &[Scalar] = &[
-    Scalar::new(255.0, 0.0, 0.0, 0.0),    // Red
-    Scalar::new(0.0, 255.0, 0.0, 0.0),    // Green
-    Scalar::new(0.0, 0.0, 255.0, 0.0),    // Blue
-    Scalar::new(255.0, 255.0, 0.0, 0.0),  // Yellow
-    Scalar::new(255.0, 0.0, 255.0, 0.0),  // Magenta
-    Scalar::new(0.0, 255.0, 255.0, 0.0),  // Cyan
-];
+/// Display label for a class id; unknown ids become `class_<id>`
+pub fn class_label(class_id: i32) -> String {
+    match coco_class_name(class_id) {
+        Some(name) => name.to_string(),
+        None => format!("class_{}", class_id),
+    }
+}
+
+/// Stable BGR color for a class id (any id, including unknown ones)
+pub fn color_for_class(class_id: i32) -> Scalar {
+    color_for_id(class_id as u32)
+}
+
+/// Golden-ratio conjugate; stepping hue by it spreads consecutive IDs around the color wheel
+const GOLDEN_RATIO_CONJUGATE: f64 = 0.618_033_988_749_895;
+
+/// Stable, visually distinct BGR color for a track ID
+pub fn color_for_id(id: u32) -> Scalar {
+    let hue = (id as f64 * GOLDEN_RATIO_CONJUGATE).fract();
+    let (r, g, b) = hsv_to_rgb(hue, 0.85, 0.95);
+    Scalar::new(b * 255.0, g * 255.0, r * 255.0, 0.0)
+}
+
+/// How boxes are colored
+#[derive(Debug, Clone, PartialEq)]
+pub enum Palette {
+    /// Golden-ratio hues, distinct for every id (see `color_for_id`)
+    Golden,
+    /// Cycle through a fixed list of BGR colors
+    Cycle(Vec<Scalar>),
+}
+
+impl Palette {
+    pub fn color(&self, id: u32) -> Scalar {
+        match self {
+            Palette::Cycle(colors) if !colors.is_empty() => colors[id as usize % colors.len()],
+            _ => color_for_id(id),
+        }
+    }
+}
+
+/// Frame size the default style is tuned for (shorter side, in pixels)
+const REFERENCE_RESOLUTION: f64 = 720.0;
+
+/// Sizes and toggles shared by the draw functions
+#[derive(Debug, Clone, PartialEq)]
+pub struct VisStyle {
+    /// Font scale of box labels; headings and frame info are drawn proportionally larger
+    pub font_scale: f64,
+    pub box_thickness: i32,
+    pub text_thickness: i32,
+    pub show_trail: bool,
+    /// Most recent trail points drawn per track
+    pub trail_length: usize,
+    pub show_score: bool,
+    pub palette: Palette,
+}
+
+impl Default for VisStyle {
+    fn default() -> Self {
+        VisStyle {
+            font_scale: 0.5,
+            box_thickness: 2,
+            text_thickness: 1,
+            show_trail: true,
+            trail_length: 30,
+            show_score: true,
+            palette: Palette::Golden,
+        }
+    }
+}
+
+impl VisStyle {
+    /// Default style scaled with the shorter side of a `width` x `height` frame, so labels
+    /// stay readable on 4K and don't swamp small frames
+    pub fn for_resolution(width: i32, height: i32) -> Self {
+        let factor = (width.min(height).max(1) as f64 / REFERENCE_RESOLUTION).clamp(0.5, 4.0);
+        let base = VisStyle::default();
+        VisStyle {
+            font_scale: base.font_scale * factor,
+            box_thickness: ((base.box_thickness as f64 * factor).round() as i32).max(1),
+            text_thickness: ((base.text_thickness as f64 * factor).round() as i32).max(1),
+            ..base
+        }
+    }
+
+    /// Font scale for overlay headings such as the track count
+    pub fn heading_scale(&self) -> f64 {
+        self.font_scale * 1.4
+    }
+
+    /// Font scale for the frame number / FPS line
+    pub fn info_scale(&self) -> f64 {
+        self.font_scale * 1.2
+    }
+}
+
+/// Convert HSV (all components in [0, 1]) to RGB in [0, 1]
+fn hsv_to_rgb(h: f64, s: f64, v: f64) -> (f64, f64, f64) {
+    let sector = h * 6.0;
+    let i = sector.floor() as i32 % 6;
+    let f = sector - sector.floor();
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+    match i {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    }
+}
 
 /// Draw text on an image with specified font size and color
 pub fn draw_text(
@@ -22,7 +128,8 @@ pub fn draw_text(
     x: i32, 
     y: i32, 
     font_scale: f64, 
-    color: (i32, i32, i32)
+    color: (i32, i32, i32),
+    style: &VisStyle,
 ) -> opencv::Result<()> {
     let color = Scalar::new(color.2 as f64, color.1 as f64, color.0 as f64, 0.0); // BGR format
     let text_pos = Point::new(x, y);
@@ -35,7 +142,7 @@ pub fn draw_text(
         imgproc::FONT_HERSHEY_SIMPLEX,
         font_scale,
         Scalar::new(0.0, 0.0, 0.0, 0.0), // Black
-        3, // Thicker line for background
+        style.text_thickness + 2, // Thicker line for background
         imgproc::LINE_8,
         false,
     )?;
@@ -48,7 +155,7 @@ pub fn draw_text(
         imgproc::FONT_HERSHEY_SIMPLEX,
         font_scale,
         color,
-        1,
+        style.text_thickness,
         imgproc::LINE_8,
         false,
     )?;
@@ -56,50 +163,133 @@ pub fn draw_text(
     Ok(())
 }
 
-pub fn draw_track(frame: &mut Mat, track: &STrack, color: Scalar) -> anyhow::Result<()> {
+/// Label drawn above a track: `ID 5 person 0.82`, or just `ID: 5` when not verbose
+pub fn track_label(track: &STrack, verbose: bool) -> String {
+    if verbose {
+        format!("ID {} {} {:.2}", track.track_id(), class_label(track.class_id), track.score)
+    } else {
+        format!("ID: {}", track.track_id())
+    }
+}
+
+/// Height in pixels of the confidence bar drawn above boxes
+const CONFIDENCE_BAR_HEIGHT: i32 = 4;
+
+/// BGR color for a score in [0, 1]: red at 0 through yellow to green at 1
+pub fn confidence_color(score: f32) -> Scalar {
+    let s = score.clamp(0.0, 1.0) as f64;
+    Scalar::new(0.0, 255.0 * (2.0 * s).min(1.0), 255.0 * (2.0 - 2.0 * s).min(1.0), 0.0)
+}
+
+/// Draw a thin bar just above the box whose filled length and color encode `score`
+pub fn draw_confidence_bar(frame: &mut Mat, tlwh: &nalgebra::SVector<f32, 4>, score: f32) -> anyhow::Result<()> {
+    let width = tlwh[2].max(1.0) as i32;
+    let y = (tlwh[1] as i32 - CONFIDENCE_BAR_HEIGHT - 2).max(0);
+    let filled = (width as f32 * score.clamp(0.0, 1.0)).round() as i32;
+
+    let track_rect = Rect::new(tlwh[0] as i32, y, width, CONFIDENCE_BAR_HEIGHT);
+    imgproc::rectangle(frame, track_rect, Scalar::new(64.0, 64.0, 64.0, 0.0), -1, imgproc::LINE_8, 0)?;
+    if filled > 0 {
+        let bar_rect = Rect::new(tlwh[0] as i32, y, filled, CONFIDENCE_BAR_HEIGHT);
+        imgproc::rectangle(frame, bar_rect, confidence_color(score), -1, imgproc::LINE_8, 0)?;
+    }
+    Ok(())
+}
+
+/// Opacity of the oldest drawn trail segment; the newest is fully opaque
+const TRAIL_MIN_ALPHA: f64 = 0.15;
+
+/// Alpha and line thickness of trail segment `index` out of `segments`, oldest first:
+/// both grow linearly from faint and thin to opaque and `max_thickness` wide
+pub fn trail_segment_style(index: usize, segments: usize, max_thickness: i32) -> (f64, i32) {
+    let t = if segments > 1 { index.min(segments - 1) as f64 / (segments - 1) as f64 } else { 1.0 };
+    let alpha = TRAIL_MIN_ALPHA + (1.0 - TRAIL_MIN_ALPHA) * t;
+    let thickness = 1 + ((max_thickness.max(1) - 1) as f64 * t).round() as i32;
+    (alpha, thickness)
+}
+
+/// Draw the last `style.trail_length` trail points as segments that fade and thin with age,
+/// blending each one into the frame so crossing trails stay readable
+fn draw_faded_trail(frame: &mut Mat, trail: &[nalgebra::SVector<f32, 4>], color: Scalar, style: &VisStyle) -> anyhow::Result<()> {
+    let points: Vec<Point> = trail[trail.len().saturating_sub(style.trail_length)..]
+        .iter()
+        .map(|p| Point::new(p[0] as i32, p[1] as i32))
+        .collect();
+    let segments = points.len().saturating_sub(1);
+    let bounds = Rect::new(0, 0, frame.cols(), frame.rows());
+
+    for (i, pair) in points.windows(2).enumerate() {
+        let (alpha, thickness) = trail_segment_style(i, segments, style.box_thickness);
+        // Blend only the patch around the segment
+        let pad = thickness + 1;
+        let x0 = (pair[0].x.min(pair[1].x) - pad).max(bounds.x);
+        let y0 = (pair[0].y.min(pair[1].y) - pad).max(bounds.y);
+        let x1 = (pair[0].x.max(pair[1].x) + pad).min(bounds.width);
+        let y1 = (pair[0].y.max(pair[1].y) + pad).min(bounds.height);
+        if x1 <= x0 || y1 <= y0 {
+            continue;
+        }
+        let patch = Rect::new(x0, y0, x1 - x0, y1 - y0);
+        let local = |p: Point| Point::new(p.x - x0, p.y - y0);
+
+        let background = Mat::roi(frame, patch)?.try_clone()?;
+        let mut overlay = background.try_clone()?;
+        imgproc::line(&mut overlay, local(pair[0]), local(pair[1]), color, thickness, imgproc::LINE_AA, 0)?;
+        let mut blended = Mat::default();
+        opencv::core::add_weighted(&background, 1.0 - alpha, &overlay, alpha, 0.0, &mut blended, -1)?;
+        let mut target = Mat::roi_mut(frame, patch)?;
+        blended.copy_to(&mut target)?;
+    }
+    Ok(())
+}
+
+/// Draw an activated track's box, label and motion trail; `confidence_bar` adds a score bar above the box
+pub fn draw_track(frame: &mut Mat, track: &STrack, verbose: bool, confidence_bar: bool, style: &VisStyle) -> anyhow::Result<()> {
     if !track.is_activated() {
         return Ok(());
     }
 
     let tlwh = track.tlwh();
     let track_id = track.track_id();
+    let color = style.palette.color(track_id);
     
     let tl = Point::new(tlwh[0] as i32, tlwh[1] as i32);
     let br = Point::new((tlwh[0] + tlwh[2]) as i32, (tlwh[1] + tlwh[3]) as i32);
     
     let rect = Rect::new(tl.x, tl.y, br.x - tl.x, br.y - tl.y);
-    imgproc::rectangle(frame, rect, color, 2, imgproc::LINE_8, 0)?;
+    imgproc::rectangle(frame, rect, color, style.box_thickness, imgproc::LINE_8, 0)?;
     
-    let text = format!("ID: {}", track_id);
-    let mut baseline = 0;
-    let _text_size = imgproc::get_text_size(&text, imgproc::FONT_HERSHEY_SIMPLEX, 0.5, 1, &mut baseline)?;
+    let text = if verbose && !style.show_score {
+        format!("ID {} {}", track_id, class_label(track.class_id))
+    } else {
+        track_label(track, verbose)
+    };
     let text_org = Point::new(tl.x, tl.y - 5);
     imgproc::put_text(
         frame,
         &text,
         text_org,
         imgproc::FONT_HERSHEY_SIMPLEX,
-        0.5,
+        style.font_scale,
         color,
-        1,
+        style.text_thickness,
         imgproc::LINE_8,
         false,
     )?;
 
-    if let Some(trail) = track.motion_trail() {
-        for i in 1..trail.len() {
-            let prev = &trail[i-1];
-            let curr = &trail[i];
-            let prev_pt = Point::new(prev[0] as i32, prev[1] as i32);
-            let curr_pt = Point::new(curr[0] as i32, curr[1] as i32);
-            imgproc::line(frame, prev_pt, curr_pt, color, 1, imgproc::LINE_8, 0)?;
-        }
+    if let Some(trail) = track.motion_trail().filter(|_| style.show_trail) {
+        draw_faded_trail(frame, trail, color, style)?;
+    }
+
+    if confidence_bar {
+        draw_confidence_bar(frame, tlwh, track.score)?;
     }
 
     Ok(())
 }
 
-pub fn draw_detection(frame: &mut Mat, det: &Detection, color: Scalar) -> anyhow::Result<()> {
+/// Draw a detection box with class and score; `confidence_bar` adds a score bar above the box
+pub fn draw_detection(frame: &mut Mat, det: &Detection, color: Scalar, confidence_bar: bool, style: &VisStyle) -> anyhow::Result<()> {
     let tlwh = det.tlwh();
     let score = det.confidence();
     
@@ -107,28 +297,21 @@ pub fn draw_detection(frame: &mut Mat, det: &Detection, color: Scalar) -> anyhow
     let br = Point::new((tlwh[0] + tlwh[2]) as i32, (tlwh[1] + tlwh[3]) as i32);
     
     let rect = Rect::new(tl.x, tl.y, br.x - tl.x, br.y - tl.y);
-    imgproc::rectangle(frame, rect, color, 2, imgproc::LINE_8, 0)?;
+    imgproc::rectangle(frame, rect, color, style.box_thickness, imgproc::LINE_8, 0)?;
     
     // Get class name based on class_id
-    let display_name = match det.class_id {
-        0 => "person".to_string(),
-        1 => "bicycle".to_string(),
-        2 => "car".to_string(),
-        3 => "motorcycle".to_string(),
-        5 => "bus".to_string(),
-        7 => "truck".to_string(),
-        9 => "traffic light".to_string(),
-        15 => "cat".to_string(),
-        16 => "dog".to_string(),
-        _ => format!("object_{}", det.class_id),
-    };
+    let display_name = class_label(det.class_id);
     
     // Format text with class name and confidence
-    let text = format!("{} {:.2}", display_name, score);
+    let text = if style.show_score {
+        format!("{} {:.2}", display_name, score)
+    } else {
+        display_name
+    };
     
     // Add text with dark background for better visibility
     let mut baseline = 0;
-    let text_size = imgproc::get_text_size(&text, imgproc::FONT_HERSHEY_SIMPLEX, 0.5, 1, &mut baseline)?;
+    let text_size = imgproc::get_text_size(&text, imgproc::FONT_HERSHEY_SIMPLEX, style.font_scale, style.text_thickness, &mut baseline)?;
     
     // Draw background rectangle for text
     let bg_rect = Rect::new(
@@ -155,17 +338,21 @@ pub fn draw_detection(frame: &mut Mat, det: &Detection, color: Scalar) -> anyhow
         &text,
         text_org,
         imgproc::FONT_HERSHEY_SIMPLEX,
-        0.5,
+        style.font_scale,
         color,
-        1,
+        style.text_thickness,
         imgproc::LINE_8,
         false,
     )?;
 
+    if confidence_bar {
+        draw_confidence_bar(frame, tlwh, score)?;
+    }
+
     Ok(())
 }
 
-pub fn draw_frame_info(frame: &mut Mat, frame_id: i32, fps: f64) -> opencv::Result<()> {
+pub fn draw_frame_info(frame: &mut Mat, frame_id: i32, fps: f64, style: &VisStyle) -> opencv::Result<()> {
     let text = format!("Frame: {} FPS: {:.1}", frame_id, fps);
     let text_pos = Point::new(10, 30);
     opencv::imgproc::put_text(
@@ -173,9 +360,9 @@ pub fn draw_frame_info(frame: &mut Mat, frame_id: i32, fps: f64) -> opencv::Resu
         &text,
         text_pos,
         opencv::imgproc::FONT_HERSHEY_SIMPLEX,
-        0.6,
+        style.info_scale(),
         Scalar::new(255.0, 255.0, 255.0, 0.0),
-        2,
+        style.text_thickness * 2,
         opencv::imgproc::LINE_8,
         false,
     )?;
@@ -183,7 +370,7 @@ pub fn draw_frame_info(frame: &mut Mat, frame_id: i32, fps: f64) -> opencv::Resu
 }
 
 /// Draw detections with a limit on how many to show
-pub fn draw_detections(frame: &mut Mat, detections: &[Detection]) -> anyhow::Result<()> {
+pub fn draw_detections(frame: &mut Mat, detections: &[Detection], confidence_bar: bool, style: &VisStyle) -> anyhow::Result<()> {
     // Limit the number of visualized detections to avoid cluttering
     const MAX_VISUALIZED_DETECTIONS: usize = 20;
     
@@ -198,16 +385,15 @@ pub fn draw_detections(frame: &mut Mat, detections: &[Detection]) -> anyhow::Res
         &sorted_dets
     };
     
-    for (i, det) in vis_dets.iter().enumerate() {
-        let color = COLORS[i % COLORS.len()];
-        draw_detection(frame, det, color)?;
+    for det in vis_dets {
+        draw_detection(frame, det, style.palette.color(det.class_id as u32), confidence_bar, style)?;
     }
     
     Ok(())
 }
 
-/// Draw tracks with a limit on how many to show
-pub fn draw_tracks(frame: &mut Mat, tracks: &[STrack]) -> anyhow::Result<()> {
+/// Draw tracks with a limit on how many to show; `verbose` adds class name and score to labels
+pub fn draw_tracks(frame: &mut Mat, tracks: &[STrack], verbose: bool, confidence_bar: bool, style: &VisStyle) -> anyhow::Result<()> {
     // Limit the number of visualized tracks to avoid cluttering
     const MAX_VISUALIZED_TRACKS: usize = 50;
     
@@ -223,9 +409,264 @@ pub fn draw_tracks(frame: &mut Mat, tracks: &[STrack]) -> anyhow::Result<()> {
     };
     
     for track in vis_tracks {
-        let color = COLORS[(track.track_id() as usize) % COLORS.len()];
-        draw_track(frame, track, color)?;
+        draw_track(frame, track, verbose, confidence_bar, style)?;
     }
     
     Ok(())
-} 
\ No newline at end of file
+} 
+
+/// Tile frames row by row into a grid `cols` wide, e.g. for a quick review of the first
+/// frames of a run. Every frame is resized to the size of the first one (grayscale frames
+/// are converted to BGR); unused cells in the last row stay black.
+pub fn make_montage(frames: &[Mat], cols: usize) -> anyhow::Result<Mat> {
+    anyhow::ensure!(!frames.is_empty(), "montage needs at least one frame");
+    anyhow::ensure!(cols > 0, "montage needs at least one column");
+    let cell = frames[0].size()?;
+    anyhow::ensure!(cell.width > 0 && cell.height > 0, "montage frames must not be empty");
+
+    let rows = frames.len().div_ceil(cols);
+    let mut montage = Mat::new_size_with_default(
+        Size::new(cell.width * cols as i32, cell.height * rows as i32),
+        CV_8UC3,
+        Scalar::all(0.0),
+    )?;
+
+    for (i, frame) in frames.iter().enumerate() {
+        let mut bgr = Mat::default();
+        if frame.channels() == 1 {
+            imgproc::cvt_color(frame, &mut bgr, imgproc::COLOR_GRAY2BGR, 0)?;
+        } else {
+            bgr = frame.clone();
+        }
+        let mut tile = Mat::default();
+        if bgr.size()? == cell {
+            tile = bgr;
+        } else {
+            imgproc::resize(&bgr, &mut tile, cell, 0.0, 0.0, imgproc::INTER_AREA)?;
+        }
+
+        let rect = Rect::new(
+            (i % cols) as i32 * cell.width,
+            (i / cols) as i32 * cell.height,
+            cell.width,
+            cell.height,
+        );
+        let mut roi = Mat::roi_mut(&mut montage, rect)?;
+        tile.copy_to(&mut roi)?;
+    }
+    Ok(montage)
+}
+
+/// Standard deviation, in pixels, of the Gaussian splatted per track by `render_heatmap`
+const HEATMAP_SIGMA: f32 = 12.0;
+
+/// Add a unit-peak Gaussian at each track's box center to `accumulator`, a `CV_32FC1` Mat
+/// the size of the frame. An empty accumulator is rejected; start from a zero-filled Mat.
+pub fn render_heatmap(accumulator: &mut Mat, tracks: &[STrack]) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        accumulator.typ() == opencv::core::CV_32FC1 && !accumulator.empty(),
+        "heatmap accumulator must be a non-empty CV_32FC1 Mat"
+    );
+    let (rows, cols) = (accumulator.rows(), accumulator.cols());
+    let radius = (3.0 * HEATMAP_SIGMA).ceil() as i32;
+    let denom = 2.0 * HEATMAP_SIGMA * HEATMAP_SIGMA;
+    for track in tracks {
+        let tlwh = track.tlwh();
+        let cx = (tlwh[0] + tlwh[2] / 2.0).round() as i32;
+        let cy = (tlwh[1] + tlwh[3] / 2.0).round() as i32;
+        for y in (cy - radius).max(0)..=(cy + radius).min(rows - 1) {
+            for x in (cx - radius).max(0)..=(cx + radius).min(cols - 1) {
+                let d2 = ((x - cx) * (x - cx) + (y - cy) * (y - cy)) as f32;
+                *accumulator.at_2d_mut::<f32>(y, x)? += (-d2 / denom).exp();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Color-map `accumulator` (normalized to its maximum, JET palette) and alpha-blend it onto
+/// `frame` wherever it is nonzero
+pub fn overlay_heatmap(frame: &mut Mat, accumulator: &Mat, alpha: f64) -> anyhow::Result<()> {
+    let mut heat = Mat::default();
+    opencv::core::normalize(accumulator, &mut heat, 0.0, 255.0, opencv::core::NORM_MINMAX, opencv::core::CV_8U, &opencv::core::no_array())?;
+    let mut colored = Mat::default();
+    imgproc::apply_color_map(&heat, &mut colored, imgproc::COLORMAP_JET)?;
+    let mut blended = Mat::default();
+    opencv::core::add_weighted(&*frame, 1.0 - alpha, &colored, alpha, 0.0, &mut blended, -1)?;
+    let mut mask = Mat::default();
+    opencv::core::compare(&heat, &Scalar::all(0.0), &mut mask, opencv::core::CMP_GT)?;
+    blended.copy_to_masked(frame, &mask)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_color_for_id_distinct_and_stable() {
+        let colors: HashSet<(u8, u8, u8)> = (0..50)
+            .map(|id| {
+                let c = color_for_id(id);
+                (c[0].round() as u8, c[1].round() as u8, c[2].round() as u8)
+            })
+            .collect();
+        assert_eq!(colors.len(), 50);
+
+        // Same ID, same color across calls
+        assert_eq!(color_for_id(7), color_for_id(7));
+        // Former palette collision (3 vs 9 with six colors) no longer collides
+        assert_ne!(color_for_id(3), color_for_id(9));
+    }
+
+    #[test]
+    fn test_track_label_formatting() {
+        let mut track = STrack::new(nalgebra::SVector::<f32, 4>::new(0.0, 0.0, 10.0, 20.0), 0.823, 0, None, 1);
+        track.track_id = 5;
+        assert_eq!(track_label(&track, true), "ID 5 person 0.82");
+        assert_eq!(track_label(&track, false), "ID: 5");
+
+        track.class_id = 123;
+        assert_eq!(track_label(&track, true), "ID 5 class_123 0.82");
+    }
+
+    #[test]
+    fn test_vis_style_scales_with_resolution() {
+        let small = VisStyle::for_resolution(640, 480);
+        let uhd = VisStyle::for_resolution(3840, 2160);
+        assert!(uhd.font_scale > small.font_scale);
+        assert!(uhd.box_thickness > small.box_thickness);
+        assert!(uhd.text_thickness >= small.text_thickness);
+        assert!(small.text_thickness >= 1 && small.box_thickness >= 1);
+        // 720p is the reference and matches the defaults
+        assert_eq!(VisStyle::for_resolution(1280, 720), VisStyle::default());
+    }
+
+    #[test]
+    fn test_trail_fades_with_age() {
+        let schedule: Vec<(f64, i32)> = (0..5).map(|i| trail_segment_style(i, 5, 3)).collect();
+        assert_eq!(schedule[0], (TRAIL_MIN_ALPHA, 1));
+        assert_eq!(schedule[4], (1.0, 3));
+        for pair in schedule.windows(2) {
+            assert!(pair[1].0 > pair[0].0);
+            assert!(pair[1].1 >= pair[0].1);
+        }
+        // A lone segment is the newest one
+        assert_eq!(trail_segment_style(0, 1, 2), (1.0, 2));
+    }
+
+    #[test]
+    fn test_palette_cycle() {
+        let red = Scalar::new(0.0, 0.0, 255.0, 0.0);
+        let blue = Scalar::new(255.0, 0.0, 0.0, 0.0);
+        let palette = Palette::Cycle(vec![red, blue]);
+        assert_eq!(palette.color(0), red);
+        assert_eq!(palette.color(3), blue);
+        assert_eq!(Palette::Golden.color(7), color_for_id(7));
+    }
+
+    fn black_frame() -> Mat {
+        Mat::new_size_with_default(
+            opencv::core::Size::new(400, 200),
+            opencv::core::CV_8UC3,
+            Scalar::all(0.0),
+        ).unwrap()
+    }
+
+    /// Pixel in the bar band above a box with top edge at y = 50, right of any label text
+    fn bar_pixel(frame: &Mat) -> opencv::core::Vec3b {
+        *frame.at_2d::<opencv::core::Vec3b>(50 - 4, 300).unwrap()
+    }
+
+    #[test]
+    fn test_confidence_bar_drawn_only_when_enabled() {
+        let tlwh = nalgebra::SVector::<f32, 4>::new(20.0, 50.0, 320.0, 100.0);
+        let det = Detection::new(tlwh, 0.9, 0, None);
+
+        let mut frame = black_frame();
+        draw_detection(&mut frame, &det, color_for_class(0), false, &VisStyle::default()).unwrap();
+        assert_eq!(bar_pixel(&frame), opencv::core::Vec3b::from([0, 0, 0]));
+
+        let mut frame = black_frame();
+        draw_detection(&mut frame, &det, color_for_class(0), true, &VisStyle::default()).unwrap();
+        let px = bar_pixel(&frame);
+        // High score: green dominates red
+        assert!(px[1] > 200 && px[2] < px[1], "bar pixel {:?}", px);
+
+        let mut track = STrack::new(tlwh, 0.2, 0, None, 1);
+        track.activate(&crate::tracker::KalmanFilter::new(), 1, 1);
+        track.confirm(1);
+        let mut frame = black_frame();
+        draw_track(&mut frame, &track, false, false, &VisStyle::default()).unwrap();
+        assert_eq!(bar_pixel(&frame), opencv::core::Vec3b::from([0, 0, 0]));
+
+        let mut frame = black_frame();
+        draw_track(&mut frame, &track, false, true, &VisStyle::default()).unwrap();
+        // Low score: the bar stops short of x = 300, leaving the gray background
+        assert_eq!(bar_pixel(&frame), opencv::core::Vec3b::from([64, 64, 64]));
+        let filled = *frame.at_2d::<opencv::core::Vec3b>(50 - 4, 40).unwrap();
+        assert!(filled[2] > 200 && filled[1] < filled[2], "bar pixel {:?}", filled);
+    }
+
+    #[test]
+    fn test_make_montage_grid() {
+        let frame = |w: i32, h: i32, shade: f64| {
+            Mat::new_size_with_default(Size::new(w, h), CV_8UC3, Scalar::all(shade)).unwrap()
+        };
+        // Second and fourth frames have other sizes and get resized to the first one's
+        let frames = vec![frame(40, 30, 50.0), frame(80, 60, 100.0), frame(40, 30, 150.0), frame(20, 10, 200.0)];
+
+        let montage = make_montage(&frames, 2).unwrap();
+        assert_eq!((montage.cols(), montage.rows()), (80, 60));
+        for (x, y, shade) in [(10, 10, 50), (50, 10, 100), (10, 40, 150), (50, 40, 200)] {
+            assert_eq!(montage.at_2d::<opencv::core::Vec3b>(y, x).unwrap()[0], shade);
+        }
+
+        // Three frames in two columns leave one black cell
+        let montage = make_montage(&frames[..3], 2).unwrap();
+        assert_eq!((montage.cols(), montage.rows()), (80, 60));
+        assert_eq!(montage.at_2d::<opencv::core::Vec3b>(40, 50).unwrap()[0], 0);
+
+        assert!(make_montage(&[], 2).is_err());
+    }
+
+    #[test]
+    fn test_unknown_class_label_and_color() {
+        assert_eq!(class_label(0), "person");
+        assert_eq!(class_label(9), "traffic light");
+        assert_eq!(class_label(80), "class_80");
+        assert_eq!(class_label(999), "class_999");
+        assert_eq!(class_label(-1), "class_-1");
+
+        for class_id in [80, 999, -1] {
+            let color = color_for_class(class_id);
+            assert_eq!(color, color_for_class(class_id));
+            for c in 0..3 {
+                assert!((0.0..=255.0).contains(&color[c]));
+            }
+            assert_eq!(color[3], 0.0);
+        }
+    }
+
+    #[test]
+    fn test_heatmap_peaks_at_track_center() {
+        let mut track = STrack::new(nalgebra::SVector::<f32, 4>::new(100.0, 50.0, 40.0, 80.0), 0.9, 0, None, 1);
+        track.activate(&crate::tracker::KalmanFilter::new(), 1, 1);
+        let mut accumulator = Mat::new_rows_cols_with_default(240, 320, opencv::core::CV_32FC1, Scalar::all(0.0)).unwrap();
+        render_heatmap(&mut accumulator, &[track.clone()]).unwrap();
+        render_heatmap(&mut accumulator, &[track]).unwrap();
+
+        let mut max_val = 0.0;
+        let mut max_loc = Point::default();
+        opencv::core::min_max_loc(&accumulator, None, Some(&mut max_val), None, Some(&mut max_loc), &opencv::core::no_array()).unwrap();
+        assert_eq!(max_loc, Point::new(120, 90));
+        assert!((max_val - 2.0).abs() < 1e-6);
+        assert_eq!(*accumulator.at_2d::<f32>(0, 0).unwrap(), 0.0);
+
+        let mut frame = Mat::new_size_with_default(Size::new(320, 240), CV_8UC3, Scalar::all(0.0)).unwrap();
+        overlay_heatmap(&mut frame, &accumulator, 0.5).unwrap();
+        assert_ne!(*frame.at_2d::<opencv::core::Vec3b>(90, 120).unwrap(), opencv::core::Vec3b::all(0));
+        assert_eq!(*frame.at_2d::<opencv::core::Vec3b>(0, 0).unwrap(), opencv::core::Vec3b::all(0));
+    }
+}